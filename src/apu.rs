@@ -7,7 +7,7 @@ use sdl2::AudioSubsystem;
 const SOURCE: RequestSource = RequestSource::APU;
 
 pub struct AudioProcessingUnit {
-    _audio_subsystem: AudioSubsystem,
+    _audio_subsystem: Option<AudioSubsystem>,
     length_counters: [u16; 4],
     length_enables: [bool; 4],
     sequence_counter: u8,
@@ -17,7 +17,7 @@ pub struct AudioProcessingUnit {
     volumes: [u8; 4],
     vol_timers: [u8; 4],
     frequencies: [u32; 4],
-    queues: [AudioQueue<f32>; 4],
+    queues: Option<[AudioQueue<f32>; 4]>,
     channel_enables: [bool; 4],
     so1_enables: [u8; 4],
     so2_enables: [u8; 4],
@@ -49,19 +49,23 @@ pub struct AudioProcessingUnit {
 
     ch_4_lsfr: u16,
     ch_4_width: bool,
+    mixed_sample: [f32; 2],
 }
 
 impl AudioProcessingUnit {
-    pub fn new(audio_subsystem: AudioSubsystem) -> AudioProcessingUnit {
+    pub fn new(audio_subsystem: Option<AudioSubsystem>) -> AudioProcessingUnit {
         let desired_spec = AudioSpecDesired {
             freq: Some(SAMPLES_PER_SECOND as i32),
             channels: Some(2),
             samples: Some(256),
         };
-        let ch_1_queue = audio_subsystem.open_queue(None, &desired_spec).unwrap();
-        let ch_2_queue = audio_subsystem.open_queue(None, &desired_spec).unwrap();
-        let ch_3_queue = audio_subsystem.open_queue(None, &desired_spec).unwrap();
-        let ch_4_queue = audio_subsystem.open_queue(None, &desired_spec).unwrap();
+        let queues = audio_subsystem.as_ref().map(|audio_subsystem| {
+            let ch_1_queue = audio_subsystem.open_queue(None, &desired_spec).unwrap();
+            let ch_2_queue = audio_subsystem.open_queue(None, &desired_spec).unwrap();
+            let ch_3_queue = audio_subsystem.open_queue(None, &desired_spec).unwrap();
+            let ch_4_queue = audio_subsystem.open_queue(None, &desired_spec).unwrap();
+            [ch_1_queue, ch_2_queue, ch_3_queue, ch_4_queue]
+        });
         AudioProcessingUnit {
             _audio_subsystem: audio_subsystem,
             length_counters: [0; 4],
@@ -73,7 +77,7 @@ impl AudioProcessingUnit {
             volumes: [0; 4],
             vol_timers: [1; 4],
             frequencies: [1; 4],
-            queues: [ch_1_queue, ch_2_queue, ch_3_queue, ch_4_queue],
+            queues,
             channel_enables: [false; 4],
             so1_enables: [0; 4],
             so2_enables: [0; 4],
@@ -105,6 +109,7 @@ impl AudioProcessingUnit {
 
             ch_4_lsfr: 0,
             ch_4_width: false,
+            mixed_sample: [0.0; 2],
         }
     }
 }
@@ -428,30 +433,36 @@ impl GameBoyEmulator {
     fn channel_1_buffer_add(&mut self) {
         let enable = self.apu.channel_enables[CH1_IND] & self.apu.all_sound_enable;
         if !enable {
-            self.apu.queues[CH1_IND].queue(&[0.0, 0.0]);
+            self.queue_sample(CH1_IND, &[0.0, 0.0]);
         } else {
             let so1_mod = self.apu.so1_enables[CH1_IND] as f32 * self.apu.so1_level;
             let so2_mod = self.apu.so2_enables[CH1_IND] as f32 * self.apu.so2_level;
 
             let duty_mod = ((self.apu.ch_1_duty_val >> self.apu.ch_1_duty_counter) & 1) as f32;
-            self.apu.queues[CH1_IND].queue(&[
-                (self.apu.volumes[CH1_IND] as f32 * so2_mod * duty_mod) / 100.0,
-                (self.apu.volumes[CH1_IND] as f32 * so1_mod * duty_mod) / 100.0,
-            ]);
+            self.queue_sample(
+                CH1_IND,
+                &[
+                    (self.apu.volumes[CH1_IND] as f32 * so2_mod * duty_mod) / 100.0,
+                    (self.apu.volumes[CH1_IND] as f32 * so1_mod * duty_mod) / 100.0,
+                ],
+            );
         }
     }
     fn channel_2_buffer_add(&mut self) {
         let enable = self.apu.channel_enables[CH2_IND] & self.apu.all_sound_enable;
         if !enable {
-            self.apu.queues[CH2_IND].queue(&[0.0, 0.0]);
+            self.queue_sample(CH2_IND, &[0.0, 0.0]);
         } else {
             let so1_mod = self.apu.so1_enables[CH2_IND] as f32 * self.apu.so1_level;
             let so2_mod = self.apu.so2_enables[CH2_IND] as f32 * self.apu.so2_level;
             let duty_mod = ((self.apu.ch_2_duty_val >> self.apu.ch_2_duty_counter) & 1) as f32;
-            self.apu.queues[CH2_IND].queue(&[
-                (self.apu.volumes[CH2_IND] as f32 * so2_mod * duty_mod) / 100.0,
-                (self.apu.volumes[CH2_IND] as f32 * so1_mod * duty_mod) / 100.0,
-            ]);
+            self.queue_sample(
+                CH2_IND,
+                &[
+                    (self.apu.volumes[CH2_IND] as f32 * so2_mod * duty_mod) / 100.0,
+                    (self.apu.volumes[CH2_IND] as f32 * so1_mod * duty_mod) / 100.0,
+                ],
+            );
         }
     }
     fn channel_3_buffer_add(&mut self) {
@@ -459,7 +470,7 @@ impl GameBoyEmulator {
         let output_shift =
             VOLUME_SHIFT_CONVERSION[self.get_memory(NR32_ADDR, SOURCE) as usize >> 5 & 0x3];
         if !enable {
-            self.apu.queues[CH3_IND].queue(&[0.0, 0.0]);
+            self.queue_sample(CH3_IND, &[0.0, 0.0]);
         } else {
             let so1_mod = self.apu.so1_enables[CH3_IND] as f32 * self.apu.so1_level;
             let so2_mod = self.apu.so2_enables[CH3_IND] as f32 * self.apu.so2_level;
@@ -469,45 +480,61 @@ impl GameBoyEmulator {
                 self.get_memory(0xFF30 + (self.apu.ch_3_pointer - 1) / 2, SOURCE) & 0xF
             };
 
-            self.apu.queues[CH3_IND].queue(&[
-                ((wave_val >> output_shift) as f32 * so2_mod) / 100.0,
-                ((wave_val >> output_shift) as f32 * so1_mod) / 100.0,
-            ]);
+            self.queue_sample(
+                CH3_IND,
+                &[
+                    ((wave_val >> output_shift) as f32 * so2_mod) / 100.0,
+                    ((wave_val >> output_shift) as f32 * so1_mod) / 100.0,
+                ],
+            );
         }
     }
     fn channel_4_buffer_add(&mut self) {
         let enable = self.apu.channel_enables[CH4_IND] & self.apu.all_sound_enable;
         if !enable {
-            self.apu.queues[CH4_IND].queue(&[0.0, 0.0]);
+            self.queue_sample(CH4_IND, &[0.0, 0.0]);
         } else {
             let so1_mod = self.apu.so1_enables[CH4_IND] as f32 * self.apu.so1_level;
             let so2_mod = self.apu.so2_enables[CH4_IND] as f32 * self.apu.so2_level;
             let reg_mod = (1 - (self.apu.ch_4_lsfr & 1)) as f32;
-            self.apu.queues[CH4_IND].queue(&[
-                (self.apu.volumes[CH4_IND] as f32 * so2_mod * reg_mod) / 100.0,
-                (self.apu.volumes[CH4_IND] as f32 * so1_mod * reg_mod) / 100.0,
-            ]);
+            self.queue_sample(
+                CH4_IND,
+                &[
+                    (self.apu.volumes[CH4_IND] as f32 * so2_mod * reg_mod) / 100.0,
+                    (self.apu.volumes[CH4_IND] as f32 * so1_mod * reg_mod) / 100.0,
+                ],
+            );
         }
     }
-    fn buffer_empty(&self) -> bool {
-        return self.apu.queues[CH1_IND].size() == 0
-            || self.apu.queues[CH2_IND].size() == 0
-            || self.apu.queues[CH3_IND].size() == 0
-            || self.apu.queues[CH4_IND].size() == 0;
+    fn queue_sample(&mut self, channel_ind: usize, sample: &[f32; 2]) {
+        self.apu.mixed_sample[0] += sample[0];
+        self.apu.mixed_sample[1] += sample[1];
+        if let Some(queues) = &self.apu.queues {
+            queues[channel_ind].queue(sample);
+        }
+    }
+    fn buffer_empty(queues: &[AudioQueue<f32>; 4]) -> bool {
+        queues[CH1_IND].size() == 0
+            || queues[CH2_IND].size() == 0
+            || queues[CH3_IND].size() == 0
+            || queues[CH4_IND].size() == 0
     }
     pub fn buffer_check(&mut self) {
-        if self.buffer_empty() && !self.apu.buffering {
+        let queues = match &self.apu.queues {
+            Some(queues) => queues,
+            None => return,
+        };
+        let buffer_empty = GameBoyEmulator::buffer_empty(queues);
+        if buffer_empty && !self.apu.buffering {
             self.apu.buffering = true;
-            self.apu.queues[CH1_IND].pause();
-            self.apu.queues[CH2_IND].pause();
-            self.apu.queues[CH3_IND].pause();
-            self.apu.queues[CH4_IND].pause();
-        } else if !self.buffer_empty() && self.apu.buffering {
+            for queue in queues.iter() {
+                queue.pause();
+            }
+        } else if !buffer_empty && self.apu.buffering {
             self.apu.buffering = false;
-            self.apu.queues[CH1_IND].resume();
-            self.apu.queues[CH2_IND].resume();
-            self.apu.queues[CH3_IND].resume();
-            self.apu.queues[CH4_IND].resume();
+            for queue in queues.iter() {
+                queue.resume();
+            }
         }
     }
     pub fn apu_advance(&mut self) {
@@ -590,6 +617,8 @@ impl GameBoyEmulator {
             self.channel_2_buffer_add();
             self.channel_3_buffer_add();
             self.channel_4_buffer_add();
+            let sample = std::mem::take(&mut self.apu.mixed_sample);
+            self.record_sample(sample);
         }
     }
 }
@@ -1,3 +1,9 @@
+use crate::constants::*;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
 pub const DMG_BOOTROM: [u8; 256] = [
     0x31, 0xFE, 0xFF, 0xAF, 0x21, 0xFF, 0x9F, 0x32, 0xCB, 0x7C, 0x20, 0xFB, 0x21, 0x26, 0xFF, 0x0E,
     0x11, 0x3E, 0x80, 0x32, 0xE2, 0x0C, 0x3E, 0xF3, 0xE2, 0x32, 0x3E, 0x77, 0x77, 0x3E, 0xFC, 0xE0,
@@ -150,3 +156,146 @@ pub const CGB_BOOTROM_2: [u8; 1792] = [
     0x1F, 0x00, 0xFF, 0x03, 0x40, 0x41, 0x42, 0x20, 0x21, 0x22, 0x80, 0x81, 0x82, 0x10, 0x11, 0x12,
     0x12, 0xB0, 0x79, 0xB8, 0xAD, 0x16, 0x17, 0x07, 0xBA, 0x05, 0x7C, 0x13, 0x00, 0x00, 0x00, 0x00,
 ];
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
+pub enum BootRomModel {
+    Dmg0,
+    Dmg,
+    Mgb,
+    Sgb,
+    Cgb,
+    Agb,
+}
+
+impl BootRomModel {
+    pub fn from_name(name: &str) -> Option<BootRomModel> {
+        match name {
+            "dmg0" => Some(BootRomModel::Dmg0),
+            "dmg" => Some(BootRomModel::Dmg),
+            "mgb" => Some(BootRomModel::Mgb),
+            "sgb" => Some(BootRomModel::Sgb),
+            "cgb" => Some(BootRomModel::Cgb),
+            "agb" => Some(BootRomModel::Agb),
+            _ => None,
+        }
+    }
+    /// The size of a dump of this model's boot ROM.
+    pub fn size(self) -> usize {
+        match self {
+            BootRomModel::Cgb | BootRomModel::Agb => CGB_BOOT_ROM_SIZE,
+            _ => DMG_BOOT_ROM_SIZE,
+        }
+    }
+}
+
+/// Boot ROM dumps supplied by the user, falling back to the built in ones.
+pub struct BootRoms {
+    images: HashMap<BootRomModel, Vec<u8>>,
+    pub skip: bool,
+}
+
+impl BootRoms {
+    pub fn new() -> BootRoms {
+        BootRoms {
+            images: HashMap::new(),
+            skip: false,
+        }
+    }
+    pub fn load(&mut self, model: BootRomModel, path: &Path) -> io::Result<()> {
+        let image = fs::read(path)?;
+        if image.len() != model.size() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "a {:?} boot ROM is {} bytes, not {}",
+                    model,
+                    model.size(),
+                    image.len()
+                ),
+            ));
+        }
+        self.images.insert(model, image);
+        Ok(())
+    }
+    /// Whether `model` has no dump of its own, so another model's built in boot ROM runs in
+    /// its place.
+    pub fn stands_in(&self, model: BootRomModel) -> bool {
+        matches!(
+            model,
+            BootRomModel::Mgb | BootRomModel::Sgb | BootRomModel::Agb
+        ) && !self.images.contains_key(&model)
+    }
+    /// The boot ROM to run for `model`. A DMG0 dump stands in for a missing DMG one.
+    pub fn image(&self, model: BootRomModel) -> Vec<u8> {
+        let supplied = match model {
+            BootRomModel::Dmg => self
+                .images
+                .get(&BootRomModel::Dmg)
+                .or_else(|| self.images.get(&BootRomModel::Dmg0)),
+            _ => self.images.get(&model),
+        };
+        if let Some(image) = supplied {
+            return image.clone();
+        }
+        match model.size() {
+            CGB_BOOT_ROM_SIZE => {
+                let mut image = vec![0; CGB_BOOT_ROM_SIZE];
+                image[..DMG_BOOT_ROM_SIZE].copy_from_slice(&CGB_BOOTROM_1);
+                image[CGB_BOOT_ROM_GAP_END..].copy_from_slice(&CGB_BOOTROM_2);
+                image
+            }
+            _ => DMG_BOOTROM.to_vec(),
+        }
+    }
+    /// The BG, OBJ0 and OBJ1 palettes `model`'s boot ROM colorizes the DMG cartridge `rom` with
+    /// when no buttons are held, looked up in the boot ROM's own tables.
+    pub fn compat_palettes(&self, model: BootRomModel, rom: &[u8]) -> [[u16; 4]; 3] {
+        let image = self.image(model);
+        let licensed = match rom[OLD_LICENSEE_ADDR] {
+            SGB_LICENSEE => &rom[NEW_LICENSEE_ADDR..NEW_LICENSEE_ADDR + 2] == NINTENDO_NEW_LICENSEE,
+            licensee => licensee == NINTENDO_LICENSEE,
+        };
+        let checksum = rom[ROM_TITLE_START_ADDR..ROM_TITLE_END_ADDR]
+            .iter()
+            .fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+        let checksums = &image[COMPAT_TITLE_CHECKSUMS_ADDR..][..COMPAT_TITLE_CHECKSUM_COUNT];
+        let letters = &image[COMPAT_FOURTH_LETTERS_ADDR..][..COMPAT_FOURTH_LETTER_COUNT];
+        let index = checksums
+            .iter()
+            .position(|sum| licensed && *sum == checksum)
+            .and_then(|index| {
+                if index < COMPAT_FIRST_AMBIGUOUS {
+                    return Some(index);
+                }
+                (index - COMPAT_FIRST_AMBIGUOUS..letters.len())
+                    .step_by(COMPAT_TITLE_CHECKSUM_COUNT - COMPAT_FIRST_AMBIGUOUS)
+                    .find(|letter| letters[*letter] == rom[ROM_TITLE_START_ADDR + 3])
+                    .map(|letter| COMPAT_FIRST_AMBIGUOUS + letter)
+            })
+            .unwrap_or(0);
+        // The low five bits pick an OBJ0, OBJ1 and BG palette. The top three say which of
+        // them the OBJ palettes really use.
+        let id = image[COMPAT_PALETTE_IDS_ADDR + index];
+        let triplet = &image[COMPAT_PALETTE_TRIPLETS_ADDR + (id & 0x1F) as usize * 3..][..3];
+        let palette = |offset: u8| {
+            let mut colors = [0; 4];
+            for (i, color) in colors.iter_mut().enumerate() {
+                let addr = COMPAT_PALETTE_COLORS_ADDR + offset as usize + i * 2;
+                *color = u16::from_le_bytes([image[addr], image[addr + 1]]);
+            }
+            colors
+        };
+        let bg = palette(triplet[2]);
+        let obj0 = if id & 0x20 != 0 {
+            palette(triplet[0])
+        } else {
+            bg
+        };
+        let obj1 = match id >> 6 {
+            0 => bg,
+            1 => palette(triplet[0]),
+            _ => palette(triplet[1]),
+        };
+        [bg, obj0, obj1]
+    }
+}
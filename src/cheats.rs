@@ -0,0 +1,350 @@
+use crate::constants::*;
+use crate::emulator::{GameBoyEmulator, RequestSource};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const SOURCE: RequestSource = RequestSource::SPEC;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum CheatCode {
+    /// Writes `val` to `addr` every frame. `bank` selects the WRAM bank for 0xD000-0xDFFF
+    /// on the CGB, otherwise the write goes to whatever is mapped there.
+    GameShark {
+        bank: Option<usize>,
+        addr: usize,
+        val: u8,
+    },
+    /// Replaces ROM reads from `addr` with `val`, but only while the ROM holds `compare`
+    /// there when one is given.
+    GameGenie {
+        addr: usize,
+        val: u8,
+        compare: Option<u8>,
+    },
+}
+
+impl CheatCode {
+    /// Parses an 8 digit GameShark code (`01VVLLHH`) or a 6 or 9 digit Game Genie code
+    /// (`ABC-DEF` or `ABC-DEF-GHI`).
+    pub fn parse(code: &str) -> Result<CheatCode, String> {
+        let digits: String = code.chars().filter(|c| *c != '-').collect();
+        let nibbles = digits
+            .chars()
+            .map(|c| c.to_digit(16).map(|d| d as usize))
+            .collect::<Option<Vec<usize>>>()
+            .ok_or_else(|| format!("\"{}\" is not a hex code", code))?;
+        match nibbles.len() {
+            8 if !code.contains('-') => {
+                let byte = |i: usize| (nibbles[i] << 4) | nibbles[i + 1];
+                let addr = (byte(6) << 8) | byte(4);
+                if addr < VRAM_START_ADDR {
+                    return Err(format!("GameShark code \"{}\" does not write to RAM", code));
+                }
+                let bank = if nibbles[0] == GAMESHARK_WRAM_BANK_TYPE {
+                    Some(nibbles[1])
+                } else {
+                    None
+                };
+                Ok(CheatCode::GameShark {
+                    bank,
+                    addr,
+                    val: byte(2) as u8,
+                })
+            }
+            6 | 9 => {
+                let addr =
+                    ((nibbles[5] ^ 0xF) << 12) | (nibbles[2] << 8) | (nibbles[3] << 4) | nibbles[4];
+                if addr >= VRAM_START_ADDR {
+                    return Err(format!("Game Genie code \"{}\" does not patch ROM", code));
+                }
+                let compare = if nibbles.len() == 9 {
+                    let encoded = ((nibbles[6] << 4) | nibbles[8]) as u8;
+                    Some(encoded.rotate_right(2) ^ GAME_GENIE_COMPARE_XOR)
+                } else {
+                    None
+                };
+                Ok(CheatCode::GameGenie {
+                    addr,
+                    val: ((nibbles[0] << 4) | nibbles[1]) as u8,
+                    compare,
+                })
+            }
+            _ => Err(format!(
+                "\"{}\" is not a GameShark or Game Genie code",
+                code
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Cheat {
+    pub name: String,
+    pub code: String,
+    pub parsed: CheatCode,
+    pub enabled: bool,
+}
+
+/// The cheats for the loaded ROM, and the file they were read from.
+pub struct Cheats {
+    pub list: Vec<Cheat>,
+    pub selected: Option<usize>,
+    pub file: Option<PathBuf>,
+    /// Enabled Game Genie codes, kept apart so ROM reads only look at these.
+    rom_patches: Vec<CheatCode>,
+}
+
+impl Cheats {
+    pub fn new() -> Cheats {
+        Cheats {
+            list: Vec::new(),
+            selected: None,
+            file: None,
+            rom_patches: Vec::new(),
+        }
+    }
+    /// Reads a cheat file where each line is a code followed by a name. A `-` in front of
+    /// the code disables that cheat and lines starting with `#` are comments.
+    pub fn load(path: &Path) -> io::Result<Cheats> {
+        let text = fs::read_to_string(path)?;
+        let mut cheats = Cheats::new();
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (code, name) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let (code, enabled) = match code.strip_prefix('-') {
+                Some(code) => (code, false),
+                None => (code, true),
+            };
+            let parsed = CheatCode::parse(code)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            cheats.list.push(Cheat {
+                name: name.trim().to_string(),
+                code: code.to_string(),
+                parsed,
+                enabled,
+            });
+        }
+        cheats.file = Some(path.to_path_buf());
+        cheats.update_patches();
+        Ok(cheats)
+    }
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut text = String::from("# code name, with a - in front of disabled codes\n");
+        for cheat in self.list.iter() {
+            let prefix = if cheat.enabled { "" } else { "-" };
+            text.push_str(&format!("{}{} {}\n", prefix, cheat.code, cheat.name));
+        }
+        fs::write(path, text)
+    }
+    fn update_patches(&mut self) {
+        self.rom_patches = self
+            .list
+            .iter()
+            .filter(|cheat| cheat.enabled)
+            .map(|cheat| cheat.parsed)
+            .filter(|code| matches!(code, CheatCode::GameGenie { .. }))
+            .collect();
+    }
+    /// The byte a ROM read from `addr` returns once Game Genie codes are applied.
+    pub fn patch_rom(&self, addr: usize, rom_val: u8) -> u8 {
+        for patch in self.rom_patches.iter() {
+            if let CheatCode::GameGenie {
+                addr: patch_addr,
+                val,
+                compare,
+            } = *patch
+            {
+                if patch_addr == addr && compare.is_none_or(|compare| compare == rom_val) {
+                    return val;
+                }
+            }
+        }
+        rom_val
+    }
+    pub fn patches_rom(&self) -> bool {
+        !self.rom_patches.is_empty()
+    }
+}
+
+impl GameBoyEmulator {
+    /// Adds an enabled cheat and returns its index.
+    pub fn add_cheat(&mut self, code: &str, name: &str) -> Result<usize, String> {
+        let parsed = CheatCode::parse(code)?;
+        self.cheats.list.push(Cheat {
+            name: name.to_string(),
+            code: code.to_string(),
+            parsed,
+            enabled: true,
+        });
+        self.cheats.update_patches();
+        Ok(self.cheats.list.len() - 1)
+    }
+    /// Enables or disables a cheat and returns whether it is now enabled. The change is
+    /// written back to the cheat file.
+    pub fn toggle_cheat(&mut self, index: usize) -> Option<bool> {
+        let cheat = self.cheats.list.get_mut(index)?;
+        cheat.enabled = !cheat.enabled;
+        let enabled = cheat.enabled;
+        self.cheats.update_patches();
+        if let Some(file) = &self.cheats.file {
+            if let Err(e) = self.cheats.save(file) {
+                eprintln!("Could not save cheats: {}", e);
+            }
+        }
+        Some(enabled)
+    }
+    pub fn select_next_cheat(&mut self) {
+        if self.cheats.list.is_empty() {
+            eprintln!("No cheats loaded");
+            return;
+        }
+        let index = self
+            .cheats
+            .selected
+            .map_or(0, |i| (i + 1) % self.cheats.list.len());
+        self.cheats.selected = Some(index);
+        let cheat = &self.cheats.list[index];
+        eprintln!(
+            "Cheat {}: {} {} ({})",
+            index,
+            cheat.code,
+            cheat.name,
+            if cheat.enabled { "on" } else { "off" }
+        );
+    }
+    pub fn toggle_selected_cheat(&mut self) {
+        let index = match self.cheats.selected {
+            Some(index) => index,
+            None => return,
+        };
+        if let Some(enabled) = self.toggle_cheat(index) {
+            let cheat = &self.cheats.list[index];
+            eprintln!(
+                "Cheat {} {}",
+                cheat.name,
+                if enabled { "enabled" } else { "disabled" }
+            );
+        }
+    }
+    /// Loads the cheat file that sits next to the ROM, if there is one.
+    pub fn load_cheats(&mut self, path: &Path) {
+        if !path.exists() {
+            self.cheats = Cheats::new();
+            self.cheats.file = Some(path.to_path_buf());
+            return;
+        }
+        match Cheats::load(path) {
+            Ok(cheats) => {
+                eprintln!(
+                    "Loaded {} cheats from {}",
+                    cheats.list.len(),
+                    path.display()
+                );
+                self.cheats = cheats;
+            }
+            Err(e) => eprintln!("Could not load cheats: {}", e),
+        }
+    }
+    /// Applies the enabled GameShark codes, once per frame at the start of VBlank.
+    pub fn apply_cheats(&mut self) {
+        for i in 0..self.cheats.list.len() {
+            let cheat = &self.cheats.list[i];
+            if !cheat.enabled {
+                continue;
+            }
+            if let CheatCode::GameShark { bank, addr, val } = cheat.parsed {
+                let banked_wram =
+                    (WRAM_START_ADDR + WRAM_BANK_SIZE..ECHO_RAM_START_ADDR).contains(&addr);
+                match bank {
+                    Some(bank) if self.cgb && banked_wram => self.write_wram_bank(bank, addr, val),
+                    _ => self.write_memory(addr, val, SOURCE),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{booted_rom, test_rom};
+
+    #[test]
+    fn parses_codes() {
+        assert_eq!(
+            CheatCode::parse("010238CD"),
+            Ok(CheatCode::GameShark {
+                bank: None,
+                addr: 0xCD38,
+                val: 0x02
+            })
+        );
+        assert_eq!(
+            CheatCode::parse("9303A0D1"),
+            Ok(CheatCode::GameShark {
+                bank: Some(3),
+                addr: 0xD1A0,
+                val: 0x03
+            })
+        );
+        assert_eq!(
+            CheatCode::parse("00A-17B"),
+            Ok(CheatCode::GameGenie {
+                addr: 0x4A17,
+                val: 0x00,
+                compare: None
+            })
+        );
+        // The compare byte is stored XORed with 0xBA and rotated left by two.
+        let encoded = (0xC9u8 ^ 0xBA).rotate_left(2);
+        let code = format!("3EA-17B-{:X}E{:X}", encoded >> 4, encoded & 0xF);
+        assert_eq!(
+            CheatCode::parse(&code),
+            Ok(CheatCode::GameGenie {
+                addr: 0x4A17,
+                val: 0x3E,
+                compare: Some(0xC9)
+            })
+        );
+        assert!(CheatCode::parse("01020040").is_err());
+        assert!(CheatCode::parse("00A-177").is_err());
+        assert!(CheatCode::parse("hello").is_err());
+    }
+
+    #[test]
+    fn applies_and_toggles_cheats() {
+        let dir = std::env::temp_dir().join(format!("gb-cheats-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("game.cht");
+        fs::write(&path, "# test\n01AAA0C0 Lives\n-01BBA1C0 Off\n").unwrap();
+        let mut rom = test_rom(&[0xF3, 0x76, 0x18, 0xFD]);
+        rom[0x4123] = 0x77;
+        let mut em = booted_rom(rom);
+        em.load_cheats(&path);
+        assert_eq!(em.cheats.list.len(), 2);
+        em.run_headless(ADVANCES_PER_FRAME);
+        assert_eq!(em.get_memory(0xC0A0_usize, RequestSource::CPU), 0xAA);
+        assert_ne!(em.get_memory(0xC0A1_usize, RequestSource::CPU), 0xBB);
+
+        assert_eq!(em.get_memory(0x4123_usize, RequestSource::CPU), 0x77);
+        let index = em.add_cheat("121-23B", "Patch").unwrap();
+        assert_eq!(em.get_memory(0x4123_usize, RequestSource::CPU), 0x12);
+        em.toggle_cheat(index);
+        assert_eq!(em.get_memory(0x4123_usize, RequestSource::CPU), 0x77);
+
+        em.toggle_cheat(1);
+        em.run_headless(ADVANCES_PER_FRAME);
+        assert_eq!(em.get_memory(0xC0A1_usize, RequestSource::CPU), 0xBB);
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(saved.contains("\n01AAA0C0 Lives\n01BBA1C0 Off\n-121-23B Patch\n"));
+
+        em.select_next_cheat();
+        em.toggle_selected_cheat();
+        assert_eq!(em.cheats.selected, Some(0));
+        assert!(!em.cheats.list[0].enabled);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,93 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The options a config file can set, and whether each one takes a value.
+const CONFIG_OPTIONS: [(&str, bool); 19] = [
+    ("link-host", true),
+    ("link-connect", true),
+    ("printer", true),
+    ("local-link", true),
+    ("fifo", false),
+    ("cgb", false),
+    ("model", true),
+    ("screenshot-dir", true),
+    ("record", true),
+    ("cheat", true),
+    ("watch", true),
+    ("watch-log", true),
+    ("boot-rom", true),
+    ("skip-boot", false),
+    ("headless-screenshot", true),
+    ("color-correction", true),
+    ("filter", true),
+    ("frame-blend", false),
+    ("palette", true),
+];
+
+/// Turns config lines into the command line options they stand for. `option = value` lines
+/// become `--option value`, a bare `option` becomes `--option`, and lines starting with `#`
+/// are comments.
+pub fn config_args(text: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let (option, value) = match line.split_once('=') {
+            Some((option, value)) => (option.trim(), Some(value.trim())),
+            None => (line, None),
+        };
+        match CONFIG_OPTIONS.iter().find(|(name, _)| *name == option) {
+            Some((_, true)) if value.is_none() => {
+                return Err(format!("\"{}\" needs a value", option))
+            }
+            Some((_, false)) if value.is_some() => {
+                return Err(format!("\"{}\" does not take a value", option))
+            }
+            Some(_) => {}
+            None => return Err(format!("Unknown option \"{}\"", option)),
+        }
+        args.push(format!("--{}", option));
+        args.extend(value.map(str::to_string));
+    }
+    Ok(args)
+}
+
+/// Reads the options from a config file. A missing file is only an error if it was asked
+/// for by name.
+pub fn read_config(path: &Path, required: bool) -> Result<Vec<String>, String> {
+    match fs::read_to_string(path) {
+        Ok(text) => config_args(&text).map_err(|e| format!("{}: {}", path.display(), e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound && !required => Ok(Vec::new()),
+        Err(e) => Err(format!("{}: {}", path.display(), e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_become_options() {
+        let text = "# settings\nmodel = sgb2\n\nskip-boot\nboot-rom=dmg=boot.bin\n";
+        assert_eq!(
+            config_args(text).unwrap(),
+            vec![
+                "--model",
+                "sgb2",
+                "--skip-boot",
+                "--boot-rom",
+                "dmg=boot.bin"
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_and_malformed_options() {
+        assert!(config_args("modle = cgb").is_err());
+        assert!(config_args("model").is_err());
+        assert!(config_args("skip-boot = yes").is_err());
+        assert!(config_args("config = other.cfg").is_err());
+    }
+}
@@ -2,6 +2,7 @@ pub const WINDOW_WIDTH: usize = 160;
 pub const WINDOW_HEIGHT: usize = 144;
 
 //CPU Specific Constants
+pub const HALT_OPCODE: u8 = 0x76;
 pub const NUM_REG: usize = 7;
 pub const REG_A: usize = 0;
 pub const REG_B: usize = 1;
@@ -27,24 +28,20 @@ pub const ADVANCE_CYCLES: u32 = 4;
 pub const INT_ENABLE_ADDR: usize = 0xFFFF;
 
 pub const P1_ADDR: usize = 0xFF00;
+pub const SB_ADDR: usize = 0xFF01;
+pub const SC_ADDR: usize = 0xFF02;
 pub const DIV_ADDR: usize = 0xFF04;
 pub const TIMA_ADDR: usize = 0xFF05;
 pub const TMA_ADDR: usize = 0xFF06;
 pub const TAC_ADDR: usize = 0xFF07;
 pub const INT_FLAG_ADDR: usize = 0xFF0F;
-pub const NR10_ADDR: usize = 0xFF10;
-pub const NR11_ADDR: usize = 0xFF11;
 pub const NR12_ADDR: usize = 0xFF12;
 pub const NR13_ADDR: usize = 0xFF13;
 pub const NR14_ADDR: usize = 0xFF14;
-pub const NR21_ADDR: usize = 0xFF16;
 pub const NR22_ADDR: usize = 0xFF17;
-pub const NR24_ADDR: usize = 0xFF19;
 pub const NR30_ADDR: usize = 0xFF1A;
 pub const NR32_ADDR: usize = 0xFF1C;
-pub const NR34_ADDR: usize = 0xFF1E;
 pub const NR42_ADDR: usize = 0xFF21;
-pub const NR44_ADDR: usize = 0xFF23;
 pub const NR52_ADDR: usize = 0xFF26;
 pub const LCDC_ADDR: usize = 0xFF40;
 pub const STAT_ADDR: usize = 0xFF41;
@@ -67,6 +64,7 @@ pub const VRAM_START_ADDR: usize = 0x8000;
 pub const OAM_START_ADDR: usize = 0xFE00;
 pub const ERAM_START_ADDR: usize = 0xA000;
 pub const WRAM_START_ADDR: usize = 0xC000;
+pub const ECHO_RAM_START_ADDR: usize = 0xE000;
 pub const ROM_BANK_SIZE: usize = 0x4000;
 pub const HRAM_START_ADDR: usize = 0xFF80;
 pub const IO_START_ADDR: usize = 0xFF00;
@@ -81,12 +79,18 @@ pub const HDMA2_ADDR: usize = 0xFF52;
 pub const HDMA3_ADDR: usize = 0xFF53;
 pub const HDMA4_ADDR: usize = 0xFF54;
 pub const HDMA5_ADDR: usize = 0xFF55;
+pub const KEY0_ADDR: usize = 0xFF4C;
 pub const KEY1_ADDR: usize = 0xFF4D;
 pub const SVBK_ADDR: usize = 0xFF70;
 pub const VBK_ADDR: usize = 0xFF4F;
 
 pub const DMA_LENGTH: usize = 0xA0;
+pub const OAM_BUG_END_ADDR: usize = 0xFEFF;
+pub const OAM_ROWS: usize = 20;
+pub const OAM_ROW_LENGTH: usize = 8;
+pub const OAM_DMA_START_DELAY: u8 = 1;
 pub const HDMA_BLOCK_LENGTH: usize = 0x10;
+pub const HDMA_BYTES_PER_CYCLE: usize = 2;
 
 //PPU Specific Constants
 pub const TILES_PER_ROW: usize = 32;
@@ -100,6 +104,12 @@ pub const OAM_SCAN_DOTS: u32 = 80;
 pub const DRAWING_DOTS: u32 = 172;
 pub const HBLANK_DOTS: u32 = 204;
 pub const ROW_DOTS: u32 = 456;
+pub const ADVANCES_PER_FRAME: u32 = 154 * ROW_DOTS / ADVANCE_CYCLES;
+pub const WINDOW_FETCH_DOTS: u32 = 6;
+pub const OBJ_FETCH_DOTS: u32 = 6;
+pub const OBJ_MAX_ALIGN_DOTS: u32 = 5;
+pub const FIFO_STARTUP_DOTS: u8 = 6;
+pub const FIFO_SPRITE_FETCH_DOTS: u8 = 6;
 pub const BYTES_PER_OAM_ENTRY: usize = 4;
 pub const OAM_Y_INDEX: usize = 0;
 pub const OAM_X_INDEX: usize = 1;
@@ -111,6 +121,51 @@ pub const DMG_COLOR_MAP: [[u8; 4]; 4] = [
     [48, 98, 48, 255],
     [15, 56, 15, 255],
 ];
+pub const GRAYSCALE_COLOR_MAP: [[u8; 4]; 4] = [
+    [255, 255, 255, 255],
+    [170, 170, 170, 255],
+    [85, 85, 85, 255],
+    [0, 0, 0, 255],
+];
+pub const POCKET_COLOR_MAP: [[u8; 4]; 4] = [
+    [196, 207, 161, 255],
+    [139, 149, 109, 255],
+    [77, 83, 60, 255],
+    [31, 31, 31, 255],
+];
+pub const LIGHT_COLOR_MAP: [[u8; 4]; 4] = [
+    [0, 181, 129, 255],
+    [0, 154, 113, 255],
+    [0, 105, 74, 255],
+    [0, 79, 59, 255],
+];
+pub const RED_COLOR_MAP: [[u8; 4]; 4] = [
+    [255, 255, 255, 255],
+    [255, 132, 132, 255],
+    [148, 58, 58, 255],
+    [0, 0, 0, 255],
+];
+pub const BLUE_COLOR_MAP: [[u8; 4]; 4] = [
+    [255, 255, 255, 255],
+    [99, 165, 255, 255],
+    [0, 0, 255, 255],
+    [0, 0, 0, 255],
+];
+pub const OUTPUT_GAMMA: f32 = 2.2;
+pub const GBC_LCD_GAMMA: f32 = 2.2;
+pub const GBC_LCD_MIX: [[f32; 3]; 3] = [
+    [26.0 / 32.0, 4.0 / 32.0, 2.0 / 32.0],
+    [0.0, 24.0 / 32.0, 8.0 / 32.0],
+    [6.0 / 32.0, 4.0 / 32.0, 22.0 / 32.0],
+];
+pub const GBC_LCD_SCALE: f32 = 0.94;
+pub const GBA_LCD_GAMMA: f32 = 4.0;
+pub const GBA_LCD_MIX: [[f32; 3]; 3] = [
+    [1.0, 50.0 / 255.0, 0.0],
+    [10.0 / 255.0, 230.0 / 255.0, 30.0 / 255.0],
+    [50.0 / 255.0, 10.0 / 255.0, 220.0 / 255.0],
+];
+pub const GBA_LCD_SCALE: f32 = 255.0 / 280.0;
 pub const BG_LCDC_LOW_PRIORITY: u8 = 220;
 pub const BG_COLOR_0_PRIORITY: u8 = 215;
 pub const OAM_LOW_PRIORITY: u8 = 210;
@@ -126,6 +181,43 @@ pub const TILE_MAP_1_START_ADDR: usize = 0x9800;
 pub const TILE_MAP_2_START_ADDR: usize = 0x9C00;
 pub const PIXEL_LENGTH: usize = 4;
 
+//Viewer Specific Constants
+pub const VIEWER_SCALE: usize = 2;
+pub const VRAM_TILES: usize = 384;
+pub const TILE_VIEW_COLUMNS: usize = 16;
+pub const TILE_VIEW_WIDTH: usize = 2 * TILE_VIEW_COLUMNS * TILE_WIDTH;
+pub const TILE_VIEW_HEIGHT: usize = VRAM_TILES / TILE_VIEW_COLUMNS * TILE_WIDTH;
+pub const OAM_VIEW_COLUMNS: usize = 8;
+pub const OAM_VIEW_CELL_WIDTH: usize = 24;
+pub const OAM_VIEW_CELL_HEIGHT: usize = 26;
+pub const OAM_VIEW_WIDTH: usize = OAM_VIEW_COLUMNS * OAM_VIEW_CELL_WIDTH;
+pub const OAM_VIEW_HEIGHT: usize = OAM_SPRITE_NUM / OAM_VIEW_COLUMNS * OAM_VIEW_CELL_HEIGHT;
+pub const PALETTE_VIEW_SWATCH: usize = 16;
+pub const PALETTE_VIEW_WIDTH: usize = 9 * PALETTE_VIEW_SWATCH;
+pub const PALETTE_VIEW_HEIGHT: usize = 8 * PALETTE_VIEW_SWATCH;
+pub const VIEWER_BACKGROUND: [u8; 4] = [40, 40, 48, 255];
+pub const VIEWER_SPRITE_BACKGROUND: [u8; 4] = [80, 80, 96, 255];
+pub const VIEWER_TEXT: [u8; 4] = [255, 255, 255, 255];
+pub const VIEWER_OUTLINE: [u8; 4] = [255, 0, 0, 255];
+pub const HEX_FONT: [[u8; 5]; 16] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+    [0b010, 0b101, 0b111, 0b101, 0b101],
+    [0b110, 0b101, 0b110, 0b101, 0b110],
+    [0b011, 0b100, 0b100, 0b100, 0b011],
+    [0b110, 0b101, 0b101, 0b101, 0b110],
+    [0b111, 0b100, 0b111, 0b100, 0b111],
+    [0b111, 0b100, 0b111, 0b100, 0b100],
+];
+
 //APU Specific Constants
 pub const DUTY_CONVERSION: [u8; 4] = [0b00000001, 0b10000001, 0b10000111, 0b01111110];
 pub const VOLUME_SHIFT_CONVERSION: [u8; 4] = [4, 0, 1, 2];
@@ -142,17 +234,220 @@ pub const SAMPLES_PER_SECOND: u32 = 44100;
 
 pub const AUDIO_BUFFER_CLOCK: f32 = CYCLES_PER_SECOND as f32 / SAMPLES_PER_SECOND as f32;
 
-pub const CYCLE_COUNT_16384HZ: u32 = 256;
+//Serial Specific Constants
+pub const SERIAL_DMG_BIT_CYCLES: u32 = 512;
+pub const SERIAL_FAST_BIT_CYCLES: u32 = 16;
+pub const LINK_SYNC_CYCLES: u32 = 4096;
+
+//Printer Specific Constants
+pub const PRINTER_MAGIC: [u8; 2] = [0x88, 0x33];
+pub const PRINTER_ALIVE: u8 = 0x81;
+pub const PRINTER_INIT_COMMAND: u8 = 0x01;
+pub const PRINTER_PRINT_COMMAND: u8 = 0x02;
+pub const PRINTER_DATA_COMMAND: u8 = 0x04;
+pub const PRINTER_BREAK_COMMAND: u8 = 0x08;
+pub const PRINTER_STATUS_COMMAND: u8 = 0x0F;
+pub const PRINTER_STATUS_CHECKSUM_ERROR: u8 = 0b0001;
+pub const PRINTER_STATUS_PRINTING: u8 = 0b0010;
+pub const PRINTER_STATUS_FULL: u8 = 0b0100;
+pub const PRINTER_STATUS_UNPROCESSED: u8 = 0b1000;
+pub const PRINTER_BAND_BYTES: usize = 640;
+pub const PRINTER_TILES_PER_ROW: usize = 20;
+pub const PRINTER_MAX_IMAGE_ROWS: usize = 144;
+pub const PRINTER_FEED_ROWS: usize = 8;
+pub const PRINTER_SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+pub const PRINTER_BUSY_CYCLES: u32 = CYCLES_PER_SECOND / 2;
+
+//Layer Specific Constants
+pub const OBJ_BOX_COLOR: [u8; 4] = [255, 0, 255, 255];
+
+//Filter Specific Constants
+pub const HQX_YUV_THRESHOLDS: [i32; 3] = [48, 7, 6];
+pub const LCD_GRID_MIN_SCALE: usize = 3;
+pub const LCD_GRID_SHADE: u32 = 80;
+
+//Recording Specific Constants
+pub const FRAME_CYCLES: u32 = 154 * ROW_DOTS;
+pub const AVI_FRAME_BYTES: usize = WINDOW_WIDTH * WINDOW_HEIGHT * 3;
+pub const AVI_MAX_BYTES: u64 = 0xF000_0000;
+pub const AVIF_HASINDEX: u32 = 0x10;
+pub const AVIIF_KEYFRAME: u32 = 0x10;
+pub const AVIH_FRAMES_OFFSET: usize = 16;
+pub const STRH_LENGTH_OFFSET: usize = 32;
+pub const WAVE_FORMAT_PCM: u32 = 1;
+pub const GIF_FRAME_STEP: u64 = 2;
+pub const GIF_QUANTIZE_SPEED: i32 = 10;
+
+//Cheat Specific Constants
+pub const GAMESHARK_WRAM_BANK_TYPE: usize = 0x9;
+pub const GAME_GENIE_COMPARE_XOR: u8 = 0xBA;
+
+//RAM Search Specific Constants
+pub const RAM_SEARCH_PRINTED_RESULTS: usize = 10;
+pub const RAM_SEARCH_WATCH_LOG: &str = "watch.csv";
+
+//Config Specific Constants
+pub const DEFAULT_CONFIG_FILE: &str = "gb-emulator.cfg";
+
+//Boot ROM Specific Constants
+pub const BOOT_ROM_EXIT_ADDR: u16 = 0x100;
+pub const DMG_BOOT_ROM_SIZE: usize = 0x100;
+pub const CGB_BOOT_ROM_SIZE: usize = 0x900;
+// The CGB boot ROM skips 0x100-0x1FF so the cartridge header shows through.
+pub const CGB_BOOT_ROM_GAP_END: usize = 0x200;
+pub const LOGO_START_ADDR: usize = 0x104;
+pub const LOGO_END_ADDR: usize = 0x134;
+pub const HEADER_CHECKSUM_ADDR: usize = 0x14D;
+pub const DMG_POST_BOOT_DIVIDER: u16 = 0xABCC;
+pub const CGB_POST_BOOT_DIVIDER: u16 = 0x1EA0;
+pub const CGB_COMPAT_KEY0: u8 = 0x04;
+// A, F, B, C, D, E, H and L after each model's boot ROM. Games tell the models apart by A
+// and B. The DMG and MGB clear H and C when the header checksum is zero.
+pub const DMG_POST_BOOT_REGISTERS: [u8; 8] = [0x01, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D];
+pub const MGB_POST_BOOT_REGISTERS: [u8; 8] = [0xFF, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D];
+pub const SGB_POST_BOOT_REGISTERS: [u8; 8] = [0x01, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60];
+pub const SGB2_POST_BOOT_REGISTERS: [u8; 8] = [0xFF, 0x00, 0x00, 0x14, 0x00, 0x00, 0xC0, 0x60];
+pub const CGB_POST_BOOT_REGISTERS: [u8; 8] = [0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D];
+pub const CGB_DMG_MODE_POST_BOOT_REGISTERS: [u8; 8] =
+    [0x11, 0x80, 0x00, 0x00, 0x00, 0x08, 0x00, 0x7C];
+pub const AGB_POST_BOOT_REGISTERS: [u8; 8] = [0x11, 0x00, 0x01, 0x00, 0xFF, 0x56, 0x00, 0x0D];
+pub const AGB_DMG_MODE_POST_BOOT_REGISTERS: [u8; 8] =
+    [0x11, 0x00, 0x01, 0x00, 0x00, 0x08, 0x00, 0x7C];
+// What the boot ROM leaves in the sound registers. The trigger bits are left clear so
+// skipping does not replay the end of the boot chime.
+pub const POST_BOOT_APU_REGISTERS: [(usize, u8); 20] = [
+    (0xFF10, 0x80),
+    (0xFF11, 0xBF),
+    (0xFF12, 0xF3),
+    (0xFF13, 0xFF),
+    (0xFF14, 0x3F),
+    (0xFF16, 0x3F),
+    (0xFF17, 0x00),
+    (0xFF18, 0xFF),
+    (0xFF19, 0x3F),
+    (0xFF1A, 0x7F),
+    (0xFF1B, 0xFF),
+    (0xFF1C, 0x9F),
+    (0xFF1D, 0xFF),
+    (0xFF1E, 0x3F),
+    (0xFF20, 0xFF),
+    (0xFF21, 0x00),
+    (0xFF22, 0x00),
+    (0xFF23, 0x3F),
+    (0xFF24, 0x77),
+    (0xFF25, 0xF3),
+];
+// Where the CGB boot ROM keeps the tables it colorizes DMG games with. Nintendo's games are
+// looked up by the sum of their title bytes. The sums from COMPAT_FIRST_AMBIGUOUS on are shared,
+// so the fourth title letter picks between entries 14 apart in the letter table.
+pub const COMPAT_TITLE_CHECKSUMS_ADDR: usize = 0x6C7;
+pub const COMPAT_TITLE_CHECKSUM_COUNT: usize = 79;
+pub const COMPAT_FIRST_AMBIGUOUS: usize = 65;
+pub const COMPAT_FOURTH_LETTERS_ADDR: usize = 0x716;
+pub const COMPAT_FOURTH_LETTER_COUNT: usize = 29;
+pub const COMPAT_PALETTE_IDS_ADDR: usize = 0x733;
+pub const COMPAT_PALETTE_TRIPLETS_ADDR: usize = 0x791;
+pub const COMPAT_PALETTE_COLORS_ADDR: usize = 0x7E8;
+pub const NINTENDO_LICENSEE: u8 = 0x01;
+pub const NINTENDO_NEW_LICENSEE: &[u8] = b"01";
+pub const NEW_LICENSEE_ADDR: usize = 0x144;
+
+//SGB Specific Constants
+pub const SGB_FLAG_ADDR: usize = 0x146;
+pub const OLD_LICENSEE_ADDR: usize = 0x14B;
+pub const SGB_SUPPORT_FLAG: u8 = 0x03;
+pub const SGB_LICENSEE: u8 = 0x33;
+pub const SGB_PACKET_LENGTH: usize = 16;
+pub const SGB_TRANSFER_SIZE: usize = 0x1000;
+pub const SGB_ATTR_COLUMNS: usize = 20;
+pub const SGB_ATTR_ROWS: usize = 18;
+pub const SGB_SYSTEM_PALETTES: usize = 512;
+pub const SGB_BORDER_WIDTH: usize = 256;
+pub const SGB_BORDER_HEIGHT: usize = 224;
+pub const SGB_BORDER_COLUMNS: usize = 32;
+pub const SGB_BORDER_TILES: usize = 256;
+pub const SGB_BORDER_TILE_SIZE: usize = 32;
+pub const SGB_BORDER_MAP_SIZE: usize = 0x800;
+pub const SGB_BORDER_PALETTES: usize = 4;
+pub const SGB_BORDER_PALETTE_OFFSET: usize = 4;
+pub const SGB_SCREEN_X: usize = 48;
+pub const SGB_SCREEN_Y: usize = 40;
+// The SGB's own palette 1-A, which games start with until they send their own.
+pub const SGB_DEFAULT_PALETTE: [u16; 4] = [0x67BF, 0x265B, 0x10B5, 0x2866];
+// The renderers draw these for the four shades in SGB mode, to be recolored per attribute
+// cell once the frame is done.
+pub const SGB_SHADE_KEYS: [[u8; 4]; 4] = [
+    [0xFF, 0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA, 0xFF],
+    [0x55, 0x55, 0x55, 0xFF],
+    [0x00, 0x00, 0x00, 0xFF],
+];
 
 //Memory Specific Constants
+pub const ROM_TITLE_START_ADDR: usize = 0x134;
+pub const ROM_TITLE_END_ADDR: usize = 0x144;
+pub const CGB_FLAG_ADDR: usize = 0x143;
 pub const VRAM_SIZE: usize = 0x2000;
 pub const IRAM_SIZE: usize = 0x8000;
 pub const OAM_SIZE: usize = 160;
 pub const IO_SIZE: usize = 0x80;
 pub const HRAM_SIZE: usize = 127;
-pub const NON_BLOCK_INVALID_IO: [usize; 24] = [
-    0x03, 0x8, 0x9, 0xA, 0xB, 0xC, 0xD, 0xE, 0x13, 0x15, 0x18, 0x1B, 0x1D, 0x1F, 0x20, 0x27, 0x28,
-    0x29, 0x2A, 0x2B, 0x2C, 0x2D, 0x2E, 0x2F,
+/// Bits of each IO register that always read back as 1 on the DMG. Unmapped registers
+/// read 0xFF.
+pub const DMG_IO_READ_MASKS: [u8; IO_SIZE] = [
+    // P1, SB, SC, DIV, TIMA, TMA, TAC, IF
+    0xC0, 0x00, 0x7E, 0xFF, 0x00, 0x00, 0x00, 0xF8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE0,
+    // NR10-NR34
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00, 0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF,
+    // NR41-NR52
+    0xFF, 0x00, 0x00, 0xBF, 0x00, 0x00, 0x70, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    // Wave RAM
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // LCDC, STAT, SCY, SCX, LY, LYC, DMA, BGP, OBP0, OBP1, WY, WX
+    0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF,
+    // Boot ROM and CGB registers
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    // CGB palettes
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    // CGB WRAM bank and undocumented
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+/// The same for CGB hardware, where the CGB-only registers are mapped.
+pub const CGB_IO_READ_MASKS: [u8; IO_SIZE] = [
+    // P1, SB, SC, DIV, TIMA, TMA, TAC, IF
+    0xC0, 0x00, 0x7C, 0xFF, 0x00, 0x00, 0x00, 0xF8, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xE0,
+    // NR10-NR34
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, 0xFF, 0x3F, 0x00, 0xFF, 0xBF, 0x7F, 0xFF, 0x9F, 0xFF, 0xBF, 0xFF,
+    // NR41-NR52
+    0xFF, 0x00, 0x00, 0xBF, 0x00, 0x00, 0x70, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    // Wave RAM
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // LCDC, STAT, SCY, SCX, LY, LYC, DMA, BGP, OBP0, OBP1, WY, WX, KEY0, KEY1, VBK
+    0x00, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xFF, 0x7E, 0xFF, 0xFE,
+    // Boot ROM, HDMA1-HDMA5, RP
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x00, 0x3C, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    // BCPS, BCPD, OCPS, OCPD, OPRI
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x40, 0x00, 0x40, 0x00, 0xFE, 0xFF, 0xFF, 0xFF,
+    // SVBK, undocumented registers, PCM12, PCM34
+    0xF8, 0xFF, 0x00, 0x00, 0x00, 0x8F, 0x00, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+];
+/// Bits of each IO register the CPU can change. Registers with their own write handling
+/// in `write_memory` apply these themselves where it matters.
+pub const IO_WRITE_MASKS: [u8; IO_SIZE] = [
+    // P1, SB, SC, DIV, TIMA, TMA, TAC, IF
+    0x30, 0xFF, 0x83, 0x00, 0xFF, 0xFF, 0xFF, 0x07, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x1F,
+    // NR10-NR34
+    0x7F, 0xFF, 0xFF, 0xFF, 0xC7, 0x00, 0xFF, 0xFF, 0xFF, 0xC7, 0x80, 0xFF, 0x60, 0xFF, 0xC7, 0x00,
+    // NR41-NR52
+    0x3F, 0xFF, 0xFF, 0xC0, 0xFF, 0xFF, 0x80, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // Wave RAM
+    0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF,
+    // LCDC, STAT, SCY, SCX, LY, LYC, DMA, BGP, OBP0, OBP1, WY, WX, KEY0, KEY1, VBK
+    0xFF, 0x78, 0xFF, 0xFF, 0x00, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01, 0x00, 0x01,
+    // Boot ROM, HDMA1-HDMA5, RP
+    0xFF, 0xFF, 0xF0, 0x1F, 0xF0, 0xFF, 0xC1, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+    // BCPS, BCPD, OCPS, OCPD, OPRI
+    0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xBF, 0xFF, 0xBF, 0xFF, 0x01, 0x00, 0x00, 0x00,
+    // SVBK, undocumented registers, PCM12, PCM34
+    0x07, 0x00, 0xFF, 0xFF, 0xFF, 0x70, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
 ];
-pub const NON_BLOCK_CGB_VALID_IO: [usize; 9] =
-    [0x51, 0x52, 0x53, 0x54, 0x55, 0x4D, 0x4F, 0x6C, 0x70];
@@ -1,6 +1,7 @@
 use crate::constants::*;
 use crate::emulator::GameBoyEmulator;
 use crate::emulator::RequestSource;
+use crate::memory::OamBug;
 use serde::{Deserialize, Serialize};
 
 const SOURCE: RequestSource = RequestSource::CPU;
@@ -326,10 +327,11 @@ pub struct CentralProcessingUnit {
     c_flag: u8,
     reenable_interrupts: bool,
     change_ime_true: bool,
-    halting: bool,
+    pub halting: bool,
     cycle_count: u32,
     repeat: bool,
     old_pc: u16,
+    opcode_pc: u16,
     pub waiting: bool,
     pub cycle_goal: u32,
     call_counter: i32,
@@ -356,6 +358,7 @@ impl CentralProcessingUnit {
             cycle_count: 0,
             repeat: false,
             old_pc: 0,
+            opcode_pc: 0,
             waiting: false,
             cycle_goal: 0,
             call_counter: 0,
@@ -365,32 +368,17 @@ impl CentralProcessingUnit {
         }
     }
 
-    fn cgb_initialize_after_boot(&mut self) {
-        self.regs[REG_A] = 0x11;
-        self.z_flag = 1;
-        self.n_flag = 0;
-        self.h_flag = 0;
-        self.c_flag = 0;
-        self.regs[REG_B] = 0;
-        self.regs[REG_C] = 0;
-        self.regs[REG_D] = 0xFF;
-        self.regs[REG_E] = 0x56;
-        self.regs[REG_H] = 0;
-        self.regs[REG_L] = 0xD;
-        self.sp = 0xFFFE;
-    }
-    fn dmg_initialize_after_boot(&mut self) {
-        self.regs[REG_A] = 1;
-        self.z_flag = 0;
-        self.n_flag = 0;
-        self.h_flag = 0;
-        self.c_flag = 0;
-        self.regs[REG_B] = 0xFF;
-        self.regs[REG_C] = 0x13;
-        self.regs[REG_D] = 0;
-        self.regs[REG_E] = 0xC1;
-        self.regs[REG_H] = 0x84;
-        self.regs[REG_L] = 0x3;
+    /// Loads A, F, B, C, D, E, H and L from `registers`.
+    fn initialize_after_boot(&mut self, registers: [u8; 8]) {
+        let [a, f, b, c, d, e, h, l] = registers;
+        self.regs[REG_A] = a;
+        self.write_f(f);
+        self.regs[REG_B] = b;
+        self.regs[REG_C] = c;
+        self.regs[REG_D] = d;
+        self.regs[REG_E] = e;
+        self.regs[REG_H] = h;
+        self.regs[REG_L] = l;
         self.sp = 0xFFFE;
     }
     fn add_set_flags_16(&mut self, val1: &u32, val2: &u32, z: bool, h: bool, c: bool) {
@@ -408,6 +396,10 @@ impl CentralProcessingUnit {
             self.c_flag = if (val1 + val2) > CARRY_LIMIT_16 { 1 } else { 0 };
         }
     }
+    /// Address of the instruction being executed.
+    pub fn get_pc(&self) -> u16 {
+        self.opcode_pc
+    }
     #[inline]
     fn get_f(&self) -> u8 {
         (self.z_flag << 7) + (self.n_flag << 6) + (self.h_flag << 5) + (self.c_flag << 4)
@@ -422,14 +414,21 @@ impl CentralProcessingUnit {
 }
 
 impl GameBoyEmulator {
+    /// Sets the registers the model's boot ROM leaves behind, for when it is skipped or
+    /// another model's boot ROM stood in for it.
     pub fn cpu_initialize_after_boot(&mut self) {
-        if self.cgb {
-            self.cpu.cgb_initialize_after_boot();
-        } else {
-            self.cpu.dmg_initialize_after_boot();
+        let dmg_mode = self.cgb && (self.get_memory(CGB_FLAG_ADDR, SOURCE) >> 7) == 0;
+        let mut registers = self.hardware.post_boot_registers(dmg_mode);
+        if self.get_memory(HEADER_CHECKSUM_ADDR, SOURCE) == 0 {
+            registers[1] &= 0x80;
         }
+        self.cpu.initialize_after_boot(registers);
+        self.cpu.pc = BOOT_ROM_EXIT_ADDR;
     }
     pub fn cpu_advance(&mut self) {
+        if self.hdma_stalling() {
+            return;
+        }
         if self.cpu.waiting {
             self.cpu.cycle_count += ADVANCE_CYCLES;
             if self.cpu.cycle_count == self.cpu.cycle_goal {
@@ -446,8 +445,9 @@ impl GameBoyEmulator {
                 self.cpu.change_ime_true = true;
             }
 
-            let viable_interrupts =
-                self.get_memory(INT_FLAG_ADDR, SOURCE) & self.get_memory(INT_ENABLE_ADDR, SOURCE);
+            let viable_interrupts = self.get_memory(INT_FLAG_ADDR, SOURCE)
+                & self.get_memory(INT_ENABLE_ADDR, SOURCE)
+                & 0x1F;
 
             if self.cpu.ime && viable_interrupts != 0 && !self.cpu.halting {
                 let (mask, addr) = match viable_interrupts.trailing_zeros() {
@@ -473,7 +473,13 @@ impl GameBoyEmulator {
                 self.cpu.cycle_count += ADVANCE_CYCLES;
                 self.cpu.cycle_goal = INTERRUPT_DOTS;
             } else {
-                self.cpu.command = self.get_memory(self.cpu.pc, SOURCE) as usize;
+                self.cpu.opcode_pc = self.cpu.pc;
+                // A halted CPU leaves the bus alone until an interrupt wakes it.
+                self.cpu.command = if self.cpu.halting {
+                    HALT_OPCODE as usize
+                } else {
+                    self.get_memory(self.cpu.pc, SOURCE) as usize
+                };
 
                 let repeat_operation = if self.cpu.repeat {
                     self.cpu.repeat = false;
@@ -502,15 +508,24 @@ impl GameBoyEmulator {
         }
     }
     fn push_stack(&mut self, high_val: u8, low_val: u8) {
+        self.oam_bug(self.cpu.sp, OamBug::Write);
         self.cpu.sp = self.cpu.sp.wrapping_sub(1);
         self.write_memory(self.cpu.sp, high_val, SOURCE);
         self.cpu.sp = self.cpu.sp.wrapping_sub(1);
         self.write_memory(self.cpu.sp, low_val, SOURCE);
     }
+    /// Reads memory the way an instruction does. Unlike `get_memory` this is a real bus access,
+    /// so a read from OAM while the PPU scans it triggers the OAM bug.
+    fn read_operand(&mut self, addr: impl Into<usize>) -> u8 {
+        let addr = addr.into();
+        self.oam_bug(addr, OamBug::Read);
+        self.get_memory(addr, SOURCE)
+    }
     fn pop_stack(&mut self) -> [u8; 2] {
         let val1 = self.get_memory((self.cpu.sp) as usize, SOURCE);
+        self.oam_bug(self.cpu.sp, OamBug::ReadIncrease);
         self.cpu.sp = self.cpu.sp.wrapping_add(1);
-        let val2 = self.get_memory((self.cpu.sp) as usize, SOURCE);
+        let val2 = self.read_operand(self.cpu.sp);
         self.cpu.sp = self.cpu.sp.wrapping_add(1);
         [val1, val2]
     }
@@ -618,6 +633,7 @@ impl GameBoyEmulator {
     fn inc_reg_16(&mut self, command: u8) {
         if command == 0x33 {
             //INC SP
+            self.oam_bug(self.cpu.sp, OamBug::Write);
             self.cpu.sp = self.cpu.sp.wrapping_add(1);
         } else {
             let (r_low, r_high) = match command {
@@ -630,6 +646,7 @@ impl GameBoyEmulator {
                 ),
             };
             let mut comb = combine_bytes(self.cpu.regs[r_high], self.cpu.regs[r_low]);
+            self.oam_bug(comb, OamBug::Write);
             comb = comb.wrapping_add(1);
             let (comb_high, comb_low) = split_u16(comb);
             self.cpu.regs[r_high] = comb_high;
@@ -657,7 +674,7 @@ impl GameBoyEmulator {
             0x34 => {
                 //INC (HL)
                 let addr = combine_bytes(self.cpu.regs[REG_H], self.cpu.regs[REG_L]);
-                let mut val = self.get_memory(addr, SOURCE);
+                let mut val = self.read_operand(addr);
                 val = val.wrapping_add(1);
                 self.write_memory(addr, val, SOURCE);
                 val
@@ -713,7 +730,7 @@ impl GameBoyEmulator {
                 //DEC (HL)
                 let addr: usize =
                     combine_bytes(self.cpu.regs[REG_H], self.cpu.regs[REG_L]) as usize;
-                let mut val = self.get_memory(addr, SOURCE);
+                let mut val = self.read_operand(addr);
                 val = val.wrapping_sub(1);
                 self.write_memory(addr, val, SOURCE);
                 val
@@ -895,6 +912,7 @@ impl GameBoyEmulator {
             0x2A => {
                 //LD A (HL +)
                 let hl_old: u16 = combine_bytes(self.cpu.regs[REG_H], self.cpu.regs[REG_L]);
+                self.oam_bug(hl_old, OamBug::ReadIncrease);
                 let hl_new = hl_old.wrapping_add(1);
                 let (h_new, l_new) = split_u16(hl_new);
                 self.cpu.regs[REG_L] = l_new;
@@ -904,6 +922,7 @@ impl GameBoyEmulator {
             0x3A => {
                 //LD A (HL -)
                 let hl_old: u16 = combine_bytes(self.cpu.regs[REG_H], self.cpu.regs[REG_L]);
+                self.oam_bug(hl_old, OamBug::ReadIncrease);
                 let hl_new = hl_old.wrapping_sub(1);
                 let (h_new, l_new) = split_u16(hl_new);
                 self.cpu.regs[REG_L] = l_new;
@@ -928,13 +947,18 @@ impl GameBoyEmulator {
                 format!("Unrecognized command {:X} at ld_a_reg_addr!", command)
             ),
         };
-        let new_val = self.get_memory(addr, SOURCE);
+        let new_val = match command {
+            // The read already corrupted OAM together with the increment.
+            0x2A | 0x3A => self.get_memory(addr, SOURCE),
+            _ => self.read_operand(addr),
+        };
         self.cpu.regs[REG_A] = new_val;
         self.cpu.pc += 1;
     }
     fn dec_reg_16(&mut self, command: u8) {
         if command == 0x3B {
             //DEC SP
+            self.oam_bug(self.cpu.sp, OamBug::Write);
             self.cpu.sp = self.cpu.sp.wrapping_sub(1);
         } else {
             let (r_low, r_high) = match command {
@@ -947,6 +971,7 @@ impl GameBoyEmulator {
                 ),
             };
             let mut comb = combine_bytes(self.cpu.regs[r_high], self.cpu.regs[r_low]);
+            self.oam_bug(comb, OamBug::Write);
             comb = comb.wrapping_sub(1);
             let (comb_high, comb_low) = split_u16(comb);
             self.cpu.regs[r_high] = comb_high;
@@ -1064,7 +1089,7 @@ impl GameBoyEmulator {
                 ),
             }
         };
-        let new_val = self.get_memory(addr, SOURCE);
+        let new_val = self.read_operand(addr);
         self.cpu.regs[reg] = new_val;
         self.cpu.pc += 1;
     }
@@ -1088,7 +1113,9 @@ impl GameBoyEmulator {
     }
     fn halt(&mut self, _command: u8) {
         self.cpu.halting = true;
-        if self.get_memory(INT_FLAG_ADDR, SOURCE) & self.get_memory(INT_ENABLE_ADDR, SOURCE) != 0 {
+        if self.get_memory(INT_FLAG_ADDR, SOURCE) & self.get_memory(INT_ENABLE_ADDR, SOURCE) & 0x1F
+            != 0
+        {
             self.cpu.pc += 1;
             self.cpu.halting = false;
             if !self.cpu.ime {
@@ -1109,7 +1136,7 @@ impl GameBoyEmulator {
                 0x5 => self.cpu.regs[REG_L],
                 0x6 => {
                     let addr = combine_bytes(self.cpu.regs[REG_H], self.cpu.regs[REG_L]) as usize;
-                    self.get_memory(addr, SOURCE)
+                    self.read_operand(addr)
                 }
                 0x7 => self.cpu.regs[REG_A],
                 _ => panic!(
@@ -1497,10 +1524,8 @@ impl GameBoyEmulator {
             0x4 => &mut self.cpu.regs[REG_H],
             0x5 => &mut self.cpu.regs[REG_L],
             0x6 => {
-                addr_val_ref = self.get_memory(
-                    combine_bytes(self.cpu.regs[REG_H], self.cpu.regs[REG_L]),
-                    SOURCE,
-                );
+                addr_val_ref =
+                    self.read_operand(combine_bytes(self.cpu.regs[REG_H], self.cpu.regs[REG_L]));
                 mem = true;
                 self.cpu.cycle_modification = 16;
                 &mut addr_val_ref
@@ -1621,3 +1646,66 @@ impl GameBoyEmulator {
         self.cpu.pc += 2;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::booted;
+
+    const OAM_ROW_5: std::ops::Range<usize> = 40..48;
+
+    /// Executes `instruction` from WRAM while the PPU scans OAM row 5, with HL and SP pointing
+    /// into OAM. OAM starts out holding its own indices and is returned afterwards.
+    fn in_mode_2(instruction: &[u8]) -> Vec<u8> {
+        let mut em = booted(&[0xF3, 0x76, 0x18, 0xFD]);
+        while em.mem_unit.ppu_mode != OAM_SEARCH_MODE || em.ppu.cycle_count != 5 * ADVANCE_CYCLES {
+            em.advance();
+        }
+        for i in 0..DMA_LENGTH {
+            em.write_memory(OAM_START_ADDR + i, i as u8, RequestSource::MAU);
+        }
+        for (i, &byte) in instruction.iter().chain(&[HALT_OPCODE]).enumerate() {
+            em.write_memory(WRAM_START_ADDR + i, byte, RequestSource::SPEC);
+        }
+        em.cpu.regs[REG_H] = 0xFE;
+        em.cpu.regs[REG_L] = 0x80;
+        em.cpu.sp = 0xFE80;
+        em.cpu.pc = WRAM_START_ADDR as u16;
+        em.cpu.halting = false;
+        em.advance();
+        (0..DMA_LENGTH)
+            .map(|i| em.get_memory(OAM_START_ADDR + i, RequestSource::MAU))
+            .collect()
+    }
+
+    #[test]
+    fn instructions_trigger_the_oam_bug() {
+        let row_4: Vec<u8> = (32..40).collect();
+        // INC HL, PUSH BC, LD A (HL), INC (HL), ADD A (HL) and BIT 0 (HL). With OAM holding its
+        // indices both the read and the write corruption turn row 5 into a copy of row 4.
+        for instruction in [
+            &[0x23][..],
+            &[0xC5],
+            &[0x7E],
+            &[0x34],
+            &[0x86],
+            &[0xCB, 0x46],
+        ] {
+            assert_eq!(
+                in_mode_2(instruction)[OAM_ROW_5],
+                row_4[..],
+                "{:X?}",
+                instruction
+            );
+        }
+        // LD A (HL +) also copies the merged row 4 over row 3.
+        let oam = in_mode_2(&[0x2A]);
+        assert_eq!(oam[24..32], row_4[..]);
+        assert_eq!(oam[OAM_ROW_5], row_4[..]);
+        // Instructions that don't touch OAM leave it alone.
+        assert_eq!(
+            in_mode_2(&[0x00])[OAM_ROW_5],
+            [40, 41, 42, 43, 44, 45, 46, 47]
+        );
+    }
+}
@@ -2,15 +2,29 @@ use pixels::Pixels;
 use sdl2::video::Window;
 
 use crate::apu::AudioProcessingUnit;
+use crate::bootroms::BootRoms;
+use crate::cheats::Cheats;
 use crate::constants::*;
 use crate::cpu::CentralProcessingUnit;
 use crate::epu::EventProcessingUnit;
+use crate::filter::Filter;
+use crate::layers::DebugLayers;
 use crate::memory::MemoryUnit;
+use crate::model::Model;
+use crate::palette::{ColorCorrection, DmgPalette, PALETTE_PRESETS};
 use crate::ppu::PictureProcessingUnit;
+use crate::recorder::Recorder;
+use crate::search::RamSearch;
+use crate::serial::{Disconnected, SerialDevice, SerialPort};
+use crate::sgb::Sgb;
 use crate::timing::Timer;
+use crate::viewer::DebugViewer;
+use crate::watch::MemoryWatch;
+use std::cell::RefCell;
+use std::path::PathBuf;
 use std::time::{Duration, Instant};
 
-#[derive(PartialEq, Debug)]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum RequestSource {
     APU,
     CPU,
@@ -18,6 +32,7 @@ pub enum RequestSource {
     MAU,
     PPU,
     SPEC,
+    Serial,
     Timer,
 }
 pub struct GameBoyEmulator {
@@ -27,12 +42,41 @@ pub struct GameBoyEmulator {
     pub epu: EventProcessingUnit,
     pub apu: AudioProcessingUnit,
     pub timer: Timer,
-    pub sdl_context: sdl2::Sdl,
+    pub serial: SerialPort,
+    pub serial_device: Box<dyn SerialDevice>,
+    pub sdl_context: Option<sdl2::Sdl>,
     pub double_speed: bool,
     pub cgb: bool,
+    pub model: Model,
+    pub hardware: Model,
+    pub dmg_compat: bool,
     pub running: bool,
-    _window: Window,
-    pub pixels: Pixels,
+    pub paused: bool,
+    pub window: Option<Window>,
+    pub pixels: Option<Pixels>,
+    pub frame: Vec<u8>,
+    /// A copy of `frame` from the last time the PPU finished one.
+    pub finished_frame: Vec<u8>,
+    pub frame_ready: bool,
+    pub layers: DebugLayers,
+    pub filter: Filter,
+    pub frame_blending: bool,
+    pub previous_frame: Vec<u8>,
+    display_size: (usize, usize),
+    pub dmg_palette: DmgPalette,
+    pub palettes: Vec<(String, DmgPalette)>,
+    pub palette_index: usize,
+    pub color_correction: ColorCorrection,
+    pub color_table: Vec<[u8; 4]>,
+    pub viewers: Vec<DebugViewer>,
+    pub screenshot_dir: PathBuf,
+    pub recorder: Option<Recorder>,
+    pub recording_dir: PathBuf,
+    pub watch: Option<RefCell<MemoryWatch>>,
+    pub cheats: Cheats,
+    pub ram_search: Option<RamSearch>,
+    pub boot_roms: BootRoms,
+    pub sgb: Option<Sgb>,
     pub iteration_count: usize,
 }
 
@@ -61,43 +105,175 @@ impl GameBoyEmulator {
             pixels::Pixels::new(WINDOW_WIDTH as u32, WINDOW_HEIGHT as u32, surface_texture)
                 .unwrap();
         let event_pump = sdl_context.event_pump().unwrap();
+        let mut emulator = GameBoyEmulator::new_headless();
+        emulator.epu = EventProcessingUnit::new(Some(event_pump));
+        emulator.apu = AudioProcessingUnit::new(Some(audio_subsystem));
+        emulator.sdl_context = Some(sdl_context);
+        emulator.window = Some(window);
+        emulator.pixels = Some(pixels);
+        emulator
+    }
+    pub fn new_headless() -> GameBoyEmulator {
         GameBoyEmulator {
             cpu: CentralProcessingUnit::new(),
             mem_unit: MemoryUnit::new(),
             ppu: PictureProcessingUnit::new(),
-            epu: EventProcessingUnit::new(event_pump),
+            epu: EventProcessingUnit::new(None),
             timer: Timer::new(),
-            sdl_context,
-            apu: AudioProcessingUnit::new(audio_subsystem),
+            serial: SerialPort::new(),
+            serial_device: Box::new(Disconnected),
+            sdl_context: None,
+            apu: AudioProcessingUnit::new(None),
             double_speed: false,
             cgb: false,
+            model: Model::Auto,
+            hardware: Model::Dmg,
+            dmg_compat: false,
             running: true,
-            _window: window,
-            pixels,
+            paused: false,
+            window: None,
+            pixels: None,
+            frame: vec![0; WINDOW_WIDTH * WINDOW_HEIGHT * PIXEL_LENGTH],
+            finished_frame: vec![0; WINDOW_WIDTH * WINDOW_HEIGHT * PIXEL_LENGTH],
+            frame_ready: false,
+            layers: DebugLayers::new(),
+            filter: Filter::None,
+            frame_blending: false,
+            previous_frame: vec![0; WINDOW_WIDTH * WINDOW_HEIGHT * PIXEL_LENGTH],
+            display_size: (WINDOW_WIDTH, WINDOW_HEIGHT),
+            dmg_palette: PALETTE_PRESETS[0].1,
+            palettes: PALETTE_PRESETS
+                .iter()
+                .map(|(name, palette)| (name.to_string(), *palette))
+                .collect(),
+            palette_index: 0,
+            color_correction: ColorCorrection::None,
+            color_table: ColorCorrection::None.color_table(),
+            viewers: Vec::new(),
+            screenshot_dir: PathBuf::from("screenshots"),
+            recorder: None,
+            recording_dir: PathBuf::from("recordings"),
+            watch: None,
+            cheats: Cheats::new(),
+            ram_search: None,
+            boot_roms: BootRoms::new(),
+            sgb: None,
             iteration_count: 0,
         }
     }
+    pub fn present_frame(&mut self) {
+        self.frame_ready = true;
+        self.finished_frame.copy_from_slice(&self.frame);
+        if self.pixels.is_some() {
+            let (display, width, height) = self.display_frame();
+            let pixels = self.pixels.as_mut().unwrap();
+            if (width, height) != self.display_size {
+                pixels.resize_buffer(width as u32, height as u32);
+                self.display_size = (width, height);
+            }
+            pixels.get_frame().copy_from_slice(&display);
+            pixels.render().unwrap();
+        }
+        self.update_viewers();
+        self.record_frame();
+    }
+    pub fn advance(&mut self) {
+        self.serial_device.advance();
+        self.cpu_advance();
+        self.timer_advance();
+        self.serial_advance();
+        self.dma_tick();
+        if self.double_speed {
+            self.cpu_advance();
+            self.timer_advance();
+            self.serial_advance();
+            self.dma_tick();
+        }
+        self.apu_advance();
+        self.ppu_advance();
+        self.iteration_count += 1;
+    }
+    #[cfg(test)]
+    pub fn run_headless(&mut self, advances: u32) {
+        for _ in 0..advances {
+            self.advance();
+        }
+    }
     pub fn run(&mut self) {
         let work_period = Duration::new(0, PERIOD_NS);
         while self.running {
             let now = Instant::now();
 
             self.buffer_check();
-            for _ in 0..ADVANCES_PER_PERIOD {
-                self.cpu_advance();
-                self.timer_advance();
-                self.dma_tick();
-                if self.double_speed {
-                    self.cpu_advance();
-                    self.timer_advance();
-                    self.dma_tick();
+            if !self.paused {
+                for _ in 0..ADVANCES_PER_PERIOD {
+                    self.advance();
                 }
-                self.apu_advance();
-                self.ppu_advance();
-                self.iteration_count += 1;
             }
             self.event_check();
             while now.elapsed() < work_period {}
         }
+        self.stop_recording();
+        self.stop_watch();
     }
 }
+
+/// Where `test_rom` puts its program.
+#[cfg(test)]
+pub const TEST_PROGRAM_ADDR: u16 = 0x150;
+
+/// Builds a 32KB ROM-only cartridge that passes the boot ROM checks and jumps to `program`
+/// at 0x150.
+#[cfg(test)]
+pub fn test_rom(program: &[u8]) -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[0x100..0x104].copy_from_slice(&[0x00, 0xC3, 0x50, 0x01]);
+    rom[0x104..0x134].copy_from_slice(&crate::bootroms::DMG_BOOTROM[0xA8..0xD8]);
+    let start = TEST_PROGRAM_ADDR as usize;
+    rom[start..start + program.len()].copy_from_slice(program);
+    set_header_checksum(&mut rom);
+    rom
+}
+
+/// Recomputes the header checksum the boot ROM checks, after a test has changed the header.
+#[cfg(test)]
+pub fn set_header_checksum(rom: &mut [u8]) {
+    let mut checksum: u8 = 0;
+    for byte in rom[ROM_TITLE_START_ADDR..HEADER_CHECKSUM_ADDR].iter() {
+        checksum = checksum.wrapping_sub(*byte).wrapping_sub(1);
+    }
+    rom[HEADER_CHECKSUM_ADDR] = checksum;
+}
+
+/// Runs until the boot ROM has finished and the CPU has started the test program.
+#[cfg(test)]
+pub fn run_to_program(em: &mut GameBoyEmulator) {
+    while em.cpu.get_pc() != TEST_PROGRAM_ADDR {
+        em.advance();
+    }
+}
+
+/// Loads `rom` with the boot ROM skipped, and runs until its program starts.
+#[cfg(test)]
+pub fn booted_rom(rom: Vec<u8>) -> GameBoyEmulator {
+    let mut em = GameBoyEmulator::new_headless();
+    em.boot_roms.skip = true;
+    em.load_rom_data(rom);
+    run_to_program(&mut em);
+    em
+}
+
+/// Starts `program` on a DMG cartridge without running the boot ROM.
+#[cfg(test)]
+pub fn booted(program: &[u8]) -> GameBoyEmulator {
+    booted_rom(test_rom(program))
+}
+
+/// Starts `program` on a cartridge that supports the CGB, without running the boot ROM.
+#[cfg(test)]
+pub fn booted_cgb(program: &[u8]) -> GameBoyEmulator {
+    let mut rom = test_rom(program);
+    rom[CGB_FLAG_ADDR] = 0x80;
+    set_header_checksum(&mut rom);
+    booted_rom(rom)
+}
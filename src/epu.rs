@@ -1,59 +1,120 @@
 use crate::constants::*;
 use crate::emulator::GameBoyEmulator;
 use crate::emulator::RequestSource;
+use crate::search::{Interpretation, SearchFilter};
 use sdl2::event::Event;
 use sdl2::event::WindowEvent;
-use sdl2::keyboard::Scancode;
+use sdl2::keyboard::{KeyboardState, Scancode};
 use sdl2::EventPump;
+use std::io::BufRead;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
 
 const SOURCE: RequestSource = RequestSource::EPU;
+
+pub struct KeyLayout {
+    pub a: Scancode,
+    pub b: Scancode,
+    pub select: Scancode,
+    pub start: Scancode,
+    pub right: Scancode,
+    pub left: Scancode,
+    pub up: Scancode,
+    pub down: Scancode,
+}
+
+pub const PLAYER_1_KEYS: KeyLayout = KeyLayout {
+    a: Scancode::Z,
+    b: Scancode::X,
+    select: Scancode::S,
+    start: Scancode::A,
+    right: Scancode::Right,
+    left: Scancode::Left,
+    up: Scancode::Up,
+    down: Scancode::Down,
+};
+
+pub const PLAYER_2_KEYS: KeyLayout = KeyLayout {
+    a: Scancode::N,
+    b: Scancode::M,
+    select: Scancode::P,
+    start: Scancode::O,
+    right: Scancode::L,
+    left: Scancode::J,
+    up: Scancode::I,
+    down: Scancode::K,
+};
+
+/// Returns the (directional, action) nibbles for P1, with pressed buttons as 0 bits.
+pub fn read_joypad(state: &KeyboardState, keys: &KeyLayout) -> (u8, u8) {
+    let mut directional_presses = 0xF;
+    let mut action_presses = 0xF;
+    for code in state.pressed_scancodes() {
+        if code == keys.a {
+            action_presses &= 0b1110;
+        } else if code == keys.b {
+            action_presses &= 0b1101;
+        } else if code == keys.select {
+            action_presses &= 0b1011;
+        } else if code == keys.start {
+            action_presses &= 0b0111;
+        } else if code == keys.right {
+            directional_presses &= 0b1110;
+        } else if code == keys.left {
+            directional_presses &= 0b1101;
+        } else if code == keys.up {
+            directional_presses &= 0b1011;
+        } else if code == keys.down {
+            directional_presses &= 0b0111;
+        }
+    }
+    (directional_presses, action_presses)
+}
 pub struct EventProcessingUnit {
     new_directional_presses: u8,
     new_action_presses: u8,
-    event_pump: EventPump,
+    event_pump: Option<EventPump>,
+    console: Option<Receiver<String>>,
+}
+
+/// Reads console commands from stdin on a separate thread, so the emulator never waits on
+/// them.
+fn spawn_console() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            match line {
+                Ok(line) => {
+                    if sender.send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+    receiver
 }
 
 impl EventProcessingUnit {
-    pub fn new(event_pump: EventPump) -> EventProcessingUnit {
+    pub fn new(event_pump: Option<EventPump>) -> EventProcessingUnit {
         let new_directional_presses = 0xF;
         let new_action_presses = 0xF;
+        let console = event_pump.as_ref().map(|_| spawn_console());
         EventProcessingUnit {
             new_directional_presses,
             new_action_presses,
             event_pump,
+            console,
         }
     }
 }
 impl GameBoyEmulator {
-    pub fn event_check(&mut self) {
-        self.epu.new_directional_presses = 0xF;
-        self.epu.new_action_presses = 0xF;
-        let state = self.epu.event_pump.keyboard_state();
-        let mut save = false;
-        let mut open = false;
-        self.cpu.debug_action = false;
-        for code in state.pressed_scancodes() {
-            match code {
-                Scancode::Z => self.epu.new_action_presses &= 0b1110,
-                Scancode::X => self.epu.new_action_presses &= 0b1101,
-                Scancode::S => self.epu.new_action_presses &= 0b1011,
-                Scancode::A => self.epu.new_action_presses &= 0b0111,
-                Scancode::Right => self.epu.new_directional_presses &= 0b1110,
-                Scancode::Left => self.epu.new_directional_presses &= 0b1101,
-                Scancode::Up => self.epu.new_directional_presses &= 0b1011,
-                Scancode::Down => self.epu.new_directional_presses &= 0b0111,
-                Scancode::Num1 => save = true,
-                Scancode::Num2 => open = true,
-                Scancode::Q => self.cpu.debug_action = true,
-                _ => {}
-            }
-        }
-        self.mem_unit.directional_presses = self.epu.new_directional_presses;
-        self.mem_unit.action_presses = self.epu.new_action_presses;
+    pub fn set_joypad(&mut self, directional_presses: u8, action_presses: u8) {
         let mut p1 = self.get_memory(P1_ADDR, SOURCE);
         let prev_p1 = p1;
-        self.mem_unit.directional_presses = self.epu.new_directional_presses;
-        self.mem_unit.action_presses = self.epu.new_action_presses;
+        self.mem_unit.directional_presses = directional_presses;
+        self.mem_unit.action_presses = action_presses;
         let p14 = (p1 >> 4) & 1;
         let p15 = (p1 >> 5) & 1;
         let mut new_bits = 0xF;
@@ -61,10 +122,10 @@ impl GameBoyEmulator {
         p1 &= 0b110000;
 
         if p14 == 0 {
-            new_bits &= self.epu.new_directional_presses;
+            new_bits &= directional_presses;
         }
         if p15 == 0 {
-            new_bits &= self.epu.new_action_presses;
+            new_bits &= action_presses;
         }
         p1 += new_bits;
         if ((prev_p1 | p1) - p1) & 0xF != 0 {
@@ -75,23 +136,249 @@ impl GameBoyEmulator {
             );
         }
         self.write_memory(P1_ADDR, p1, SOURCE);
+    }
+    pub fn event_check(&mut self) {
+        let state = match &self.epu.event_pump {
+            Some(event_pump) => event_pump.keyboard_state(),
+            None => return,
+        };
+        let joypads = [
+            read_joypad(&state, &PLAYER_1_KEYS),
+            read_joypad(&state, &PLAYER_2_KEYS),
+        ];
+        let (directional_presses, action_presses) = match self.sgb.as_mut() {
+            Some(sgb) => sgb.select_joypad(joypads),
+            None => joypads[0],
+        };
+        self.epu.new_directional_presses = directional_presses;
+        self.epu.new_action_presses = action_presses;
+        let mut save = false;
+        let mut open = false;
+        self.cpu.debug_action = false;
+        for code in state.pressed_scancodes() {
+            match code {
+                Scancode::Num1 => save = true,
+                Scancode::Num2 => open = true,
+                Scancode::Q => self.cpu.debug_action = true,
+                _ => {}
+            }
+        }
+        self.set_joypad(
+            self.epu.new_directional_presses,
+            self.epu.new_action_presses,
+        );
 
-        for event in self.epu.event_pump.poll_iter() {
+        let mut next_palette = false;
+        let mut next_color_correction = false;
+        let mut toggle_viewers = false;
+        let mut screenshot = None;
+        let mut recording = None;
+        let mut next_filter = false;
+        let mut toggled_layer = None;
+        let mut toggle_frame_blending = false;
+        let mut next_cheat = false;
+        let mut toggle_cheat = false;
+        let mut ram_search = None;
+        let main_window_id = self.window.as_ref().map_or(0, |window| window.id());
+        let event_pump = self.epu.event_pump.as_mut().unwrap();
+        for event in event_pump.poll_iter() {
             match event {
                 Event::Quit { .. }
                 | Event::KeyDown {
                     scancode: Some(Scancode::Escape),
                     ..
                 } => self.running = false,
+                Event::KeyDown {
+                    scancode: Some(Scancode::F),
+                    repeat: false,
+                    ..
+                } => self.ppu.fifo_renderer = !self.ppu.fifo_renderer,
+                Event::KeyDown {
+                    scancode: Some(Scancode::Num3),
+                    repeat: false,
+                    ..
+                } => next_palette = true,
+                Event::KeyDown {
+                    scancode: Some(Scancode::Num4),
+                    repeat: false,
+                    ..
+                } => next_color_correction = true,
+                Event::KeyDown {
+                    scancode: Some(Scancode::Num5),
+                    repeat: false,
+                    ..
+                } => screenshot = Some(false),
+                Event::KeyDown {
+                    scancode: Some(Scancode::Num6),
+                    repeat: false,
+                    ..
+                } => screenshot = Some(true),
+                Event::KeyDown {
+                    scancode: Some(Scancode::Num7),
+                    repeat: false,
+                    ..
+                } => recording = Some("avi"),
+                Event::KeyDown {
+                    scancode: Some(Scancode::Num8),
+                    repeat: false,
+                    ..
+                } => recording = Some("gif"),
+                Event::KeyDown {
+                    scancode: Some(Scancode::Num9),
+                    repeat: false,
+                    ..
+                } => next_filter = true,
+                Event::KeyDown {
+                    scancode: Some(Scancode::Num0),
+                    repeat: false,
+                    ..
+                } => toggle_frame_blending = true,
+                Event::KeyDown {
+                    scancode: Some(Scancode::F2),
+                    repeat: false,
+                    ..
+                } => toggled_layer = Some("bg"),
+                Event::KeyDown {
+                    scancode: Some(Scancode::F3),
+                    repeat: false,
+                    ..
+                } => toggled_layer = Some("window"),
+                Event::KeyDown {
+                    scancode: Some(Scancode::F4),
+                    repeat: false,
+                    ..
+                } => toggled_layer = Some("objs"),
+                Event::KeyDown {
+                    scancode: Some(Scancode::F5),
+                    repeat: false,
+                    ..
+                } => toggled_layer = Some("obj boxes"),
+                Event::KeyDown {
+                    scancode: Some(Scancode::F6),
+                    repeat: false,
+                    ..
+                } => next_cheat = true,
+                Event::KeyDown {
+                    scancode: Some(Scancode::F7),
+                    repeat: false,
+                    ..
+                } => toggle_cheat = true,
+                Event::KeyDown {
+                    scancode: Some(Scancode::F8),
+                    repeat: false,
+                    ..
+                } => ram_search = Some(None),
+                Event::KeyDown {
+                    scancode: Some(Scancode::F9),
+                    repeat: false,
+                    ..
+                } => ram_search = Some(Some(SearchFilter::Changed)),
+                Event::KeyDown {
+                    scancode: Some(Scancode::F10),
+                    repeat: false,
+                    ..
+                } => ram_search = Some(Some(SearchFilter::Equal)),
+                Event::KeyDown {
+                    scancode: Some(Scancode::F11),
+                    repeat: false,
+                    ..
+                } => ram_search = Some(Some(SearchFilter::Increased)),
+                Event::KeyDown {
+                    scancode: Some(Scancode::F12),
+                    repeat: false,
+                    ..
+                } => ram_search = Some(Some(SearchFilter::Decreased)),
+                Event::KeyDown {
+                    scancode: Some(Scancode::Space),
+                    repeat: false,
+                    ..
+                } => self.paused = !self.paused,
+                Event::KeyDown {
+                    scancode: Some(Scancode::F1),
+                    repeat: false,
+                    ..
+                } => toggle_viewers = true,
+                Event::Window {
+                    window_id,
+                    win_event,
+                    ..
+                } if window_id != main_window_id => match win_event {
+                    WindowEvent::Resized(width, height) => {
+                        for viewer in self.viewers.iter_mut() {
+                            if viewer.window.id() == window_id {
+                                viewer.pixels.resize_surface(width as u32, height as u32);
+                            }
+                        }
+                    }
+                    WindowEvent::Close => self.viewers.retain(|v| v.window.id() != window_id),
+                    _ => {}
+                },
+                Event::Window {
+                    win_event: WindowEvent::Close,
+                    ..
+                } => self.running = false,
                 Event::Window {
                     win_event: WindowEvent::Resized(width, height),
                     ..
                 } => {
-                    self.pixels.resize_surface(width as u32, height as u32);
+                    if let Some(pixels) = self.pixels.as_mut() {
+                        pixels.resize_surface(width as u32, height as u32);
+                    }
                 }
                 _ => {}
             }
         }
+        if next_palette {
+            self.next_palette();
+        }
+        if next_color_correction {
+            self.next_color_correction();
+        }
+        if toggle_viewers {
+            self.toggle_viewers();
+        }
+        if let Some(window_scale) = screenshot {
+            let scale = if window_scale { self.window_scale() } else { 1 };
+            self.take_screenshot(scale);
+        }
+        if next_filter {
+            self.next_filter();
+        }
+        if let Some(layer) = toggled_layer {
+            self.toggle_layer(layer);
+        }
+        if toggle_frame_blending {
+            self.toggle_frame_blending();
+        }
+        if next_cheat {
+            self.select_next_cheat();
+        }
+        if toggle_cheat {
+            self.toggle_selected_cheat();
+        }
+        match ram_search {
+            Some(None) => {
+                self.start_ram_search(Interpretation::U8);
+                self.print_ram_search();
+            }
+            Some(Some(filter)) => {
+                self.filter_ram_search(filter);
+                self.print_ram_search();
+            }
+            None => {}
+        }
+        let commands: Vec<String> = match &self.epu.console {
+            Some(console) => console.try_iter().collect(),
+            None => Vec::new(),
+        };
+        for command in commands {
+            if let Err(e) = self.ram_search_command(&command) {
+                eprintln!("{}", e);
+            }
+        }
+        if let Some(extension) = recording {
+            self.toggle_recording(extension);
+        }
         if save {
             let save_file = rfd::Dialog::save_file().open();
             if save_file.len() == 1 {
@@ -0,0 +1,472 @@
+use crate::constants::*;
+use crate::emulator::{GameBoyEmulator, RequestSource};
+use crate::ppu::convert_to_index;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+
+const SOURCE: RequestSource = RequestSource::PPU;
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct BgPixel {
+    color: u8,
+    palette: u8,
+    priority: bool,
+    window: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+struct ObjPixel {
+    color: u8,
+    palette: u8,
+    bg_over_obj: bool,
+    oam_order: u8,
+}
+
+/// State of the dot-accurate renderer used during mode 3.
+///
+/// The fetcher spends 2 dots each on the tile number, the low and the high byte of the tile
+/// row, then pushes 8 pixels once the background FIFO is empty. One pixel is shifted out
+/// per dot, mixed with the sprite FIFO and drawn using the registers as they are at that
+/// dot. Reaching a sprite's X position stops the shifter while the sprite row is fetched.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PixelFifo {
+    bg_fifo: VecDeque<BgPixel>,
+    obj_fifo: VecDeque<ObjPixel>,
+    fetcher_step: u8,
+    fetcher_x: u8,
+    fetching_window: bool,
+    window_eligible: bool,
+    tile_number: u8,
+    tile_attributes: u8,
+    tile_low: u8,
+    tile_high: u8,
+    startup_dots: u8,
+    discard: u8,
+    lcd_x: usize,
+    sprites_fetched: [bool; MAX_SPRITES_PER_ROW],
+    sprite_fetch: Option<usize>,
+    sprite_fetch_dots: u8,
+}
+
+impl PixelFifo {
+    pub fn new() -> PixelFifo {
+        PixelFifo {
+            bg_fifo: VecDeque::with_capacity(16),
+            obj_fifo: VecDeque::with_capacity(8),
+            fetcher_step: 0,
+            fetcher_x: 0,
+            fetching_window: false,
+            window_eligible: false,
+            tile_number: 0,
+            tile_attributes: 0,
+            tile_low: 0,
+            tile_high: 0,
+            startup_dots: 0,
+            discard: 0,
+            lcd_x: 0,
+            sprites_fetched: [false; MAX_SPRITES_PER_ROW],
+            sprite_fetch: None,
+            sprite_fetch_dots: 0,
+        }
+    }
+}
+
+impl GameBoyEmulator {
+    pub fn fifo_drawing(&mut self) {
+        if self.ppu.starting {
+            self.fifo_initialize();
+        }
+        for _ in 0..ADVANCE_CYCLES {
+            if self.ppu.fifo.lcd_x < WINDOW_WIDTH {
+                self.fifo_dot();
+            }
+        }
+        self.ppu.cycle_count += ADVANCE_CYCLES;
+        if self.ppu.fifo.lcd_x == WINDOW_WIDTH {
+            // However long mode 3 ran, HBlank takes up the rest of the line.
+            let drawn_dots = OAM_SCAN_DOTS + self.ppu.cycle_count;
+            debug_assert!(drawn_dots + 2 * ADVANCE_CYCLES <= ROW_DOTS);
+            self.ppu.hblank_dots = ROW_DOTS - drawn_dots;
+            self.ppu.cycle_count = 0;
+            self.ppu.starting = true;
+            self.set_mode(HBLANK_MODE);
+        }
+    }
+    fn fifo_initialize(&mut self) {
+        let row = self.get_memory(LY_ADDR, SOURCE) as usize;
+        let wy = self.get_memory(WY_ADDR, SOURCE) as usize;
+        let scx = self.get_memory(SCX_ADDR, SOURCE);
+        let fifo = &mut self.ppu.fifo;
+        fifo.bg_fifo.clear();
+        fifo.obj_fifo.clear();
+        fifo.fetcher_step = 0;
+        fifo.fetcher_x = 0;
+        fifo.fetching_window = false;
+        fifo.window_eligible = wy <= row;
+        fifo.startup_dots = FIFO_STARTUP_DOTS;
+        fifo.discard = scx % TILE_WIDTH as u8;
+        fifo.lcd_x = 0;
+        fifo.sprites_fetched = [false; MAX_SPRITES_PER_ROW];
+        fifo.sprite_fetch = None;
+        fifo.sprite_fetch_dots = 0;
+        self.ppu.window_row_activated = false;
+        self.ppu.starting = false;
+    }
+    fn fifo_dot(&mut self) {
+        if self.ppu.fifo.startup_dots > 0 {
+            self.ppu.fifo.startup_dots -= 1;
+            return;
+        }
+        if let Some(sprite_index) = self.ppu.fifo.sprite_fetch {
+            if self.ppu.fifo.bg_fifo.is_empty() {
+                self.fetcher_dot();
+            } else {
+                self.ppu.fifo.sprite_fetch_dots += 1;
+                if self.ppu.fifo.sprite_fetch_dots == FIFO_SPRITE_FETCH_DOTS {
+                    self.fetch_sprite(sprite_index);
+                    self.ppu.fifo.sprite_fetch = None;
+                }
+            }
+            return;
+        }
+        self.fetcher_dot();
+        if self.ppu.fifo.bg_fifo.is_empty() {
+            return;
+        }
+        if self.ppu.fifo.discard > 0 {
+            self.ppu.fifo.bg_fifo.pop_front();
+            self.ppu.fifo.discard -= 1;
+            return;
+        }
+        if self.window_check() {
+            return;
+        }
+        if self.get_sprite_enable_flag() == 1 {
+            if let Some(sprite_index) = self.sprite_at_x() {
+                self.ppu.fifo.sprites_fetched[sprite_index] = true;
+                self.ppu.fifo.sprite_fetch = Some(sprite_index);
+                self.ppu.fifo.sprite_fetch_dots = 0;
+                return;
+            }
+        }
+        self.shift_pixel();
+    }
+    /// Restarts the fetcher on the window once the shifter reaches WX.
+    fn window_check(&mut self) -> bool {
+        let fifo = &self.ppu.fifo;
+        if fifo.fetching_window || !fifo.window_eligible || self.get_win_enable_flag() == 0 {
+            return false;
+        }
+        let wx = self.get_memory(WX_ADDR, SOURCE) as usize;
+        if wx > WINDOW_WIDTH + 6 || fifo.lcd_x + 7 < wx {
+            return false;
+        }
+        let fifo = &mut self.ppu.fifo;
+        fifo.bg_fifo.clear();
+        fifo.fetcher_step = 0;
+        fifo.fetcher_x = 0;
+        fifo.fetching_window = true;
+        self.ppu.window_row_activated = true;
+        true
+    }
+    fn sprite_at_x(&self) -> Option<usize> {
+        let lcd_x = self.ppu.fifo.lcd_x;
+        (0..self.ppu.sprite_num).find(|&i| {
+            let x = self.ppu.possible_sprites[i][OAM_X_INDEX] as usize;
+            !self.ppu.fifo.sprites_fetched[i]
+                && x > 0
+                && (x == lcd_x + TILE_WIDTH || (lcd_x == 0 && x < TILE_WIDTH))
+        })
+    }
+    fn fetcher_dot(&mut self) {
+        match self.ppu.fifo.fetcher_step {
+            1 => self.fetch_tile_number(),
+            3 => self.ppu.fifo.tile_low = self.fetch_tile_data(0),
+            5 => self.ppu.fifo.tile_high = self.fetch_tile_data(1),
+            6 => {
+                if self.ppu.fifo.bg_fifo.is_empty() {
+                    self.push_tile();
+                    self.ppu.fifo.fetcher_x = self.ppu.fifo.fetcher_x.wrapping_add(1);
+                    self.ppu.fifo.fetcher_step = 0;
+                }
+                return;
+            }
+            _ => {}
+        }
+        self.ppu.fifo.fetcher_step += 1;
+    }
+    fn fetch_tile_number(&mut self) {
+        let row = self.get_memory(LY_ADDR, SOURCE) as usize;
+        let (tilemap_start_addr, tile_x, tile_y) = if self.ppu.fifo.fetching_window {
+            let tilemap_start_addr = if self.get_win_tile_map_flag() == 0 {
+                TILE_MAP_1_START_ADDR
+            } else {
+                TILE_MAP_2_START_ADDR
+            };
+            (
+                tilemap_start_addr,
+                self.ppu.fifo.fetcher_x as usize,
+                self.ppu.current_window_row / BG_TILE_HEIGHT,
+            )
+        } else {
+            let tilemap_start_addr = if self.get_bg_tile_map_flag() == 0 {
+                TILE_MAP_1_START_ADDR
+            } else {
+                TILE_MAP_2_START_ADDR
+            };
+            let scx = self.get_memory(SCX_ADDR, SOURCE) as usize;
+            let scy = self.get_memory(SCY_ADDR, SOURCE) as usize;
+            (
+                tilemap_start_addr,
+                scx / TILE_WIDTH + self.ppu.fifo.fetcher_x as usize,
+                ((scy + row) % BG_MAP_SIZE_PX) / BG_TILE_HEIGHT,
+            )
+        };
+        let tile_map_addr = tilemap_start_addr + TILES_PER_ROW * tile_y + tile_x % TILES_PER_ROW;
+        self.ppu.fifo.tile_number = self.access_vram(tile_map_addr, 0);
+        self.ppu.fifo.tile_attributes = if self.cgb {
+            self.access_vram(tile_map_addr, 1)
+        } else {
+            0
+        };
+    }
+    fn fetch_tile_data(&mut self, byte: usize) -> u8 {
+        let row = self.get_memory(LY_ADDR, SOURCE) as usize;
+        let mut row_within_tile = if self.ppu.fifo.fetching_window {
+            self.ppu.current_window_row % BG_TILE_HEIGHT
+        } else {
+            let scy = self.get_memory(SCY_ADDR, SOURCE) as usize;
+            ((scy + row) % BG_MAP_SIZE_PX) % BG_TILE_HEIGHT
+        };
+        let attributes = self.ppu.fifo.tile_attributes;
+        if ((attributes >> 6) & 1) == 1 {
+            row_within_tile = 7 - row_within_tile;
+        }
+        let tile_index = self.ppu.fifo.tile_number as usize;
+        let absolute_tile_data_index =
+            if self.get_tile_data_flag() == 0 && tile_index < VRAM_BLOCK_SIZE {
+                tile_index + 2 * VRAM_BLOCK_SIZE
+            } else {
+                tile_index
+            };
+        let tile_data_addr = VRAM_START_ADDR
+            + absolute_tile_data_index * BYTES_PER_TILE
+            + row_within_tile * BYTES_PER_TILE_ROW
+            + byte;
+        self.access_vram(tile_data_addr, (attributes >> 3) & 1)
+    }
+    fn push_tile(&mut self) {
+        let fifo = &mut self.ppu.fifo;
+        let attributes = fifo.tile_attributes;
+        let horizontal_flip = ((attributes >> 5) & 1) == 1;
+        for i in 0..TILE_WIDTH {
+            let bit = if horizontal_flip {
+                i
+            } else {
+                TILE_WIDTH - 1 - i
+            };
+            fifo.bg_fifo.push_back(BgPixel {
+                color: (((fifo.tile_high >> bit) & 1) << 1) | ((fifo.tile_low >> bit) & 1),
+                palette: attributes & 0b111,
+                priority: (attributes >> 7) == 1,
+                window: fifo.fetching_window,
+            });
+        }
+    }
+    fn fetch_sprite(&mut self, sprite_index: usize) {
+        let row = self.get_memory(LY_ADDR, SOURCE) as usize;
+        let obj_length = self.get_obj_size();
+        let sprite = self.ppu.possible_sprites[sprite_index];
+        let attributes = sprite[OAM_ATTRIBUTE_INDEX];
+        let x_flip = ((attributes >> 5) & 1) == 1;
+        let y_flip = ((attributes >> 6) & 1) == 1;
+        let row_within = if y_flip {
+            (obj_length - 1 + sprite[OAM_Y_INDEX]) as usize - row - 16
+        } else {
+            (row + 16) - sprite[OAM_Y_INDEX] as usize
+        };
+        let tile_map_index = if obj_length == 16 {
+            if row_within >= 8 {
+                sprite[OAM_TILE_INDEX] | 0x01
+            } else {
+                sprite[OAM_TILE_INDEX] & 0xFE
+            }
+        } else {
+            sprite[OAM_TILE_INDEX]
+        };
+        let tile_data_index = tile_map_index as usize * BYTES_PER_TILE
+            + (row_within % TILE_WIDTH) * BYTES_PER_TILE_ROW;
+        let (palette, bank_number) = if self.cgb {
+            (attributes & 0b111, (attributes >> 3) & 1)
+        } else {
+            ((attributes >> 4) & 1, 0)
+        };
+        let low = self.access_vram(VRAM_START_ADDR + tile_data_index, bank_number);
+        let high = self.access_vram(VRAM_START_ADDR + tile_data_index + 1, bank_number);
+
+        let x = sprite[OAM_X_INDEX] as usize;
+        let hidden = TILE_WIDTH.saturating_sub(x);
+        let cgb = self.cgb;
+        let fifo = &mut self.ppu.fifo;
+        for i in hidden..TILE_WIDTH {
+            let bit = if x_flip { i } else { TILE_WIDTH - 1 - i };
+            let pixel = ObjPixel {
+                color: (((high >> bit) & 1) << 1) | ((low >> bit) & 1),
+                palette,
+                bg_over_obj: (attributes >> 7) == 1,
+                oam_order: sprite_index as u8,
+            };
+            let slot = i - hidden;
+            if slot >= fifo.obj_fifo.len() {
+                fifo.obj_fifo.push_back(pixel);
+            } else {
+                let current = fifo.obj_fifo[slot];
+                let replace = current.color == 0
+                    || (cgb && pixel.color != 0 && pixel.oam_order < current.oam_order);
+                if replace {
+                    fifo.obj_fifo[slot] = pixel;
+                }
+            }
+        }
+    }
+    fn shift_pixel(&mut self) {
+        let row = self.get_memory(LY_ADDR, SOURCE) as usize;
+        let bg = self.ppu.fifo.bg_fifo.pop_front().unwrap();
+        let obj = self.ppu.fifo.obj_fifo.pop_front();
+        let bg_win_enable = self.get_bg_window_enable() == 1;
+        let bg_color = if bg_win_enable || self.cgb {
+            bg.color
+        } else {
+            0
+        };
+        let obj_visible = match obj {
+            Some(obj) if obj.color != 0 && self.get_sprite_enable_flag() == 1 => {
+                if self.cgb && !bg_win_enable {
+                    true
+                } else {
+                    bg_color == 0 || !(obj.bg_over_obj || (self.cgb && bg.priority))
+                }
+            }
+            _ => false,
+        };
+        let obj_visible = obj_visible && self.layers.objs;
+        let layer_shown = if bg.window {
+            self.layers.window
+        } else {
+            self.layers.bg
+        };
+        let bg_color = if layer_shown { bg_color } else { 0 };
+        let color = if obj_visible {
+            let obj = obj.unwrap();
+            if self.cgb {
+                self.get_obj_rbg(obj.palette)[obj.color as usize]
+            } else {
+                let palette_addr = if obj.palette == 0 {
+                    OBP0_ADDR
+                } else {
+                    OBP1_ADDR
+                };
+                let color_data = self.get_memory(palette_addr, SOURCE);
+                self.obj_shades(obj.palette)[((color_data >> (obj.color * 2)) & 0b11) as usize]
+            }
+        } else if self.cgb {
+            self.get_bg_rbg(bg.palette)[bg_color as usize]
+        } else {
+            let color_data = self.get_memory(BGP_ADDR, SOURCE);
+            self.bg_shades()[((color_data >> (bg_color * 2)) & 0b11) as usize]
+        };
+        let frame_index = convert_to_index(row, self.ppu.fifo.lcd_x);
+        self.frame[frame_index..(frame_index + PIXEL_LENGTH)].copy_from_slice(&color);
+        self.ppu.fifo.lcd_x += 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::*;
+    use crate::emulator::{booted, GameBoyEmulator, RequestSource};
+    use crate::ppu::convert_to_index;
+
+    const CPU: RequestSource = RequestSource::CPU;
+
+    /// Runs `em` with the FIFO renderer until mode 3 of `row` is about to start.
+    fn run_to_drawing(em: &mut GameBoyEmulator, row: u8) {
+        em.ppu.fifo_renderer = true;
+        while em.get_memory(LY_ADDR, CPU) != row
+            || em.get_memory(STAT_ADDR, CPU) & 0b11 != DRAWING_MODE
+        {
+            em.advance();
+        }
+    }
+
+    // The boot logo is left on screen, so the two renderers should agree on it.
+    #[test]
+    fn matches_line_renderer() {
+        let mut frames = Vec::new();
+        for &fifo_renderer in [false, true].iter() {
+            let mut em = booted(&[0x18, 0xFE]);
+            em.ppu.fifo_renderer = fifo_renderer;
+            em.run_headless(2 * ADVANCES_PER_FRAME);
+            frames.push(em.frame.clone());
+        }
+        assert!(frames[0].iter().any(|&byte| byte != frames[0][0]));
+        assert!(frames[0] == frames[1]);
+    }
+
+    // A BGP write partway through mode 3 only recolors the pixels shifted out after it, which
+    // move 4 to the right for every M-cycle the write is held back.
+    #[test]
+    fn mid_line_palette_write_splits_the_row() {
+        let mut splits = Vec::new();
+        for delay in [10, 20] {
+            let mut em = booted(&[0x18, 0xFE]);
+            run_to_drawing(&mut em, 10);
+            for _ in 0..delay {
+                em.advance();
+            }
+            let shades = em.bg_shades();
+            em.write_memory(BGP_ADDR, 0xFF, CPU);
+            while em.get_memory(LY_ADDR, CPU) == 10 {
+                em.advance();
+            }
+            let row: Vec<&[u8]> = (0..WINDOW_WIDTH)
+                .map(|x| &em.frame[convert_to_index(10_usize, x)..][..PIXEL_LENGTH])
+                .collect();
+            let split = row.iter().position(|&pixel| pixel != shades[0]).unwrap();
+            assert!(split > 0);
+            assert!(row[split..].iter().all(|&pixel| pixel == shades[3]));
+            splits.push(split);
+        }
+        assert_eq!(splits[1] - splits[0], 10 * ADVANCE_CYCLES as usize);
+    }
+
+    // Fine scrolling, the window and a full row of sprites all stretch mode 3, which HBlank
+    // has to give back so the line stays ROW_DOTS long.
+    #[test]
+    fn long_mode_3_keeps_the_line_length() {
+        let mut em = booted(&[0x18, 0xFE]);
+        em.write_memory(SCX_ADDR, 7, CPU);
+        em.write_memory(WY_ADDR, 0, CPU);
+        em.write_memory(WX_ADDR, 87, CPU);
+        em.write_memory(LCDC_ADDR, 0xB3, CPU);
+        for i in 0..MAX_SPRITES_PER_ROW {
+            em.write_memory(OAM_START_ADDR + i * 4, 16, RequestSource::MAU);
+            em.write_memory(
+                OAM_START_ADDR + i * 4 + 1,
+                9 + 15 * i as u8,
+                RequestSource::MAU,
+            );
+        }
+        run_to_drawing(&mut em, 2);
+        let (mut drawing, mut line) = (0, 0);
+        while em.get_memory(LY_ADDR, CPU) == 2 {
+            if em.get_memory(STAT_ADDR, CPU) & 0b11 == DRAWING_MODE {
+                drawing += 1;
+            }
+            em.advance();
+            line += 1;
+        }
+        assert!(drawing * ADVANCE_CYCLES > DRAWING_DOTS + 60);
+        assert_eq!(line * ADVANCE_CYCLES, ROW_DOTS - OAM_SCAN_DOTS);
+    }
+}
@@ -0,0 +1,351 @@
+use crate::constants::*;
+use crate::emulator::GameBoyEmulator;
+
+/// How a finished frame is upscaled on the CPU before it is handed to the pixels surface.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Filter {
+    /// Passes the frame straight to the surface.
+    None,
+    /// Repeats every pixel to the largest whole multiple that fits the window.
+    Nearest,
+    Scale2x,
+    Scale3x,
+    /// Blends the corners of pixels along edges found by comparing colors in YUV, like hq2x.
+    Hq2x,
+    /// Draws each pixel as a dot separated from its neighbours by a darker grid.
+    LcdGrid,
+}
+
+impl Filter {
+    pub fn from_name(name: &str) -> Option<Filter> {
+        match name {
+            "none" => Some(Filter::None),
+            "nearest" => Some(Filter::Nearest),
+            "scale2x" => Some(Filter::Scale2x),
+            "scale3x" => Some(Filter::Scale3x),
+            "hq2x" => Some(Filter::Hq2x),
+            "lcd" => Some(Filter::LcdGrid),
+            _ => None,
+        }
+    }
+    fn next(self) -> Filter {
+        match self {
+            Filter::None => Filter::Nearest,
+            Filter::Nearest => Filter::Scale2x,
+            Filter::Scale2x => Filter::Scale3x,
+            Filter::Scale3x => Filter::Hq2x,
+            Filter::Hq2x => Filter::LcdGrid,
+            Filter::LcdGrid => Filter::None,
+        }
+    }
+    /// Upscales a `width` x `height` `frame` for a window `window_scale` times that size,
+    /// returning the new frame with its width and height.
+    pub fn apply(
+        self,
+        frame: &[u8],
+        width: usize,
+        height: usize,
+        window_scale: usize,
+    ) -> (Vec<u8>, usize, usize) {
+        let scaled = |frame: Vec<u8>, scale: usize| (frame, width * scale, height * scale);
+        let (filtered, scale) = match self {
+            Filter::None => return (frame.to_vec(), width, height),
+            Filter::Nearest => return scaled(nearest(frame, width, window_scale), window_scale),
+            Filter::Scale2x => (scale2x(frame, width, height), 2),
+            Filter::Scale3x => (scale3x(frame, width, height), 3),
+            Filter::Hq2x => (hq2x(frame, width, height), 2),
+            Filter::LcdGrid => {
+                let scale = window_scale.max(LCD_GRID_MIN_SCALE);
+                return scaled(lcd_grid(frame, width, scale), scale);
+            }
+        };
+        // Fill the rest of the window by repeating the filtered pixels.
+        let repeat = (window_scale / scale).max(1);
+        if repeat == 1 {
+            return scaled(filtered, scale);
+        }
+        scaled(nearest(&filtered, width * scale, repeat), scale * repeat)
+    }
+}
+
+fn pixel(frame: &[u8], width: usize, x: usize, y: usize) -> [u8; 4] {
+    let i = (y * width + x) * PIXEL_LENGTH;
+    [frame[i], frame[i + 1], frame[i + 2], frame[i + 3]]
+}
+
+/// The 3x3 block of pixels around (x, y), with the edges of the frame repeated outwards.
+fn neighbours(frame: &[u8], width: usize, height: usize, x: usize, y: usize) -> [[u8; 4]; 9] {
+    let xs = [x.saturating_sub(1), x, (x + 1).min(width - 1)];
+    let ys = [y.saturating_sub(1), y, (y + 1).min(height - 1)];
+    let mut block = [[0; 4]; 9];
+    for (i, color) in block.iter_mut().enumerate() {
+        *color = pixel(frame, width, xs[i % 3], ys[i / 3]);
+    }
+    block
+}
+
+/// Writes a `scale` x `scale` block of `colors`, given row by row, for the pixel at (x, y).
+fn put_block(out: &mut [u8], width: usize, scale: usize, x: usize, y: usize, colors: &[[u8; 4]]) {
+    let out_width = width * scale;
+    for (i, color) in colors.iter().enumerate() {
+        let index = ((y * scale + i / scale) * out_width + x * scale + i % scale) * PIXEL_LENGTH;
+        out[index..index + PIXEL_LENGTH].copy_from_slice(color);
+    }
+}
+
+pub fn nearest(frame: &[u8], width: usize, scale: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(frame.len() * scale * scale);
+    for row in frame.chunks_exact(width * PIXEL_LENGTH) {
+        for _ in 0..scale {
+            for color in row.chunks_exact(PIXEL_LENGTH) {
+                for _ in 0..scale {
+                    out.extend_from_slice(color);
+                }
+            }
+        }
+    }
+    out
+}
+
+pub fn scale2x(frame: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut out = vec![0; frame.len() * 4];
+    for y in 0..height {
+        for x in 0..width {
+            let [_, b, _, d, e, f, _, h, _] = neighbours(frame, width, height, x, y);
+            let block = if b != h && d != f {
+                [
+                    if d == b { d } else { e },
+                    if b == f { f } else { e },
+                    if d == h { d } else { e },
+                    if h == f { f } else { e },
+                ]
+            } else {
+                [e; 4]
+            };
+            put_block(&mut out, width, 2, x, y, &block);
+        }
+    }
+    out
+}
+
+pub fn scale3x(frame: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut out = vec![0; frame.len() * 9];
+    for y in 0..height {
+        for x in 0..width {
+            let [a, b, c, d, e, f, g, h, i] = neighbours(frame, width, height, x, y);
+            let block = if b != h && d != f {
+                [
+                    if d == b { d } else { e },
+                    if (d == b && e != c) || (b == f && e != a) {
+                        b
+                    } else {
+                        e
+                    },
+                    if b == f { f } else { e },
+                    if (d == b && e != g) || (d == h && e != a) {
+                        d
+                    } else {
+                        e
+                    },
+                    e,
+                    if (b == f && e != i) || (h == f && e != c) {
+                        f
+                    } else {
+                        e
+                    },
+                    if d == h { d } else { e },
+                    if (d == h && e != i) || (h == f && e != g) {
+                        h
+                    } else {
+                        e
+                    },
+                    if h == f { f } else { e },
+                ]
+            } else {
+                [e; 9]
+            };
+            put_block(&mut out, width, 3, x, y, &block);
+        }
+    }
+    out
+}
+
+fn yuv(color: [u8; 4]) -> [i32; 3] {
+    let [r, g, b] = [color[0] as i32, color[1] as i32, color[2] as i32];
+    [
+        (299 * r + 587 * g + 114 * b) / 1000,
+        (-169 * r - 331 * g + 500 * b) / 1000,
+        (500 * r - 419 * g - 81 * b) / 1000,
+    ]
+}
+
+fn distinct(first: [u8; 4], second: [u8; 4]) -> bool {
+    let (first, second) = (yuv(first), yuv(second));
+    (0..3).any(|i| (first[i] - second[i]).abs() > HQX_YUV_THRESHOLDS[i])
+}
+
+/// Mixes colors with the given weights.
+fn blend(colors: &[([u8; 4], u32)]) -> [u8; 4] {
+    let total: u32 = colors.iter().map(|(_, weight)| weight).sum();
+    let mut out = [0xFF; 4];
+    for (channel, value) in out.iter_mut().take(3).enumerate() {
+        let sum: u32 = colors
+            .iter()
+            .map(|(color, weight)| color[channel] as u32 * weight)
+            .sum();
+        *value = (sum / total) as u8;
+    }
+    out
+}
+
+/// A corner of `center`, between the pixels `side_1` and `side_2` next to it and `diagonal`.
+fn hq_corner(center: [u8; 4], side_1: [u8; 4], side_2: [u8; 4], diagonal: [u8; 4]) -> [u8; 4] {
+    if !distinct(side_1, side_2) && distinct(center, side_1) && distinct(center, side_2) {
+        // An edge runs diagonally across the corner.
+        blend(&[(center, 2), (side_1, 1), (side_2, 1)])
+    } else if distinct(center, diagonal) && !distinct(center, side_1) && !distinct(center, side_2) {
+        blend(&[(center, 3), (diagonal, 1)])
+    } else if distinct(center, side_1) != distinct(center, side_2) {
+        let side = if distinct(center, side_1) {
+            side_1
+        } else {
+            side_2
+        };
+        blend(&[(center, 3), (side, 1)])
+    } else {
+        center
+    }
+}
+
+pub fn hq2x(frame: &[u8], width: usize, height: usize) -> Vec<u8> {
+    let mut out = vec![0; frame.len() * 4];
+    for y in 0..height {
+        for x in 0..width {
+            let [a, b, c, d, e, f, g, h, i] = neighbours(frame, width, height, x, y);
+            let block = [
+                hq_corner(e, b, d, a),
+                hq_corner(e, b, f, c),
+                hq_corner(e, h, d, g),
+                hq_corner(e, h, f, i),
+            ];
+            put_block(&mut out, width, 2, x, y, &block);
+        }
+    }
+    out
+}
+
+/// Repeats every pixel `scale` times with its last row and column darkened.
+pub fn lcd_grid(frame: &[u8], width: usize, scale: usize) -> Vec<u8> {
+    let mut out = nearest(frame, width, scale);
+    let out_width = width * scale;
+    for (i, color) in out.chunks_exact_mut(PIXEL_LENGTH).enumerate() {
+        let (x, y) = (i % out_width, i / out_width);
+        if x % scale == scale - 1 || y % scale == scale - 1 {
+            for channel in color.iter_mut().take(3) {
+                *channel = (*channel as u32 * LCD_GRID_SHADE / 100) as u8;
+            }
+        }
+    }
+    out
+}
+
+impl GameBoyEmulator {
+    pub fn next_filter(&mut self) {
+        self.filter = self.filter.next();
+        eprintln!("Filter: {:?}", self.filter);
+    }
+    pub fn toggle_frame_blending(&mut self) {
+        self.frame_blending = !self.frame_blending;
+        eprintln!(
+            "Frame blending: {}",
+            if self.frame_blending { "on" } else { "off" }
+        );
+    }
+    /// The finished frame as it should be shown with any sprite boxes, returning the frame
+    /// with its width and height. Frame blending averages it with the previous frame first,
+    /// like the slow DMG LCD that games flickering objects for transparency rely on. CGB games
+    /// are left alone, since the GBC's faster LCD doesn't ghost, but DMG games on it still
+    /// blend.
+    pub fn display_frame(&mut self) -> (Vec<u8>, usize, usize) {
+        let mut frame = if self.frame_blending && !self.cgb {
+            self.frame
+                .iter()
+                .zip(self.previous_frame.iter())
+                .map(|(&current, &previous)| ((current as u16 + previous as u16) / 2) as u8)
+                .collect::<Vec<u8>>()
+        } else {
+            self.frame.clone()
+        };
+        self.draw_obj_boxes(&mut frame);
+        let (frame, width, height) = self.sgb_screen(frame);
+        let display = self
+            .filter
+            .apply(&frame, width, height, self.window_scale());
+        self.previous_frame.copy_from_slice(&self.frame);
+        display
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: [u8; 4] = [0xFF, 0xFF, 0xFF, 0xFF];
+    const K: [u8; 4] = [0, 0, 0, 0xFF];
+
+    fn image(colors: &[[u8; 4]]) -> Vec<u8> {
+        colors.concat()
+    }
+
+    #[test]
+    fn scale_filters_round_diagonals() {
+        // A black diagonal from the top right to the bottom left.
+        let frame = image(&[W, W, K, W, K, W, K, W, W]);
+        // The white pixel above the diagonal's middle gets a black bottom right corner.
+        let out = scale2x(&frame, 3, 3);
+        assert_eq!(pixel(&out, 6, 3, 1), K);
+        assert_eq!(pixel(&out, 6, 2, 0), W);
+        assert_eq!(pixel(&out, 6, 3, 3), K);
+
+        let out = scale3x(&frame, 3, 3);
+        assert_eq!(pixel(&out, 9, 5, 2), K);
+        assert_eq!(pixel(&out, 9, 4, 1), W);
+        assert_eq!(pixel(&out, 9, 4, 4), K);
+
+        let out = hq2x(&frame, 3, 3);
+        let corner = pixel(&out, 6, 3, 1);
+        assert!(corner != W && corner != K);
+        assert_eq!(pixel(&out, 6, 2, 0), W);
+    }
+
+    #[test]
+    fn grid_and_blending() {
+        let out = lcd_grid(&image(&[W, W]), 2, 3);
+        assert_eq!(pixel(&out, 6, 0, 0), W);
+        assert_ne!(pixel(&out, 6, 2, 0), W);
+        assert_ne!(pixel(&out, 6, 0, 2), W);
+
+        let mut em = GameBoyEmulator::new_headless();
+        em.frame_blending = true;
+        em.frame.iter_mut().for_each(|byte| *byte = 0xFF);
+        em.display_frame();
+        em.frame.iter_mut().for_each(|byte| *byte = 0);
+        let (display, width, height) = em.display_frame();
+        assert_eq!((width, height), (WINDOW_WIDTH, WINDOW_HEIGHT));
+        assert_eq!(display[0], 0x7F);
+
+        em.cgb = true;
+        em.frame.iter_mut().for_each(|byte| *byte = 0xFF);
+        assert_eq!(em.display_frame().0[0], 0xFF);
+    }
+
+    #[test]
+    fn none_leaves_scaling_to_the_surface() {
+        let frame = image(&[W, K, K, W]);
+        assert_eq!(Filter::None.apply(&frame, 2, 2, 3), (frame.clone(), 2, 2));
+        let (nearest, width, height) = Filter::Nearest.apply(&frame, 2, 2, 3);
+        assert_eq!((width, height), (6, 6));
+        assert_eq!(pixel(&nearest, 6, 2, 2), W);
+        assert_eq!(pixel(&nearest, 6, 3, 0), K);
+    }
+}
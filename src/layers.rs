@@ -0,0 +1,135 @@
+use crate::constants::*;
+use crate::emulator::{GameBoyEmulator, RequestSource};
+const SOURCE: RequestSource = RequestSource::SPEC;
+
+/// Which layers are drawn, for tracking down rendering bugs. Hidden layers still take part
+/// in sprite priority, so only the colors written to the frame change.
+#[derive(Clone, Copy)]
+pub struct DebugLayers {
+    pub bg: bool,
+    pub window: bool,
+    pub objs: bool,
+    /// Outlines every sprite on screen and labels it with its OAM index in hex.
+    pub obj_boxes: bool,
+}
+
+impl DebugLayers {
+    pub fn new() -> DebugLayers {
+        DebugLayers {
+            bg: true,
+            window: true,
+            objs: true,
+            obj_boxes: false,
+        }
+    }
+}
+
+fn set_clipped(frame: &mut [u8], x: isize, y: isize, color: [u8; 4]) {
+    if (0..WINDOW_WIDTH as isize).contains(&x) && (0..WINDOW_HEIGHT as isize).contains(&y) {
+        let index = (y as usize * WINDOW_WIDTH + x as usize) * PIXEL_LENGTH;
+        frame[index..index + PIXEL_LENGTH].copy_from_slice(&color);
+    }
+}
+
+impl GameBoyEmulator {
+    pub fn toggle_layer(&mut self, layer: &str) {
+        let (name, shown) = match layer {
+            "bg" => ("Background", &mut self.layers.bg),
+            "window" => ("Window", &mut self.layers.window),
+            "objs" => ("Sprites", &mut self.layers.objs),
+            "obj boxes" => ("Sprite boxes", &mut self.layers.obj_boxes),
+            _ => return,
+        };
+        *shown = !*shown;
+        eprintln!("{}: {}", name, if *shown { "on" } else { "off" });
+    }
+    /// Draws the sprite boxes over `frame` when they are turned on.
+    pub fn draw_obj_boxes(&self, frame: &mut [u8]) {
+        if !self.layers.obj_boxes {
+            return;
+        }
+        let height = self.get_obj_size() as isize;
+        for sprite in 0..OAM_SPRITE_NUM {
+            let entry_addr = OAM_START_ADDR + sprite * BYTES_PER_OAM_ENTRY;
+            let top = self.get_memory(entry_addr + OAM_Y_INDEX, SOURCE) as isize - 16;
+            let left = self.get_memory(entry_addr + OAM_X_INDEX, SOURCE) as isize - 8;
+            if top + height <= 0
+                || top >= WINDOW_HEIGHT as isize
+                || left + 8 <= 0
+                || left >= WINDOW_WIDTH as isize
+            {
+                continue;
+            }
+            for x in left..left + 8 {
+                set_clipped(frame, x, top, OBJ_BOX_COLOR);
+                set_clipped(frame, x, top + height - 1, OBJ_BOX_COLOR);
+            }
+            for y in top..top + height {
+                set_clipped(frame, left, y, OBJ_BOX_COLOR);
+                set_clipped(frame, left + 7, y, OBJ_BOX_COLOR);
+            }
+            // The label sits just above the box, or inside it at the top of the screen.
+            let label_y = if top >= 6 { top - 6 } else { top + 1 };
+            for (digit_index, digit) in [sprite >> 4, sprite & 0xF].iter().enumerate() {
+                for (row, bits) in HEX_FONT[*digit].iter().enumerate() {
+                    for column in 0..3 {
+                        if (bits >> (2 - column)) & 1 == 1 {
+                            let x = left + digit_index as isize * 4 + column;
+                            set_clipped(frame, x, label_y + row as isize, OBJ_BOX_COLOR);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::booted;
+
+    #[test]
+    fn hidden_layers_leave_state_alone() {
+        let run = |layers: DebugLayers| {
+            let mut em = booted(&[0x18, 0xFE]);
+            em.layers = layers;
+            // Turn on 8x8 sprites and put sprite 1 in the top left corner of the screen.
+            while em.get_mode() != VBLANK_MODE {
+                em.advance();
+            }
+            em.write_memory(LCDC_ADDR, 0x93, SOURCE);
+            for (i, &byte) in [16, 8, 0x19, 0].iter().enumerate() {
+                em.write_memory(OAM_START_ADDR + BYTES_PER_OAM_ENTRY + i, byte, SOURCE);
+            }
+            em.run_headless(2 * ADVANCES_PER_FRAME);
+            em
+        };
+        let shown = run(DebugLayers::new());
+        let hidden = run(DebugLayers {
+            bg: false,
+            window: false,
+            objs: false,
+            obj_boxes: true,
+        });
+        assert_eq!(shown.ppu.cycle_count, hidden.ppu.cycle_count);
+        assert_eq!(
+            shown.get_memory(LY_ADDR, SOURCE),
+            hidden.get_memory(LY_ADDR, SOURCE)
+        );
+        assert_ne!(shown.frame, hidden.frame);
+        let background = &hidden.frame[0..PIXEL_LENGTH];
+        assert!(hidden
+            .frame
+            .chunks_exact(PIXEL_LENGTH)
+            .all(|pixel| pixel == background));
+
+        let mut frame = hidden.frame.clone();
+        hidden.draw_obj_boxes(&mut frame);
+        assert_eq!(frame[0..PIXEL_LENGTH], OBJ_BOX_COLOR);
+        let right_edge = 7 * PIXEL_LENGTH;
+        assert_eq!(frame[right_edge..right_edge + PIXEL_LENGTH], OBJ_BOX_COLOR);
+        let inside = (6 * WINDOW_WIDTH + 6) * PIXEL_LENGTH;
+        assert_eq!(frame[inside..inside + PIXEL_LENGTH], *background);
+    }
+}
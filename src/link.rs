@@ -0,0 +1,298 @@
+use crate::constants::*;
+use crate::serial::SerialDevice;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io;
+use std::io::prelude::*;
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::rc::Rc;
+
+/// A link cable to another emulator instance over TCP.
+///
+/// Both ends stop every `sync_cycles` M-cycles and swap a sync packet listing what their
+/// port did during that slice, so neither instance can run ahead of the other. Transfers
+/// are resolved only from what both ends agreed on at earlier syncs, which keeps the result
+/// independent of network timing:
+/// - every time a port starts waiting on an external clock it arms with its SB, and the
+///   armings are sent to the partner in order.
+/// - every transfer on the internal clock is paired with the partner's next arming. It
+///   receives that arming's SB if it arrived at an earlier sync, and 0xFF otherwise.
+/// - the partner gets all of our transfers at the next sync, and completes its armings
+///   with them in order, however many were sent in one slice.
+pub struct TcpLinkCable {
+    stream: Option<TcpStream>,
+    sync_cycles: u32,
+    cycle_count: u32,
+    transfers: Vec<u8>,
+    armings: Vec<u8>,
+    armed: bool,
+    polled: bool,
+    peer_armings: VecDeque<u8>,
+    unpaired_transfers: usize,
+    inbox: VecDeque<u8>,
+}
+
+impl TcpLinkCable {
+    /// Waits for the other instance to connect on `addr`.
+    pub fn host(addr: impl ToSocketAddrs, sync_cycles: u32) -> io::Result<TcpLinkCable> {
+        let listener = TcpListener::bind(addr)?;
+        let (stream, _) = listener.accept()?;
+        TcpLinkCable::from_stream(stream, sync_cycles)
+    }
+    /// Connects to an instance started with `host`.
+    pub fn connect(addr: impl ToSocketAddrs, sync_cycles: u32) -> io::Result<TcpLinkCable> {
+        let stream = TcpStream::connect(addr)?;
+        TcpLinkCable::from_stream(stream, sync_cycles)
+    }
+    pub fn from_stream(stream: TcpStream, sync_cycles: u32) -> io::Result<TcpLinkCable> {
+        stream.set_nodelay(true)?;
+        Ok(TcpLinkCable {
+            stream: Some(stream),
+            sync_cycles: sync_cycles.max(ADVANCE_CYCLES) / ADVANCE_CYCLES * ADVANCE_CYCLES,
+            cycle_count: 0,
+            transfers: Vec::new(),
+            armings: Vec::new(),
+            armed: false,
+            polled: false,
+            peer_armings: VecDeque::new(),
+            unpaired_transfers: 0,
+            inbox: VecDeque::new(),
+        })
+    }
+    /// Sends the transfer and arming counts followed by the bytes themselves, and reads the
+    /// partner's packet back.
+    fn swap_packets(&mut self) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => return Ok((Vec::new(), Vec::new())),
+        };
+        let mut packet = Vec::with_capacity(4 + self.transfers.len() + self.armings.len());
+        packet.extend_from_slice(&(self.transfers.len() as u16).to_le_bytes());
+        packet.extend_from_slice(&(self.armings.len() as u16).to_le_bytes());
+        packet.extend_from_slice(&self.transfers);
+        packet.extend_from_slice(&self.armings);
+        stream.write_all(&packet)?;
+        let mut counts = [0; 4];
+        stream.read_exact(&mut counts)?;
+        let transfers = u16::from_le_bytes([counts[0], counts[1]]) as usize;
+        let armings = u16::from_le_bytes([counts[2], counts[3]]) as usize;
+        let mut peer_transfers = vec![0; transfers];
+        stream.read_exact(&mut peer_transfers)?;
+        let mut peer_armings = vec![0; armings];
+        stream.read_exact(&mut peer_armings)?;
+        Ok((peer_transfers, peer_armings))
+    }
+    fn sync(&mut self) {
+        let (peer_transfers, peer_armings) = match self.swap_packets() {
+            Ok(packets) => packets,
+            Err(e) => {
+                eprintln!("Link cable disconnected: {}", e);
+                self.stream = None;
+                (Vec::new(), Vec::new())
+            }
+        };
+        self.inbox.extend(peer_transfers);
+        for sb in peer_armings {
+            // Armings our earlier transfers were already paired with, while unknown.
+            if self.unpaired_transfers > 0 {
+                self.unpaired_transfers -= 1;
+            } else {
+                self.peer_armings.push_back(sb);
+            }
+        }
+        self.transfers.clear();
+        self.armings.clear();
+    }
+}
+
+impl SerialDevice for TcpLinkCable {
+    fn exchange(&mut self, out: u8) -> u8 {
+        self.transfers.push(out);
+        self.peer_armings.pop_front().unwrap_or_else(|| {
+            self.unpaired_transfers += 1;
+            0xFF
+        })
+    }
+    fn external_exchange(&mut self, out: u8) -> Option<u8> {
+        self.polled = true;
+        if !self.armed {
+            self.armed = true;
+            self.armings.push(out);
+        }
+        let received = self.inbox.pop_front();
+        if received.is_some() {
+            self.armed = false;
+        }
+        received
+    }
+    fn advance(&mut self) {
+        // The port stopped waiting on the external clock without receiving anything.
+        if !self.polled {
+            self.armed = false;
+        }
+        self.polled = false;
+        self.cycle_count += ADVANCE_CYCLES;
+        if self.cycle_count == self.sync_cycles {
+            self.cycle_count = 0;
+            self.sync();
+        }
+    }
+}
+
+#[derive(Default)]
+struct Wire {
+    armed_sb: [Option<u8>; 2],
+    inbox: [Option<u8>; 2],
+}
+
+/// One end of a link cable between two cores in the same process.
+///
+/// The two cores have to be advanced in lockstep. A transfer on the internal clock
+/// receives the partner's SB if the partner is waiting on an external clock, and the
+/// partner's transfer completes with our byte on its next M-cycle.
+pub struct DirectLinkCable {
+    wire: Rc<RefCell<Wire>>,
+    side: usize,
+}
+
+impl DirectLinkCable {
+    pub fn pair() -> (DirectLinkCable, DirectLinkCable) {
+        let wire = Rc::new(RefCell::new(Wire::default()));
+        (
+            DirectLinkCable {
+                wire: wire.clone(),
+                side: 0,
+            },
+            DirectLinkCable { wire, side: 1 },
+        )
+    }
+}
+
+impl SerialDevice for DirectLinkCable {
+    fn exchange(&mut self, out: u8) -> u8 {
+        let mut wire = self.wire.borrow_mut();
+        let peer = 1 - self.side;
+        match wire.armed_sb[peer].take() {
+            Some(peer_sb) => {
+                wire.inbox[peer] = Some(out);
+                peer_sb
+            }
+            None => 0xFF,
+        }
+    }
+    fn external_exchange(&mut self, out: u8) -> Option<u8> {
+        let mut wire = self.wire.borrow_mut();
+        let received = wire.inbox[self.side].take();
+        wire.armed_sb[self.side] = if received.is_none() { Some(out) } else { None };
+        received
+    }
+    fn advance(&mut self) {
+        self.wire.borrow_mut().armed_sb[self.side] = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{booted, GameBoyEmulator, RequestSource};
+    use std::thread;
+
+    const SOURCE: RequestSource = RequestSource::SPEC;
+    // Enough for the master's wait and one transfer at 8192 Hz.
+    const TEST_ADVANCES: u32 = 20_000;
+
+    // Waits ~32K cycles so the other side is ready, then sends 0x42 on the internal clock.
+    const MASTER_PROGRAM: [u8; 20] = [
+        0x0E, 0x08, 0x06, 0x00, 0x05, 0x20, 0xFD, 0x0D, 0x20, 0xF8, 0x3E, 0x42, 0xE0, 0x01, 0x3E,
+        0x81, 0xE0, 0x02, 0x18, 0xFE,
+    ];
+    // Puts 0x99 in SB and waits on the external clock.
+    const SLAVE_PROGRAM: [u8; 10] = [0x3E, 0x99, 0xE0, 0x01, 0x3E, 0x80, 0xE0, 0x02, 0x18, 0xFE];
+
+    fn port_state(em: &mut GameBoyEmulator) -> (u8, u8, u8) {
+        (
+            em.get_memory(SB_ADDR, SOURCE),
+            em.get_memory(SC_ADDR, SOURCE),
+            em.get_memory(INT_FLAG_ADDR, SOURCE),
+        )
+    }
+
+    fn run_linked(cable: TcpLinkCable, program: &[u8]) -> (u8, u8, u8) {
+        let mut em = booted(program);
+        em.connect_serial_device(Box::new(cable));
+        em.run_headless(TEST_ADVANCES);
+        port_state(&mut em)
+    }
+
+    fn check_transfer(master: (u8, u8, u8), slave: (u8, u8, u8)) {
+        let (master_sb, master_sc, master_if) = master;
+        let (slave_sb, slave_sc, slave_if) = slave;
+        assert_eq!(master_sb, 0x99);
+        assert_eq!(slave_sb, 0x42);
+        assert_eq!(master_sc & 0x80, 0);
+        assert_eq!(slave_sc & 0x80, 0);
+        assert_ne!(master_if & 0b1000, 0);
+        assert_ne!(slave_if & 0b1000, 0);
+    }
+
+    #[test]
+    fn transfer_over_loopback() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let master = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let cable = TcpLinkCable::from_stream(stream, LINK_SYNC_CYCLES).unwrap();
+            run_linked(cable, &MASTER_PROGRAM)
+        });
+        let slave = thread::spawn(move || {
+            let cable = TcpLinkCable::connect(addr, LINK_SYNC_CYCLES).unwrap();
+            run_linked(cable, &SLAVE_PROGRAM)
+        });
+        check_transfer(master.join().unwrap(), slave.join().unwrap());
+    }
+
+    #[test]
+    fn queues_transfers_within_a_slice() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Arms with 0x10, then again with the next value after every byte it receives.
+        let slave = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut cable = TcpLinkCable::from_stream(stream, ADVANCE_CYCLES).unwrap();
+            let mut sb = 0x10;
+            let mut received = Vec::new();
+            for _ in 0..3 {
+                while let Some(byte) = cable.external_exchange(sb) {
+                    received.push(byte);
+                    sb += 1;
+                }
+                cable.advance();
+            }
+            received
+        });
+        let mut cable = TcpLinkCable::connect(addr, ADVANCE_CYCLES).unwrap();
+        cable.advance();
+        // Three fast transfers in one slice: only the first arming is known yet.
+        let replies: Vec<u8> = [1, 2, 3].iter().map(|&out| cable.exchange(out)).collect();
+        assert_eq!(replies, [0x10, 0xFF, 0xFF]);
+        cable.advance();
+        cable.advance();
+        // The armings the unknown replies were paired with are skipped.
+        assert_eq!(cable.exchange(4), 0x13);
+        assert_eq!(slave.join().unwrap(), [1, 2, 3]);
+    }
+
+    #[test]
+    fn transfer_over_direct_link() {
+        let (master_cable, slave_cable) = DirectLinkCable::pair();
+        let mut master = booted(&MASTER_PROGRAM);
+        let mut slave = booted(&SLAVE_PROGRAM);
+        master.connect_serial_device(Box::new(master_cable));
+        slave.connect_serial_device(Box::new(slave_cable));
+        for _ in 0..TEST_ADVANCES {
+            master.advance();
+            slave.advance();
+        }
+        check_transfer(port_state(&mut master), port_state(&mut slave));
+    }
+}
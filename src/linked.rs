@@ -0,0 +1,163 @@
+use pixels::Pixels;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::Scancode;
+use sdl2::video::Window;
+use sdl2::EventPump;
+
+use crate::apu::AudioProcessingUnit;
+use crate::constants::*;
+use crate::emulator::GameBoyEmulator;
+use crate::epu::{read_joypad, PLAYER_1_KEYS, PLAYER_2_KEYS};
+use crate::link::DirectLinkCable;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+/// Two cores joined by a link cable, advanced together one M-cycle at a time and shown
+/// side by side in one window. The left core plays the audio.
+pub struct LinkedEmulators {
+    pub left: GameBoyEmulator,
+    pub right: GameBoyEmulator,
+    _sdl_context: sdl2::Sdl,
+    _window: Window,
+    pixels: Pixels,
+    event_pump: EventPump,
+    running: bool,
+}
+
+impl LinkedEmulators {
+    pub fn new() -> LinkedEmulators {
+        let sdl_context = sdl2::init().unwrap();
+        let video_subsystem = sdl_context.video().unwrap();
+        let audio_subsystem = sdl_context.audio().unwrap();
+        let mut window = video_subsystem
+            .window(
+                "Gameboy Emulator - Linked",
+                2 * WINDOW_WIDTH as u32,
+                WINDOW_HEIGHT as u32,
+            )
+            .position_centered()
+            .resizable()
+            .allow_highdpi()
+            .build()
+            .unwrap();
+        window
+            .set_minimum_size(2 * WINDOW_WIDTH as u32, WINDOW_HEIGHT as u32)
+            .unwrap();
+        let surface_texture =
+            pixels::SurfaceTexture::new(2 * WINDOW_WIDTH as u32, WINDOW_HEIGHT as u32, &window);
+        let pixels = pixels::Pixels::new(
+            2 * WINDOW_WIDTH as u32,
+            WINDOW_HEIGHT as u32,
+            surface_texture,
+        )
+        .unwrap();
+        let event_pump = sdl_context.event_pump().unwrap();
+
+        let mut left = GameBoyEmulator::new_headless();
+        let mut right = GameBoyEmulator::new_headless();
+        left.apu = AudioProcessingUnit::new(Some(audio_subsystem));
+        let (left_cable, right_cable) = DirectLinkCable::pair();
+        left.connect_serial_device(Box::new(left_cable));
+        right.connect_serial_device(Box::new(right_cable));
+        LinkedEmulators {
+            left,
+            right,
+            _sdl_context: sdl_context,
+            _window: window,
+            pixels,
+            event_pump,
+            running: true,
+        }
+    }
+    pub fn load_roms(&mut self, left_path: &Path, right_path: &Path) {
+        self.left.load_rom(left_path);
+        self.right.load_rom(right_path);
+    }
+    pub fn advance(&mut self) {
+        self.left.advance();
+        self.right.advance();
+    }
+    fn draw(&mut self) {
+        side_by_side(&self.left, &self.right, self.pixels.get_frame());
+        self.pixels.render().unwrap();
+    }
+    fn event_check(&mut self) {
+        let state = self.event_pump.keyboard_state();
+        let (left_directional, left_action) = read_joypad(&state, &PLAYER_1_KEYS);
+        let (right_directional, right_action) = read_joypad(&state, &PLAYER_2_KEYS);
+        self.left.set_joypad(left_directional, left_action);
+        self.right.set_joypad(right_directional, right_action);
+        for event in self.event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    scancode: Some(Scancode::Escape),
+                    ..
+                } => self.running = false,
+                Event::Window {
+                    win_event: WindowEvent::Resized(width, height),
+                    ..
+                } => self.pixels.resize_surface(width as u32, height as u32),
+                _ => {}
+            }
+        }
+    }
+    pub fn run(&mut self) {
+        let work_period = Duration::new(0, PERIOD_NS);
+        while self.running {
+            let now = Instant::now();
+
+            self.left.buffer_check();
+            for _ in 0..ADVANCES_PER_PERIOD {
+                self.advance();
+                if self.left.frame_ready || self.right.frame_ready {
+                    self.left.frame_ready = false;
+                    self.right.frame_ready = false;
+                    self.draw();
+                }
+            }
+            self.event_check();
+            while now.elapsed() < work_period {}
+        }
+    }
+}
+
+/// Lays the last frames `left` and `right` finished side by side in `frame`. Only one of them
+/// has just finished when this is drawn, so the other's frame in progress would tear.
+fn side_by_side(left: &GameBoyEmulator, right: &GameBoyEmulator, frame: &mut [u8]) {
+    let row_length = WINDOW_WIDTH * PIXEL_LENGTH;
+    for (y, row) in frame.chunks_exact_mut(2 * row_length).enumerate() {
+        let source = y * row_length..(y + 1) * row_length;
+        row[..row_length].copy_from_slice(&left.finished_frame[source.clone()]);
+        row[row_length..].copy_from_slice(&right.finished_frame[source]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::side_by_side;
+    use crate::constants::*;
+    use crate::emulator::{booted, RequestSource};
+
+    #[test]
+    fn draws_finished_frames() {
+        let mut cores = [booted(&[0x18, 0xFE]), booted(&[0x18, 0xFE])];
+        for (i, core) in cores.iter_mut().enumerate() {
+            while !core.frame_ready {
+                core.advance();
+            }
+            // Blacken the cores part way through their next frames.
+            core.write_memory(BGP_ADDR, 0xFF, RequestSource::CPU);
+            core.run_headless((i as u32 + 1) * ADVANCES_PER_FRAME / 3);
+            assert!(core.frame != core.finished_frame);
+        }
+        let mut frame = vec![0; 2 * WINDOW_WIDTH * WINDOW_HEIGHT * PIXEL_LENGTH];
+        side_by_side(&cores[0], &cores[1], &mut frame);
+        let row_length = WINDOW_WIDTH * PIXEL_LENGTH;
+        for (y, row) in frame.chunks_exact(2 * row_length).enumerate() {
+            let source = y * row_length..(y + 1) * row_length;
+            assert_eq!(row[..row_length], cores[0].finished_frame[source.clone()]);
+            assert_eq!(row[row_length..], cores[1].finished_frame[source]);
+        }
+    }
+}
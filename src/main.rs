@@ -1,19 +1,254 @@
 mod apu;
+mod cheats;
+mod config;
 mod constants;
 mod cpu;
 mod emulator;
 mod epu;
+mod fifo;
+mod filter;
+mod layers;
+mod link;
+mod linked;
 mod memory;
+mod model;
+mod palette;
 //mod pdu;
 mod bootroms;
 mod ppu;
+mod printer;
+mod recorder;
+mod screenshot;
+mod search;
+mod serial;
+mod sgb;
 mod timing;
+mod viewer;
+mod watch;
+
+use constants::{DEFAULT_CONFIG_FILE, LINK_SYNC_CYCLES};
+use std::path::{Path, PathBuf};
+
+/// What the command line plugs into the link port.
+enum SerialOption {
+    LinkHost(String),
+    LinkConnect(String),
+    Printer(PathBuf),
+    LocalLink(PathBuf),
+}
 
 fn main() {
-    let mut em = emulator::GameBoyEmulator::new();
-    let res = rfd::Dialog::pick_file().open();
-    if res.len() == 1 {
-        em.load_rom(&res[0]);
-        em.run()
+    let mut rom_path = None;
+    let mut serial: Option<SerialOption> = None;
+    let mut fifo_renderer = false;
+    let mut palette = None;
+    let mut model = None;
+    let mut color_correction = None;
+    let mut filter = None;
+    let mut frame_blending = false;
+    let mut screenshot_dir = None;
+    let mut headless_frame = None;
+    let mut record_path = None;
+    let mut watch_ranges = None;
+    let mut cheat_codes = Vec::new();
+    let mut watch_path = PathBuf::from("watch.csv");
+    let mut boot_rom_files = Vec::new();
+    let mut skip_boot = false;
+    let cli_args: Vec<String> = std::env::args().skip(1).collect();
+    // Options from the config file come first so the command line can override them.
+    let config_args = match cli_args.iter().position(|arg| arg == "--config") {
+        Some(i) => {
+            let path = cli_args.get(i + 1).expect("--config needs a file name.");
+            config::read_config(Path::new(path), true)
+        }
+        None => config::read_config(Path::new(DEFAULT_CONFIG_FILE), false),
+    }
+    .unwrap_or_else(|e| panic!("Could not read config {}", e));
+    let mut args = config_args.into_iter().chain(cli_args);
+    while let Some(arg) = args.next() {
+        let serial_option = match arg.as_str() {
+            "--link-host" => Some(SerialOption::LinkHost(
+                args.next()
+                    .expect("--link-host needs an address to listen on."),
+            )),
+            "--link-connect" => Some(SerialOption::LinkConnect(
+                args.next()
+                    .expect("--link-connect needs an address to connect to."),
+            )),
+            "--printer" => Some(SerialOption::Printer(PathBuf::from(
+                args.next()
+                    .expect("--printer needs a directory to print to."),
+            ))),
+            "--local-link" => Some(SerialOption::LocalLink(PathBuf::from(
+                args.next().expect("--local-link needs a second ROM."),
+            ))),
+            _ => None,
+        };
+        if serial_option.is_some() {
+            if serial.is_some() {
+                panic!(
+                    "Only one of --link-host, --link-connect, --printer and --local-link can be given."
+                );
+            }
+            serial = serial_option;
+            continue;
+        }
+        match arg.as_str() {
+            "--fifo" => fifo_renderer = true,
+            "--cgb" => model = Some(model::Model::Cgb),
+            "--model" => {
+                let name = args.next().expect("--model needs a model name.");
+                model = Some(
+                    model::Model::from_name(&name)
+                        .expect("Model must be auto, dmg, mgb, sgb, sgb2, cgb or agb."),
+                );
+            }
+            "--config" => {
+                args.next();
+            }
+            "--screenshot-dir" => {
+                let dir = args.next().expect("--screenshot-dir needs a directory.");
+                screenshot_dir = Some(PathBuf::from(dir));
+            }
+            "--record" => {
+                let path = args.next().expect("--record needs a file name.");
+                record_path = Some(PathBuf::from(path));
+            }
+            "--cheat" => {
+                cheat_codes.push(args.next().expect("--cheat needs a code."));
+            }
+            "--watch" => {
+                let ranges = args
+                    .next()
+                    .expect("--watch needs addresses such as C000-C0FF,FF40.");
+                watch_ranges = Some(
+                    watch::parse_ranges(&ranges)
+                        .expect("Watched addresses must be hex addresses or ranges."),
+                );
+            }
+            "--boot-rom" => {
+                let spec = args
+                    .next()
+                    .expect("--boot-rom needs a model and a file, such as dmg=dmg_boot.bin.");
+                let (model, path) = spec
+                    .split_once('=')
+                    .expect("--boot-rom needs a model and a file, such as dmg=dmg_boot.bin.");
+                let model = bootroms::BootRomModel::from_name(model)
+                    .expect("Boot ROM model must be dmg0, dmg, mgb, sgb, cgb or agb.");
+                boot_rom_files.push((model, PathBuf::from(path)));
+            }
+            "--skip-boot" => skip_boot = true,
+            "--watch-log" => {
+                let path = args.next().expect("--watch-log needs a file name.");
+                watch_path = PathBuf::from(path);
+            }
+            "--headless-screenshot" => {
+                let frame = args
+                    .next()
+                    .expect("--headless-screenshot needs a frame number.");
+                headless_frame = Some(frame.parse::<u32>().expect("Bad frame number."));
+            }
+            "--color-correction" => {
+                let name = args.next().expect("--color-correction needs a mode.");
+                color_correction = Some(
+                    palette::ColorCorrection::from_name(&name)
+                        .expect("Color correction must be none, gbc or gba."),
+                );
+            }
+            "--filter" => {
+                let name = args.next().expect("--filter needs a filter name.");
+                filter = Some(
+                    filter::Filter::from_name(&name)
+                        .expect("Filter must be none, nearest, scale2x, scale3x, hq2x or lcd."),
+                );
+            }
+            "--frame-blend" => frame_blending = true,
+            "--palette" => {
+                palette = Some(args.next().expect("--palette needs a preset name or file."));
+            }
+            _ if arg.starts_with("--") => panic!("Unknown option {}.", arg),
+            _ if rom_path.is_some() => panic!("Only one ROM can be given, but got {} too.", arg),
+            _ => rom_path = Some(PathBuf::from(arg)),
+        }
+    }
+    let rom_path = match rom_path {
+        Some(path) => path,
+        None => {
+            let res = rfd::Dialog::pick_file().open();
+            if res.len() != 1 {
+                return;
+            }
+            res[0].clone()
+        }
+    };
+    if let Some(SerialOption::LocalLink(local_link_path)) = serial {
+        let mut linked = linked::LinkedEmulators::new();
+        linked.load_roms(&rom_path, &local_link_path);
+        linked.run();
+        return;
+    }
+    let mut em = if headless_frame.is_some() {
+        emulator::GameBoyEmulator::new_headless()
+    } else {
+        emulator::GameBoyEmulator::new()
+    };
+    em.ppu.fifo_renderer = fifo_renderer;
+    if let Some(model) = model {
+        em.model = model;
+    }
+    em.frame_blending = frame_blending;
+    if let Some(filter) = filter {
+        em.filter = filter;
+    }
+    // The GBA screen is darker than the GBC one, so it gets its own correction by default.
+    match color_correction {
+        Some(color_correction) => em.set_color_correction(color_correction),
+        None if em.model == model::Model::Agb => {
+            em.set_color_correction(palette::ColorCorrection::GbaLcd)
+        }
+        None => {}
+    }
+    if let Some(palette) = palette {
+        em.set_palette(&palette).expect("Could not load palette.");
+    }
+    match serial {
+        Some(SerialOption::LinkHost(addr)) => em.connect_serial_device(Box::new(
+            link::TcpLinkCable::host(addr, LINK_SYNC_CYCLES).expect("Could not host link cable."),
+        )),
+        Some(SerialOption::LinkConnect(addr)) => em.connect_serial_device(Box::new(
+            link::TcpLinkCable::connect(addr, LINK_SYNC_CYCLES)
+                .expect("Could not connect link cable."),
+        )),
+        Some(SerialOption::Printer(dir)) => {
+            em.connect_serial_device(Box::new(printer::GameBoyPrinter::new(dir)))
+        }
+        _ => {}
+    }
+    if let Some(screenshot_dir) = screenshot_dir {
+        em.screenshot_dir = screenshot_dir;
+    }
+    for (model, path) in boot_rom_files {
+        em.boot_roms
+            .load(model, &path)
+            .expect("Could not load boot ROM.");
+    }
+    em.boot_roms.skip = skip_boot;
+    em.load_rom(&rom_path);
+    if let Some(record_path) = record_path {
+        em.start_recording(&record_path);
+    }
+    for code in cheat_codes {
+        em.add_cheat(&code, "").expect("Bad cheat code.");
+    }
+    if let Some(watch_ranges) = watch_ranges {
+        em.start_watch(&watch_path, watch_ranges);
+    }
+    if let Some(frame) = headless_frame {
+        em.run_frames(frame);
+        em.take_screenshot(1);
+        em.stop_recording();
+        em.stop_watch();
+        return;
     }
+    em.run()
 }
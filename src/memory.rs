@@ -10,7 +10,11 @@ use crate::emulator::RequestSource;
 use crate::bootroms::*;
 use crate::constants::*;
 use crate::ppu::PictureProcessingUnit;
+use crate::search::{RamLocation, RamRegion};
+use crate::serial::SerialPort;
+use crate::sgb::Sgb;
 use crate::timing::Timer;
+use crate::watch::Access;
 
 const SOURCE: RequestSource = RequestSource::MAU;
 
@@ -27,10 +31,49 @@ fn split_u16(val: u16) -> (u8, u8) {
 }
 
 #[inline]
-fn convert_to_8_bit(color5: u8) -> u8 {
+pub fn convert_to_8_bit(color5: u8) -> u8 {
     (color5 << 3) | (color5 >> 2)
 }
 
+/// An OAM DMA transfer, copying one byte per M-cycle once its start delay has passed.
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct OamDma {
+    source: Option<usize>,
+    index: usize,
+    pending: Option<usize>,
+    delay: u8,
+    bus_value: u8,
+}
+
+impl OamDma {
+    fn new() -> OamDma {
+        OamDma {
+            source: None,
+            index: 0,
+            pending: None,
+            delay: 0,
+            bus_value: 0xFF,
+        }
+    }
+}
+
+#[derive(PartialEq)]
+enum Bus {
+    External,
+    Video,
+    Wram,
+    Internal,
+}
+
+/// The kinds of bus activity that corrupt OAM on the DMG when they land in 0xFE00-0xFEFF
+/// during mode 2.
+#[derive(PartialEq, Clone, Copy)]
+pub enum OamBug {
+    Write,
+    Read,
+    ReadIncrease,
+}
+
 #[derive(Serialize, Deserialize, Clone, Copy)]
 enum CartType {
     Uninitialized,
@@ -61,11 +104,11 @@ struct SaveGame {
     cartridge_type: CartType,
     available_rom_banks: usize,
     available_ram_banks: u8,
-    hold_mem: Vec<u8>,
+    boot_rom: Vec<u8>,
     in_boot_rom: bool,
     directional_presses: u8,
     action_presses: u8,
-    dma_cycles: u32,
+    oam_dma: OamDma,
     ppu_mode: u8,
     bg_color_ram: Vec<u8>,
     obj_color_ram: Vec<u8>,
@@ -77,12 +120,16 @@ struct SaveGame {
     hdma_primed: bool,
     hdma_blocks: u8,
     hdma_active: bool,
+    hdma_general: bool,
+    hdma_block_bytes: usize,
     hdma_current_dest_addr: usize,
     hdma_current_source_addr: usize,
-    valid_io: Vec<bool>,
     cpu: CentralProcessingUnit,
     ppu: PictureProcessingUnit,
     timer: Timer,
+    serial: SerialPort,
+    dmg_compat: bool,
+    sgb: Option<Sgb>,
 }
 
 pub struct MemoryUnit {
@@ -106,11 +153,11 @@ pub struct MemoryUnit {
     cartridge_type: CartType,
     available_rom_banks: usize,
     available_ram_banks: u8,
-    hold_mem: Vec<u8>,
+    boot_rom: Vec<u8>,
     in_boot_rom: bool,
     pub directional_presses: u8,
     pub action_presses: u8,
-    dma_cycles: u32,
+    oam_dma: OamDma,
     pub ppu_mode: u8,
     bg_color_ram: Vec<u8>,
     obj_color_ram: Vec<u8>,
@@ -122,18 +169,14 @@ pub struct MemoryUnit {
     hdma_primed: bool,
     hdma_blocks: u8,
     hdma_active: bool,
+    hdma_general: bool,
+    hdma_block_bytes: usize,
     hdma_current_dest_addr: usize,
     hdma_current_source_addr: usize,
-    valid_io: Vec<bool>,
 }
 
 impl MemoryUnit {
     pub fn new() -> MemoryUnit {
-        let mut valid_io = vec![true; 0x80];
-        for ind in NON_BLOCK_INVALID_IO.iter() {
-            valid_io[*ind] = false;
-        }
-        valid_io[0x4C..0x80].copy_from_slice(&[false; (0x80 - 0x4C)]);
         MemoryUnit {
             rom: Vec::new(),
             vram_0: vec![0; VRAM_SIZE],
@@ -155,11 +198,11 @@ impl MemoryUnit {
             cartridge_type: CartType::Uninitialized,
             available_rom_banks: 0,
             available_ram_banks: 0,
-            hold_mem: vec![0; 2048],
-            in_boot_rom: true,
+            boot_rom: Vec::new(),
+            in_boot_rom: false,
             directional_presses: 0xF,
             action_presses: 0xF,
-            dma_cycles: 0,
+            oam_dma: OamDma::new(),
             ppu_mode: 0,
             bg_color_ram: vec![0; 64],
             obj_color_ram: vec![0; 64],
@@ -171,16 +214,31 @@ impl MemoryUnit {
             hdma_primed: false,
             hdma_blocks: 0,
             hdma_active: false,
+            hdma_general: false,
+            hdma_block_bytes: 0,
             hdma_current_dest_addr: 0,
             hdma_current_source_addr: 0,
-            valid_io,
         }
     }
 }
 impl GameBoyEmulator {
     pub fn get_memory(&self, addr: impl Into<usize>, source: RequestSource) -> u8 {
         let addr = addr.into() as usize;
+        let mut val = self.read_memory(addr, source);
+        if addr < VRAM_START_ADDR && self.cheats.patches_rom() {
+            val = self.cheats.patch_rom(addr, val);
+        }
+        if self.watch.is_some() {
+            self.watch_access(Access::Read, addr, val, source);
+        }
+        val
+    }
+    fn read_memory(&self, addr: usize, source: RequestSource) -> u8 {
+        if source == RequestSource::CPU && self.dma_conflict(addr) {
+            return self.mem_unit.oam_dma.bus_value;
+        }
         match addr {
+            0x0000..=0x08FF if self.boot_rom_mapped(addr) => self.mem_unit.boot_rom[addr],
             0x0000..=0x3FFF => match self.mem_unit.cartridge_type {
                 CartType::RomOnly | CartType::Mbc2 | CartType::Mbc3 | CartType::Mbc5 => {
                     self.mem_unit.rom[addr]
@@ -245,7 +303,11 @@ impl GameBoyEmulator {
             }
             0xE000..=0xFDFF => self.mem_unit.internal_ram[addr - 0xE000],
             0xFE00..=0xFE9F => {
-                if source == RequestSource::MAU
+                let dma_blocked = self.mem_unit.oam_dma.source.is_some()
+                    && (source == RequestSource::CPU || source == RequestSource::PPU);
+                if dma_blocked {
+                    0xFF
+                } else if source == RequestSource::MAU
                     || source == RequestSource::PPU
                     || self.mem_unit.ppu_mode == HBLANK_MODE
                     || self.mem_unit.ppu_mode == VBLANK_MODE
@@ -256,76 +318,6 @@ impl GameBoyEmulator {
                 }
             }
 
-            NR10_ADDR => {
-                if source == RequestSource::CPU {
-                    self.mem_unit.io_registers[NR10_ADDR - IO_START_ADDR] | 0x80
-                } else {
-                    self.mem_unit.io_registers[NR10_ADDR - IO_START_ADDR]
-                }
-            }
-            NR11_ADDR => {
-                if source == RequestSource::CPU {
-                    self.mem_unit.io_registers[NR11_ADDR - IO_START_ADDR] | 0x3F
-                } else {
-                    self.mem_unit.io_registers[NR11_ADDR - IO_START_ADDR]
-                }
-            }
-            NR14_ADDR => {
-                if source == RequestSource::CPU {
-                    self.mem_unit.io_registers[NR14_ADDR - IO_START_ADDR] | 0xBF
-                } else {
-                    self.mem_unit.io_registers[NR14_ADDR - IO_START_ADDR]
-                }
-            }
-            NR21_ADDR => {
-                if source == RequestSource::CPU {
-                    self.mem_unit.io_registers[NR21_ADDR - IO_START_ADDR] | 0x3F
-                } else {
-                    self.mem_unit.io_registers[NR21_ADDR - IO_START_ADDR]
-                }
-            }
-            NR24_ADDR => {
-                if source == RequestSource::CPU {
-                    self.mem_unit.io_registers[NR24_ADDR - IO_START_ADDR] | 0xBF
-                } else {
-                    self.mem_unit.io_registers[NR24_ADDR - IO_START_ADDR]
-                }
-            }
-            NR30_ADDR => {
-                if source == RequestSource::CPU {
-                    self.mem_unit.io_registers[NR30_ADDR - IO_START_ADDR] | 0x7F
-                } else {
-                    self.mem_unit.io_registers[NR30_ADDR - IO_START_ADDR]
-                }
-            }
-            NR32_ADDR => {
-                if source == RequestSource::CPU {
-                    self.mem_unit.io_registers[NR32_ADDR - IO_START_ADDR] | 0x9F
-                } else {
-                    self.mem_unit.io_registers[NR32_ADDR - IO_START_ADDR]
-                }
-            }
-            NR34_ADDR => {
-                if source == RequestSource::CPU {
-                    self.mem_unit.io_registers[NR34_ADDR - IO_START_ADDR] | 0xBF
-                } else {
-                    self.mem_unit.io_registers[NR34_ADDR - IO_START_ADDR]
-                }
-            }
-            NR44_ADDR => {
-                if source == RequestSource::CPU {
-                    self.mem_unit.io_registers[NR44_ADDR - IO_START_ADDR] | 0xBF
-                } else {
-                    self.mem_unit.io_registers[NR44_ADDR - IO_START_ADDR]
-                }
-            }
-            NR52_ADDR => {
-                if source == RequestSource::CPU {
-                    self.mem_unit.io_registers[NR52_ADDR - IO_START_ADDR] | 0x70
-                } else {
-                    self.mem_unit.io_registers[NR52_ADDR - IO_START_ADDR]
-                }
-            }
             0xFF30..=0xFF3F => {
                 if source == RequestSource::CPU {
                     self.wave_ram_read(addr)
@@ -333,19 +325,27 @@ impl GameBoyEmulator {
                     self.mem_unit.io_registers[addr - IO_START_ADDR]
                 }
             }
-            BCPS_ADDR => {
-                self.mem_unit.bg_color_ram
-                    [self.mem_unit.io_registers[BCPS_ADDR - IO_START_ADDR] as usize]
-            }
-            BCPD_ADDR => {
-                self.mem_unit.obj_color_ram
-                    [self.mem_unit.io_registers[BCPD_ADDR - IO_START_ADDR] as usize]
-            }
             0xFF00..=0xFF7F => {
-                if self.mem_unit.valid_io[addr - IO_START_ADDR] || source != RequestSource::CPU {
-                    self.mem_unit.io_registers[addr - IO_START_ADDR]
+                let val = match addr {
+                    BCPS_ADDR | OCPS_ADDR => {
+                        // The current index lives in the data register's slot.
+                        (self.mem_unit.io_registers[addr - IO_START_ADDR] & 0x80)
+                            | self.mem_unit.io_registers[addr + 1 - IO_START_ADDR]
+                    }
+                    BCPD_ADDR => {
+                        self.mem_unit.bg_color_ram
+                            [self.mem_unit.io_registers[BCPD_ADDR - IO_START_ADDR] as usize]
+                    }
+                    OCPD_ADDR => {
+                        self.mem_unit.obj_color_ram
+                            [self.mem_unit.io_registers[OCPD_ADDR - IO_START_ADDR] as usize]
+                    }
+                    _ => self.mem_unit.io_registers[addr - IO_START_ADDR],
+                };
+                if source == RequestSource::CPU {
+                    val | self.io_read_mask(addr)
                 } else {
-                    0xFF
+                    val
                 }
             }
             0xFF80..=0xFFFE => self.mem_unit.high_ram[addr - HRAM_START_ADDR],
@@ -355,6 +355,15 @@ impl GameBoyEmulator {
     }
     pub fn write_memory(&mut self, addr: impl Into<usize>, val: u8, source: RequestSource) {
         let addr = addr.into() as usize;
+        if self.watch.is_some() {
+            self.watch_access(Access::Write, addr, val, source);
+        }
+        if source == RequestSource::CPU && self.dma_conflict(addr) {
+            return;
+        }
+        if source == RequestSource::CPU {
+            self.oam_bug(addr, OamBug::Write);
+        }
 
         match addr {
             0x0000..=0x1FFF => match self.mem_unit.cartridge_type {
@@ -528,9 +537,12 @@ impl GameBoyEmulator {
             }
             0xE000..=0xFDFF => self.mem_unit.internal_ram[addr - 0xE000] = val,
             0xFE00..=0xFE9F => {
-                if source == RequestSource::MAU
-                    || self.mem_unit.ppu_mode == HBLANK_MODE
-                    || self.mem_unit.ppu_mode == VBLANK_MODE
+                let dma_blocked =
+                    self.mem_unit.oam_dma.source.is_some() && source == RequestSource::CPU;
+                if !dma_blocked
+                    && (source == RequestSource::MAU
+                        || self.mem_unit.ppu_mode == HBLANK_MODE
+                        || self.mem_unit.ppu_mode == VBLANK_MODE)
                 {
                     self.mem_unit.oam[addr - OAM_START_ADDR] = val
                 }
@@ -538,6 +550,9 @@ impl GameBoyEmulator {
             0xFF00 => {
                 let mut p1 = self.get_memory(P1_ADDR, SOURCE);
                 let prev_p1 = p1;
+                if source == RequestSource::CPU {
+                    self.sgb_p1_write(prev_p1, val);
+                }
                 p1 &= 0b001111;
                 p1 |= val & 0b110000;
                 let p14 = (p1 >> 4) & 1;
@@ -551,6 +566,9 @@ impl GameBoyEmulator {
                 if p15 == 0 {
                     new_bits &= self.mem_unit.action_presses;
                 }
+                if let (1, 1, Some(player)) = (p14, p15, self.sgb_joypad_id()) {
+                    new_bits = 0xF - player;
+                }
                 p1 += new_bits;
                 if ((prev_p1 | p1) - p1) & 0xF != 0 {
                     self.write_memory(
@@ -561,12 +579,31 @@ impl GameBoyEmulator {
                 }
                 self.mem_unit.io_registers[addr - IO_START_ADDR] = p1;
             }
-            DIV_ADDR => {
-                self.mem_unit.io_registers[DIV_ADDR - IO_START_ADDR] = 0;
+            SC_ADDR => {
+                if source == RequestSource::Serial {
+                    self.mem_unit.io_registers[SC_ADDR - IO_START_ADDR] = val;
+                } else {
+                    self.sc_write(val);
+                }
+            }
+            DIV_ADDR..=TAC_ADDR => {
+                if source == RequestSource::Timer {
+                    self.mem_unit.io_registers[addr - IO_START_ADDR] = val;
+                } else {
+                    match addr {
+                        DIV_ADDR => self.div_write(),
+                        TIMA_ADDR => self.tima_write(val),
+                        TMA_ADDR => self.tma_write(val),
+                        TAC_ADDR => self.tac_write(val),
+                        _ => {}
+                    }
+                }
             }
             0xFF10..=0xFF2F => {
                 if addr == NR52_ADDR {
-                    if source == RequestSource::CPU {
+                    // Skipping the boot ROM powers the APU up the way the boot ROM's own write
+                    // does.
+                    if matches!(source, RequestSource::CPU | RequestSource::MAU) {
                         let old_power_val = self.mem_unit.io_registers[0x26] >> 7;
                         self.mem_unit.io_registers[NR52_ADDR - IO_START_ADDR] &= 0x7F;
                         if (val >> 7) == 0 {
@@ -684,7 +721,7 @@ impl GameBoyEmulator {
             }
             HDMA5_ADDR => {
                 if self.mem_unit.cgb {
-                    if self.mem_unit.hdma_active {
+                    if self.mem_unit.hdma_active && !self.mem_unit.hdma_general {
                         if (val >> 7) == 0 {
                             self.mem_unit.hdma_active = false;
                             self.mem_unit.io_registers[HDMA5_ADDR - IO_START_ADDR] |= 0x80;
@@ -731,70 +768,196 @@ impl GameBoyEmulator {
                         0b11111000 | self.mem_unit.wram_bank as u8;
                 }
             }
-            0xFF00..=0xFF7F => self.mem_unit.io_registers[addr - IO_START_ADDR] = val,
+            0xFF00..=0xFF7F => {
+                let reg = &mut self.mem_unit.io_registers[addr - IO_START_ADDR];
+                if source == RequestSource::CPU {
+                    let mask = IO_WRITE_MASKS[addr - IO_START_ADDR];
+                    *reg = (*reg & !mask) | (val & mask);
+                } else {
+                    *reg = val;
+                }
+            }
             0xFF80..=0xFFFE => self.mem_unit.high_ram[addr - HRAM_START_ADDR] = val,
             INT_ENABLE_ADDR => self.mem_unit.interrupt_enable = val,
             _ => {}
         }
     }
 
+    /// Starts an OAM DMA from `reg` * 0x100 after `OAM_DMA_START_DELAY` M-cycles. A transfer
+    /// that is already running carries on until then. Sources from 0xE000 read the echo of
+    /// WRAM.
     fn dma_transfer(&mut self, reg: usize) {
         let start_address = reg << 8;
-        match reg >> 4 {
-            0x0..=0x3 => {
-                let end_address = (start_address + DMA_LENGTH) as usize;
-                self.mem_unit
-                    .oam
-                    .copy_from_slice(&self.mem_unit.rom[start_address..end_address]);
+        self.mem_unit.oam_dma.pending = Some(if start_address >= ECHO_RAM_START_ADDR {
+            start_address - (ECHO_RAM_START_ADDR - WRAM_START_ADDR)
+        } else {
+            start_address
+        });
+        self.mem_unit.oam_dma.delay = OAM_DMA_START_DELAY;
+    }
+    /// Writes to 0xD000-0xDFFF in WRAM bank `bank` whichever bank is mapped.
+    pub fn write_wram_bank(&mut self, bank: usize, addr: usize, val: u8) {
+        let bank = bank.max(1);
+        self.mem_unit.internal_ram
+            [addr - WRAM_START_ADDR - WRAM_BANK_SIZE + WRAM_BANK_SIZE * bank] = val;
+    }
+    fn searchable_wram_len(&self) -> usize {
+        if self.cgb {
+            IRAM_SIZE
+        } else {
+            2 * WRAM_BANK_SIZE
+        }
+    }
+    /// WRAM (every bank the model has), cartridge RAM and HRAM back to back, for the RAM
+    /// search.
+    pub fn searchable_ram(&self) -> Vec<u8> {
+        let mut ram = self.mem_unit.internal_ram[..self.searchable_wram_len()].to_vec();
+        ram.extend_from_slice(&self.mem_unit.external_ram);
+        ram.extend_from_slice(&self.mem_unit.high_ram);
+        ram
+    }
+    pub fn searchable_ram_len(&self) -> usize {
+        self.searchable_wram_len() + self.mem_unit.external_ram.len() + HRAM_SIZE
+    }
+    /// Where byte `offset` of `searchable_ram` shows up in the memory map.
+    pub fn searchable_location(&self, offset: usize) -> RamLocation {
+        let wram_len = self.searchable_wram_len();
+        let eram_len = self.mem_unit.external_ram.len();
+        if offset < WRAM_BANK_SIZE {
+            RamLocation {
+                region: RamRegion::Wram,
+                bank: 0,
+                addr: WRAM_START_ADDR + offset,
             }
-            0x4..=0x7 => {
-                let adjusted_start_address =
-                    start_address - ROM_BANK_SIZE + ROM_BANK_SIZE * self.mem_unit.rom_bank;
-                let adjusted_end_address = adjusted_start_address + DMA_LENGTH;
-                self.mem_unit.oam.copy_from_slice(
-                    &self.mem_unit.rom[adjusted_start_address..adjusted_end_address],
-                );
+        } else if offset < wram_len {
+            RamLocation {
+                region: RamRegion::Wram,
+                bank: offset / WRAM_BANK_SIZE,
+                addr: WRAM_START_ADDR + WRAM_BANK_SIZE + offset % WRAM_BANK_SIZE,
             }
-            0x8..=0x9 => {
-                let adjusted_start_address = start_address - VRAM_START_ADDR;
-                let adjusted_end_address = adjusted_start_address + DMA_LENGTH;
-                if self.mem_unit.vram_bank == 0 {
-                    self.mem_unit.oam.copy_from_slice(
-                        &self.mem_unit.vram_0[adjusted_start_address..adjusted_end_address],
-                    );
-                } else {
-                    self.mem_unit.oam.copy_from_slice(
-                        &self.mem_unit.vram_1[adjusted_start_address..adjusted_end_address],
-                    );
-                }
+        } else if offset < wram_len + eram_len {
+            RamLocation {
+                region: RamRegion::Eram,
+                bank: (offset - wram_len) / ERAM_BANK_SIZE,
+                addr: ERAM_START_ADDR + (offset - wram_len) % ERAM_BANK_SIZE,
+            }
+        } else {
+            RamLocation {
+                region: RamRegion::Hram,
+                bank: 0,
+                addr: HRAM_START_ADDR + offset - wram_len - eram_len,
             }
-            0xA..=0xB => {
-                let adjusted_start_address =
-                    start_address - ERAM_START_ADDR + ERAM_BANK_SIZE * (self.mem_unit.ram_bank);
-                let adjusted_end_address = adjusted_start_address + DMA_LENGTH;
-                self.mem_unit.oam.copy_from_slice(
-                    &self.mem_unit.external_ram[adjusted_start_address..adjusted_end_address],
-                );
+        }
+    }
+    /// The ROM bank mapped at 0x4000-0x7FFF.
+    pub fn rom_bank(&self) -> usize {
+        self.mem_unit.rom_bank
+    }
+    /// The cartridge RAM bank mapped at 0xA000-0xBFFF.
+    pub fn eram_bank(&self) -> usize {
+        self.mem_unit.ram_bank
+    }
+    /// LY without going through `get_memory`, so it never shows up in a memory watch.
+    pub fn current_ly(&self) -> u8 {
+        self.mem_unit.io_registers[LY_ADDR - IO_START_ADDR]
+    }
+    fn io_read_mask(&self, addr: usize) -> u8 {
+        if self.mem_unit.cgb {
+            CGB_IO_READ_MASKS[addr - IO_START_ADDR]
+        } else {
+            DMG_IO_READ_MASKS[addr - IO_START_ADDR]
+        }
+    }
+    fn memory_bus(&self, addr: usize) -> Bus {
+        match addr {
+            0x8000..=0x9FFF => Bus::Video,
+            0xC000..=0xFDFF if self.mem_unit.cgb => Bus::Wram,
+            0x0000..=0xFDFF => Bus::External,
+            _ => Bus::Internal,
+        }
+    }
+    /// Whether a CPU access to `addr` collides with a running OAM DMA on the same bus, in
+    /// which case reads see the byte being transferred and writes are lost. HRAM and the IO
+    /// registers are on their own bus and stay accessible.
+    fn dma_conflict(&self, addr: usize) -> bool {
+        match self.mem_unit.oam_dma.source {
+            Some(source) => {
+                self.memory_bus(addr) != Bus::Internal
+                    && self.memory_bus(addr) == self.memory_bus(source)
             }
-            0xC => {
-                let adjusted_start_address = (start_address - WRAM_START_ADDR) as usize;
-                let adjusted_end_address = adjusted_start_address + DMA_LENGTH;
-                self.mem_unit.oam.copy_from_slice(
-                    &self.mem_unit.internal_ram[adjusted_start_address..adjusted_end_address],
-                );
+            None => false,
+        }
+    }
+    fn oam_word(&self, row: usize, word: usize) -> u16 {
+        let index = row * OAM_ROW_LENGTH + word * 2;
+        combine_bytes(self.mem_unit.oam[index + 1], self.mem_unit.oam[index])
+    }
+    fn set_oam_word(&mut self, row: usize, word: usize, val: u16) {
+        let index = row * OAM_ROW_LENGTH + word * 2;
+        let (high, low) = split_u16(val);
+        self.mem_unit.oam[index] = low;
+        self.mem_unit.oam[index + 1] = high;
+    }
+    fn copy_oam_row(&mut self, from: usize, to: usize, start_word: usize) {
+        let start = start_word * 2;
+        self.mem_unit.oam.copy_within(
+            from * OAM_ROW_LENGTH + start..(from + 1) * OAM_ROW_LENGTH,
+            to * OAM_ROW_LENGTH + start,
+        );
+    }
+    /// Applies the DMG OAM corruption bug for an access to `addr`. The row the PPU is
+    /// currently scanning is scrambled with the row before it.
+    pub fn oam_bug(&mut self, addr: impl Into<usize>, kind: OamBug) {
+        let addr = addr.into();
+        if !self.hardware.has_oam_bug()
+            || !(OAM_START_ADDR..=OAM_BUG_END_ADDR).contains(&addr)
+            || self.mem_unit.ppu_mode != OAM_SEARCH_MODE
+        {
+            return;
+        }
+        let row = self.ppu.cycle_count as usize / ADVANCE_CYCLES as usize;
+        if row == 0 || row >= OAM_ROWS {
+            return;
+        }
+        if kind == OamBug::ReadIncrease && (4..OAM_ROWS - 1).contains(&row) {
+            let a = self.oam_word(row - 2, 0);
+            let b = self.oam_word(row - 1, 0);
+            let c = self.oam_word(row, 0);
+            let d = self.oam_word(row - 1, 2);
+            self.set_oam_word(row - 1, 0, (b & (a | c | d)) | (a & c & d));
+            self.copy_oam_row(row - 1, row, 0);
+            self.copy_oam_row(row - 1, row - 2, 0);
+        }
+        let a = self.oam_word(row, 0);
+        let b = self.oam_word(row - 1, 0);
+        let c = self.oam_word(row - 1, 2);
+        let corrupted = match kind {
+            OamBug::Write => ((a ^ c) & (b ^ c)) ^ c,
+            OamBug::Read | OamBug::ReadIncrease => b | (a & c),
+        };
+        self.set_oam_word(row, 0, corrupted);
+        self.copy_oam_row(row - 1, row, 1);
+    }
+    fn oam_dma_tick(&mut self) {
+        if let Some(pending) = self.mem_unit.oam_dma.pending {
+            if self.mem_unit.oam_dma.delay == 0 {
+                self.mem_unit.oam_dma.source = Some(pending);
+                self.mem_unit.oam_dma.index = 0;
+                self.mem_unit.oam_dma.pending = None;
+            } else {
+                self.mem_unit.oam_dma.delay -= 1;
             }
-            0xD => {
-                let adjusted_start_address = (start_address - WRAM_START_ADDR - WRAM_BANK_SIZE
-                    + WRAM_BANK_SIZE * self.mem_unit.wram_bank)
-                    as usize;
-                let adjusted_end_address = adjusted_start_address + DMA_LENGTH;
-                self.mem_unit.oam.copy_from_slice(
-                    &self.mem_unit.internal_ram[adjusted_start_address..adjusted_end_address],
-                );
+        }
+        if let Some(source) = self.mem_unit.oam_dma.source {
+            let index = self.mem_unit.oam_dma.index;
+            let byte = self.get_memory(source + index, SOURCE);
+            self.mem_unit.oam_dma.bus_value = byte;
+            self.mem_unit.oam[index] = byte;
+            self.mem_unit.oam_dma.index += 1;
+            if self.mem_unit.oam_dma.index == DMA_LENGTH {
+                self.mem_unit.oam_dma.source = None;
             }
-            _ => {}
         }
-        self.mem_unit.dma_cycles = 640;
     }
     fn hdma_transfer(&mut self) {
         let hdma5 = self.get_memory(HDMA5_ADDR, SOURCE);
@@ -810,65 +973,28 @@ impl GameBoyEmulator {
             & 0x1FF0)
             | 0x8000;
         self.mem_unit.hdma_blocks = (hdma5 & 0x7F) + 1;
-        let gen_dma = (hdma5 >> 7) == 0;
-        if gen_dma {
-            for _ in 0..self.mem_unit.hdma_blocks {
-                self.hdma_block_transfer()
-            }
-        } else {
-            self.mem_unit.hdma_active = true;
-            self.mem_unit.hdma_primed = true;
-            self.mem_unit.io_registers[HDMA5_ADDR - IO_START_ADDR] &= 0x7F;
-        }
+        self.mem_unit.hdma_active = true;
+        self.mem_unit.hdma_general = (hdma5 >> 7) == 0;
+        self.mem_unit.hdma_primed = true;
+        self.mem_unit.io_registers[HDMA5_ADDR - IO_START_ADDR] = hdma5 & 0x7F;
     }
-    pub fn hdma_block_transfer(&mut self) {
-        let high_nibble_source = self.mem_unit.hdma_current_source_addr >> 12;
-        let copy_data = match high_nibble_source {
-            0x0..=0x3 => {
-                &self.mem_unit.rom[self.mem_unit.hdma_current_source_addr
-                    ..(self.mem_unit.hdma_current_source_addr + HDMA_BLOCK_LENGTH)]
-            }
-            0x4..=0x7 => {
-                let adjusted_address = self.mem_unit.hdma_current_source_addr
-                    + ROM_BANK_SIZE * self.mem_unit.rom_bank
-                    - ROM_BANK_SIZE;
-                &self.mem_unit.rom[adjusted_address..(adjusted_address + HDMA_BLOCK_LENGTH)]
-            }
-            0xA..=0xB => {
-                let adjusted_address = self.mem_unit.hdma_current_source_addr - ERAM_START_ADDR
-                    + ERAM_BANK_SIZE * self.mem_unit.ram_bank;
-                &self.mem_unit.external_ram
-                    [adjusted_address..(adjusted_address + HDMA_BLOCK_LENGTH)]
-            }
-            0xC => {
-                let adjusted_address = self.mem_unit.hdma_current_source_addr - WRAM_START_ADDR;
-                &self.mem_unit.internal_ram
-                    [adjusted_address..(adjusted_address + HDMA_BLOCK_LENGTH)]
-            }
-            0xD => {
-                let adjusted_address =
-                    self.mem_unit.hdma_current_source_addr - WRAM_START_ADDR - WRAM_BANK_SIZE
-                        + WRAM_BANK_SIZE * self.mem_unit.wram_bank;
-                &self.mem_unit.internal_ram
-                    [adjusted_address..(adjusted_address + HDMA_BLOCK_LENGTH)]
-            }
-            _ => {
-                panic!("HDMA BAD ADDRESS");
-            }
+    fn hdma_copy_byte(&mut self) {
+        let byte = match self.mem_unit.hdma_current_source_addr >> 12 {
+            0x8..=0x9 | 0xE..=0xF => 0xFF,
+            _ => self.get_memory(self.mem_unit.hdma_current_source_addr, SOURCE),
         };
-        let adjusted_dest_address = self.mem_unit.hdma_current_dest_addr - VRAM_START_ADDR;
+        let dest = (self.mem_unit.hdma_current_dest_addr & 0x1FFF) | VRAM_START_ADDR;
         if self.mem_unit.vram_bank == 0 {
-            self.mem_unit.vram_0
-                [adjusted_dest_address..(adjusted_dest_address + HDMA_BLOCK_LENGTH)]
-                .copy_from_slice(copy_data);
-        }
-        if self.mem_unit.vram_bank == 1 {
-            self.mem_unit.vram_1
-                [adjusted_dest_address..(adjusted_dest_address + HDMA_BLOCK_LENGTH)]
-                .copy_from_slice(copy_data);
+            self.mem_unit.vram_0[dest - VRAM_START_ADDR] = byte;
+        } else {
+            self.mem_unit.vram_1[dest - VRAM_START_ADDR] = byte;
         }
-        self.mem_unit.hdma_current_source_addr += HDMA_BLOCK_LENGTH;
-        self.mem_unit.hdma_current_dest_addr += HDMA_BLOCK_LENGTH;
+        self.mem_unit.hdma_current_source_addr =
+            (self.mem_unit.hdma_current_source_addr + 1) & 0xFFFF;
+        self.mem_unit.hdma_current_dest_addr = dest + 1;
+        self.mem_unit.hdma_block_bytes -= 1;
+    }
+    fn hdma_block_done(&mut self) {
         let (source_high, source_low) = split_u16(self.mem_unit.hdma_current_source_addr as u16);
         self.write_memory(HDMA1_ADDR, source_high, SOURCE);
         self.write_memory(HDMA2_ADDR, source_low, SOURCE);
@@ -877,26 +1003,48 @@ impl GameBoyEmulator {
         self.write_memory(HDMA3_ADDR, dest_high, SOURCE);
         self.write_memory(HDMA4_ADDR, dest_low, SOURCE);
         self.mem_unit.hdma_blocks -= 1;
-        self.mem_unit.io_registers[HDMA5_ADDR - IO_START_ADDR] = self.mem_unit.hdma_blocks;
-        self.cpu.waiting = true;
-        self.cpu.cycle_goal += if self.double_speed { 64 } else { 32 };
         if self.mem_unit.hdma_blocks == 0 {
             self.mem_unit.hdma_active = false;
+            self.mem_unit.hdma_general = false;
             self.mem_unit.io_registers[HDMA5_ADDR - IO_START_ADDR] = 0xFF;
+        } else {
+            self.mem_unit.io_registers[HDMA5_ADDR - IO_START_ADDR] =
+                (self.mem_unit.hdma_blocks - 1) & 0x7F;
         }
     }
-    pub fn dma_tick(&mut self) {
-        if self.mem_unit.dma_cycles > 0 {
-            self.mem_unit.dma_cycles -= ADVANCE_CYCLES;
-        }
-        if self.mem_unit.hdma_active {
-            if self.mem_unit.hdma_primed && self.mem_unit.ppu_mode == HBLANK_MODE {
-                self.hdma_block_transfer();
-                self.mem_unit.hdma_primed = false;
-            } else if !self.mem_unit.hdma_primed && self.mem_unit.ppu_mode != HBLANK_MODE {
+    fn hdma_tick(&mut self) {
+        if self.mem_unit.hdma_block_bytes == 0 && self.mem_unit.hdma_active {
+            let lcd_on = self.get_memory(LCDC_ADDR, SOURCE) >> 7 == 1;
+            let in_hblank = self.mem_unit.ppu_mode == HBLANK_MODE;
+            if self.mem_unit.hdma_general {
+                self.mem_unit.hdma_block_bytes = HDMA_BLOCK_LENGTH;
+            } else if !in_hblank {
                 self.mem_unit.hdma_primed = true;
+            } else if self.mem_unit.hdma_primed && lcd_on && !self.cpu.halting {
+                self.mem_unit.hdma_block_bytes = HDMA_BLOCK_LENGTH;
+                self.mem_unit.hdma_primed = false;
             }
         }
+        if self.mem_unit.hdma_block_bytes > 0 {
+            let bytes = if self.double_speed {
+                HDMA_BYTES_PER_CYCLE / 2
+            } else {
+                HDMA_BYTES_PER_CYCLE
+            };
+            for _ in 0..bytes {
+                self.hdma_copy_byte();
+            }
+            if self.mem_unit.hdma_block_bytes == 0 {
+                self.hdma_block_done();
+            }
+        }
+    }
+    pub fn hdma_stalling(&self) -> bool {
+        self.mem_unit.hdma_block_bytes > 0 || self.mem_unit.hdma_general
+    }
+    pub fn dma_tick(&mut self) {
+        self.oam_dma_tick();
+        self.hdma_tick();
     }
     pub fn access_vram(&self, addr: impl Into<usize>, bank: u8) -> u8 {
         let addr = addr.into() as usize;
@@ -912,16 +1060,8 @@ impl GameBoyEmulator {
         for i in 0..4 {
             let color_data = self.mem_unit.bg_color_ram[index] as u16
                 + ((self.mem_unit.bg_color_ram[index + 1] as u16) << 8);
-            let red = (color_data & 0x1F) as u8;
-            let green = ((color_data >> 5) & 0x1F) as u8;
-            let blue = ((color_data >> 10) & 0x1F) as u8;
             index += 2;
-            out[i] = [
-                convert_to_8_bit(red),
-                convert_to_8_bit(green),
-                convert_to_8_bit(blue),
-                0xFF,
-            ]
+            out[i] = self.color_table[(color_data & 0x7FFF) as usize];
         }
         out
     }
@@ -931,16 +1071,8 @@ impl GameBoyEmulator {
         for i in 0..4 {
             let color_data = self.mem_unit.obj_color_ram[index] as u16
                 + ((self.mem_unit.obj_color_ram[index + 1] as u16) << 8);
-            let red = (color_data & 0x1F) as u8;
-            let green = ((color_data >> 5) & 0x1F) as u8;
-            let blue = ((color_data >> 10) & 0x1F) as u8;
             index += 2;
-            out[i] = [
-                convert_to_8_bit(red),
-                convert_to_8_bit(green),
-                convert_to_8_bit(blue),
-                0xFF,
-            ]
+            out[i] = self.color_table[(color_data & 0x7FFF) as usize];
         }
         out
     }
@@ -965,32 +1097,124 @@ impl GameBoyEmulator {
         self.mem_unit.io_registers[HDMA5_ADDR - IO_START_ADDR] = 0xFF;
 
         self.mem_unit.io_registers[BGP_ADDR - IO_START_ADDR] = 0xFC;
-        self.mem_unit.bg_color_ram.copy_from_slice(&[255; 64]);
+        if !self.dmg_compat {
+            self.mem_unit.bg_color_ram.copy_from_slice(&[255; 64]);
+        }
         self.write_memory(SVBK_ADDR, 1, SOURCE);
         self.mem_unit.interrupt_enable = 0;
     }
+    /// Whether a read from `addr` goes to the boot ROM instead of the cartridge.
+    fn boot_rom_mapped(&self, addr: usize) -> bool {
+        self.mem_unit.in_boot_rom
+            && (addr < DMG_BOOT_ROM_SIZE
+                || (CGB_BOOT_ROM_GAP_END..self.mem_unit.boot_rom.len()).contains(&addr))
+    }
     fn load_boot_rom(&mut self) {
-        self.mem_unit.hold_mem[..0x100].copy_from_slice(&self.mem_unit.rom[..0x100]);
-        if self.mem_unit.cgb {
-            self.mem_unit.hold_mem[0x100..0x800].copy_from_slice(&self.mem_unit.rom[0x200..0x900]);
-            self.mem_unit.rom[..0x100].copy_from_slice(&CGB_BOOTROM_1);
-            self.mem_unit.rom[0x200..0x900].copy_from_slice(&CGB_BOOTROM_2);
+        if self.boot_roms.skip {
+            self.mem_unit.boot_rom = Vec::new();
+            self.mem_unit.in_boot_rom = false;
+            self.skip_boot_rom();
         } else {
-            self.mem_unit.rom[..0x100].copy_from_slice(&DMG_BOOTROM);
+            self.mem_unit.boot_rom = self.boot_roms.image(self.hardware.boot_rom());
+            self.mem_unit.in_boot_rom = true;
         }
     }
     fn unload_boot_rom(&mut self) {
-        self.mem_unit.rom[..0x100].copy_from_slice(&self.mem_unit.hold_mem[..0x100]);
-        if self.mem_unit.cgb {
-            self.mem_unit.rom[0x200..0x900].copy_from_slice(&self.mem_unit.hold_mem[0x100..0x800]);
+        self.mem_unit.in_boot_rom = false;
+        if self.boot_roms.stands_in(self.hardware.boot_rom()) {
+            self.cpu_initialize_after_boot();
+        }
+        self.finish_boot();
+    }
+    fn finish_boot(&mut self) {
+        // The CGB boot ROM sets KEY0 bit 2 when it has colorized a DMG cartridge, which
+        // locks the rest of the CGB hardware out until the next reset.
+        if self.mem_unit.cgb
+            && (self.mem_unit.io_registers[KEY0_ADDR - IO_START_ADDR] >> 2) & 1 == 1
+        {
+            self.dmg_compat = true;
         }
-        self.cpu_initialize_after_boot();
         self.memory_initialize_after_boot();
+        if self.dmg_compat {
+            self.cgb = false;
+            self.mem_unit.cgb = false;
+        }
+    }
+    /// Leaves the machine the way the boot ROM would and starts the cartridge at 0x100.
+    fn skip_boot_rom(&mut self) {
+        if self.mem_unit.cgb && (self.mem_unit.rom[CGB_FLAG_ADDR] >> 7) == 0 {
+            self.mem_unit.io_registers[KEY0_ADDR - IO_START_ADDR] = CGB_COMPAT_KEY0;
+            let [bg, obj0, obj1] = self
+                .boot_roms
+                .compat_palettes(self.hardware.boot_rom(), &self.mem_unit.rom);
+            // The boot ROM whitens every BG palette before loading the first one.
+            for color in self.mem_unit.bg_color_ram.chunks_mut(2) {
+                color.copy_from_slice(&[0xFF, 0x7F]);
+            }
+            let (obj0_ram, obj1_ram) = self.mem_unit.obj_color_ram[..16].split_at_mut(8);
+            let palettes = [
+                (&mut self.mem_unit.bg_color_ram[..8], bg),
+                (obj0_ram, obj0),
+                (obj1_ram, obj1),
+            ];
+            for (palette_ram, palette) in palettes {
+                for (i, color) in palette.iter().enumerate() {
+                    palette_ram[i * 2..i * 2 + 2].copy_from_slice(&color.to_le_bytes());
+                }
+            }
+        }
+        if self.hardware.draws_boot_logo() {
+            self.draw_boot_logo();
+        }
+        self.write_memory(NR52_ADDR, 0x80, SOURCE);
+        for (addr, val) in POST_BOOT_APU_REGISTERS.iter() {
+            self.write_memory(*addr, *val, SOURCE);
+        }
+        self.set_divider(if self.mem_unit.cgb {
+            CGB_POST_BOOT_DIVIDER
+        } else {
+            DMG_POST_BOOT_DIVIDER
+        });
+        self.cpu_initialize_after_boot();
+        self.finish_boot();
+    }
+    /// Draws the cartridge logo and the ® into VRAM, where the DMG boot ROM leaves them.
+    fn draw_boot_logo(&mut self) {
+        let logo = self.mem_unit.rom[LOGO_START_ADDR..LOGO_END_ADDR].to_vec();
+        let vram = &mut self.mem_unit.vram_0;
+        // Each logo nibble becomes a byte with every bit doubled, drawn on two rows of
+        // tiles 1-24.
+        let mut index = BYTES_PER_TILE;
+        for byte in logo {
+            for nibble in [byte >> 4, byte & 0xF] {
+                let doubled = (0..4).fold(0, |acc, bit| {
+                    acc | (((nibble >> bit) & 1) * 0b11) << (bit * 2)
+                });
+                vram[index] = doubled;
+                vram[index + BYTES_PER_TILE_ROW] = doubled;
+                index += 2 * BYTES_PER_TILE_ROW;
+            }
+        }
+        for (i, byte) in DMG_BOOTROM[0xD8..0xE0].iter().enumerate() {
+            vram[index + i * BYTES_PER_TILE_ROW] = *byte;
+        }
+        let map = TILE_MAP_1_START_ADDR - VRAM_START_ADDR;
+        vram[map + 0x110] = 0x19;
+        for i in 0..12 {
+            vram[map + 0x12F - i] = 0x18 - i as u8;
+            vram[map + 0x10F - i] = 0x0C - i as u8;
+        }
     }
 
     pub fn load_rom(&mut self, path: &Path) {
         let mut f = File::open(path).expect("File problem!");
-        f.read_to_end(&mut self.mem_unit.rom).expect("Read issue!");
+        let mut rom = Vec::new();
+        f.read_to_end(&mut rom).expect("Read issue!");
+        self.load_rom_data(rom);
+        self.load_cheats(&path.with_extension("cht"));
+    }
+    pub fn load_rom_data(&mut self, rom: Vec<u8>) {
+        self.mem_unit.rom = rom;
         self.mem_unit.cartridge_type = match self.mem_unit.rom[CART_TYPE_ADDR] {
             0 => CartType::RomOnly,
             1..=3 => CartType::Mbc1,
@@ -1012,16 +1236,43 @@ impl GameBoyEmulator {
             .external_ram
             .extend(vec![0; 0x2000 * self.mem_unit.available_ram_banks as usize]);
         self.mem_unit.rom_bank_bits = (self.get_memory(ROM_BANK_ADDR, SOURCE) + 1) as usize;
-        self.cgb = (self.mem_unit.rom[0x143] >> 7) == 1;
+        self.hardware = self.model.resolve(self.mem_unit.rom[CGB_FLAG_ADDR]);
+        self.cgb = self.hardware.is_cgb();
+        self.dmg_compat = false;
         self.mem_unit.cgb = self.cgb;
-        if self.cgb {
-            for ind in NON_BLOCK_CGB_VALID_IO.iter() {
-                self.mem_unit.valid_io[*ind] = true;
-            }
-        }
+        // The SGB only listens for packets from games that ask for its features.
+        self.sgb = if self.hardware.is_sgb()
+            && self.mem_unit.rom[SGB_FLAG_ADDR] == SGB_SUPPORT_FLAG
+            && self.mem_unit.rom[OLD_LICENSEE_ADDR] == SGB_LICENSEE
+        {
+            Some(Sgb::new())
+        } else {
+            None
+        };
         self.load_boot_rom();
     }
 
+    /// The title from the cartridge header, with anything that could upset a file name
+    /// replaced by underscores.
+    pub fn rom_title(&self) -> String {
+        let title: String = self.mem_unit.rom[ROM_TITLE_START_ADDR..ROM_TITLE_END_ADDR]
+            .iter()
+            .take_while(|&&byte| byte != 0)
+            .map(|&byte| {
+                if byte.is_ascii_alphanumeric() {
+                    byte as char
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        if title.is_empty() {
+            String::from("UNTITLED")
+        } else {
+            title
+        }
+    }
+
     pub fn save_game(&self, path: &Path) {
         let save_file = File::create(&path).unwrap();
         let save_data = SaveGame {
@@ -1044,11 +1295,11 @@ impl GameBoyEmulator {
             cartridge_type: self.mem_unit.cartridge_type,
             available_rom_banks: self.mem_unit.available_rom_banks,
             available_ram_banks: self.mem_unit.available_ram_banks,
-            hold_mem: self.mem_unit.hold_mem.clone(),
+            boot_rom: self.mem_unit.boot_rom.clone(),
             in_boot_rom: self.mem_unit.in_boot_rom,
             directional_presses: self.mem_unit.directional_presses,
             action_presses: self.mem_unit.action_presses,
-            dma_cycles: self.mem_unit.dma_cycles,
+            oam_dma: self.mem_unit.oam_dma,
             ppu_mode: self.mem_unit.ppu_mode,
             bg_color_ram: self.mem_unit.bg_color_ram.clone(),
             obj_color_ram: self.mem_unit.obj_color_ram.clone(),
@@ -1060,12 +1311,16 @@ impl GameBoyEmulator {
             hdma_primed: self.mem_unit.hdma_primed,
             hdma_blocks: self.mem_unit.hdma_blocks,
             hdma_active: self.mem_unit.hdma_active,
+            hdma_general: self.mem_unit.hdma_general,
+            hdma_block_bytes: self.mem_unit.hdma_block_bytes,
             hdma_current_dest_addr: self.mem_unit.hdma_current_dest_addr,
             hdma_current_source_addr: self.mem_unit.hdma_current_source_addr,
-            valid_io: self.mem_unit.valid_io.clone(),
             cpu: self.cpu,
             ppu: self.ppu.clone(),
             timer: self.timer,
+            serial: self.serial,
+            dmg_compat: self.dmg_compat,
+            sgb: self.sgb.clone(),
         };
         bincode::serialize_into(save_file, &save_data).unwrap();
     }
@@ -1091,11 +1346,11 @@ impl GameBoyEmulator {
         self.mem_unit.cartridge_type = open_data.cartridge_type;
         self.mem_unit.available_rom_banks = open_data.available_rom_banks;
         self.mem_unit.available_ram_banks = open_data.available_ram_banks;
-        self.mem_unit.hold_mem = open_data.hold_mem;
+        self.mem_unit.boot_rom = open_data.boot_rom;
         self.mem_unit.in_boot_rom = open_data.in_boot_rom;
         self.mem_unit.directional_presses = open_data.directional_presses;
         self.mem_unit.action_presses = open_data.action_presses;
-        self.mem_unit.dma_cycles = open_data.dma_cycles;
+        self.mem_unit.oam_dma = open_data.oam_dma;
         self.mem_unit.ppu_mode = open_data.ppu_mode;
         self.mem_unit.bg_color_ram = open_data.bg_color_ram;
         self.mem_unit.obj_color_ram = open_data.obj_color_ram;
@@ -1104,14 +1359,413 @@ impl GameBoyEmulator {
         self.mem_unit.vram_bank = open_data.vram_bank;
         self.mem_unit.wram_bank = open_data.wram_bank;
         self.mem_unit.cgb = open_data.cgb;
+        self.cgb = open_data.cgb;
+        self.dmg_compat = open_data.dmg_compat;
         self.mem_unit.hdma_primed = open_data.hdma_primed;
         self.mem_unit.hdma_blocks = open_data.hdma_blocks;
         self.mem_unit.hdma_active = open_data.hdma_active;
+        self.mem_unit.hdma_general = open_data.hdma_general;
+        self.mem_unit.hdma_block_bytes = open_data.hdma_block_bytes;
         self.mem_unit.hdma_current_dest_addr = open_data.hdma_current_dest_addr;
         self.mem_unit.hdma_current_source_addr = open_data.hdma_current_source_addr;
-        self.mem_unit.valid_io = open_data.valid_io;
         self.cpu = open_data.cpu;
         self.ppu = open_data.ppu;
         self.timer = open_data.timer;
+        self.serial = open_data.serial;
+        self.sgb = open_data.sgb;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{booted, booted_cgb, run_to_program, set_header_checksum, test_rom};
+    use crate::model::Model;
+
+    const CPU: RequestSource = RequestSource::CPU;
+
+    /// Starts a cartridge that halts with interrupts off, so the CPU never touches the bus.
+    fn halted() -> GameBoyEmulator {
+        let mut em = booted(&[0xF3, 0x76, 0x18, 0xFD]);
+        while !em.cpu.halting {
+            em.advance();
+        }
+        for i in 0..0x200 {
+            em.write_memory(WRAM_START_ADDR + i, i as u8 ^ 0x5A, RequestSource::SPEC);
+        }
+        em
+    }
+
+    #[test]
+    fn oam_dma_copies_a_byte_per_cycle() {
+        let mut em = halted();
+        em.write_memory(DMA_ADDR, 0xC0, CPU);
+        em.advance();
+        assert!(em.mem_unit.oam_dma.source.is_none());
+        for i in 0..DMA_LENGTH {
+            em.advance();
+            assert_eq!(em.mem_unit.oam[i], i as u8 ^ 0x5A);
+            if i == DMA_LENGTH - 1 {
+                break;
+            }
+            assert_eq!(em.get_memory(OAM_START_ADDR, CPU), 0xFF);
+            // On the DMG ROM and WRAM share the external bus, while VRAM and HRAM do not.
+            assert_eq!(em.get_memory(0xC1F0_usize, CPU), i as u8 ^ 0x5A);
+            assert_eq!(em.get_memory(0x0150_usize, CPU), i as u8 ^ 0x5A);
+            em.write_memory(HRAM_START_ADDR, 0x12, CPU);
+            assert_eq!(em.get_memory(HRAM_START_ADDR, CPU), 0x12);
+            assert_eq!(
+                em.get_memory(VRAM_START_ADDR, CPU),
+                em.get_memory(VRAM_START_ADDR, SOURCE)
+            );
+        }
+        assert!(em.mem_unit.oam_dma.source.is_none());
+        assert_eq!(em.get_memory(0xC1F0_usize, CPU), 0xF0 ^ 0x5A);
+        em.write_memory(WRAM_START_ADDR, 0x34, CPU);
+        assert_eq!(em.get_memory(WRAM_START_ADDR, CPU), 0x34);
+    }
+
+    #[test]
+    fn oam_dma_restarts_from_echo_ram() {
+        let mut em = halted();
+        em.write_memory(DMA_ADDR, 0xC0, CPU);
+        for _ in 0..11 {
+            em.advance();
+        }
+        // The first transfer keeps going during the start delay of the second.
+        em.write_memory(DMA_ADDR, 0xE1, CPU);
+        em.advance();
+        assert_eq!(em.mem_unit.oam[10], 10 ^ 0x5A);
+        for _ in 0..DMA_LENGTH {
+            em.advance();
+        }
+        assert!(em.mem_unit.oam_dma.source.is_none());
+        for i in 0..DMA_LENGTH {
+            assert_eq!(em.mem_unit.oam[i], (0x100 + i) as u8 ^ 0x5A);
+        }
+    }
+
+    #[test]
+    fn halted_cpu_ignores_oam_dma_conflicts() {
+        let mut em = halted();
+        let pc = em.cpu.get_pc();
+        // A fetch from ROM would see 0xDD from the DMA source, which is not an opcode.
+        for i in 0..DMA_LENGTH {
+            em.write_memory(WRAM_START_ADDR + i, 0xDD, RequestSource::SPEC);
+        }
+        em.write_memory(DMA_ADDR, 0xC0, CPU);
+        for _ in 0..=DMA_LENGTH {
+            em.advance();
+        }
+        assert!(em.cpu.halting);
+        assert_eq!(em.cpu.get_pc(), pc);
+    }
+
+    /// Starts a CGB cartridge running `program` with WRAM filled like `halted`.
+    fn cgb(program: &[u8]) -> GameBoyEmulator {
+        let mut em = booted_cgb(program);
+        for i in 0..0x200 {
+            em.write_memory(WRAM_START_ADDR + i, i as u8 ^ 0x5A, RequestSource::SPEC);
+        }
+        em
+    }
+
+    fn start_hdma(em: &mut GameBoyEmulator, hdma5: u8) {
+        em.write_memory(HDMA1_ADDR, 0xC0, CPU);
+        em.write_memory(HDMA2_ADDR, 0x00, CPU);
+        em.write_memory(HDMA3_ADDR, 0x00, CPU);
+        em.write_memory(HDMA4_ADDR, 0x00, CPU);
+        em.write_memory(HDMA5_ADDR, hdma5, CPU);
+    }
+
+    #[test]
+    fn gdma_stalls_cpu_per_block() {
+        // Keeps incrementing the first byte of HRAM.
+        let mut em = cgb(&[0xF3, 0xF0, 0x80, 0x3C, 0xE0, 0x80, 0x18, 0xF9]);
+        assert!(em.cgb);
+        start_hdma(&mut em, 0x01);
+        em.advance();
+        let counter = em.get_memory(HRAM_START_ADDR, CPU);
+        for _ in 1..2 * HDMA_BLOCK_LENGTH / HDMA_BYTES_PER_CYCLE {
+            assert!(em.hdma_stalling());
+            em.advance();
+            assert_eq!(em.get_memory(HRAM_START_ADDR, CPU), counter);
+        }
+        assert!(!em.hdma_stalling());
+        assert_eq!(em.get_memory(HDMA5_ADDR, CPU), 0xFF);
+        for i in 0..2 * HDMA_BLOCK_LENGTH {
+            assert_eq!(em.access_vram(VRAM_START_ADDR + i, 0), i as u8 ^ 0x5A);
+        }
+        for _ in 0..16 {
+            em.advance();
+        }
+        assert_ne!(em.get_memory(HRAM_START_ADDR, CPU), counter);
+    }
+
+    #[test]
+    fn hblank_dma_waits_for_halt_and_lcd() {
+        let mut em = cgb(&[0xF3, 0x76, 0x18, 0xFD]);
+        start_hdma(&mut em, 0x83);
+        em.run_headless(ADVANCES_PER_FRAME);
+        assert_eq!(em.get_memory(HDMA5_ADDR, CPU), 0x03);
+        assert_eq!(em.access_vram(VRAM_START_ADDR, 0), 0);
+    }
+
+    #[test]
+    fn hblank_dma_reports_length_and_cancels() {
+        let mut em = cgb(&[0xF3, 0xF0, 0x80, 0x3C, 0xE0, 0x80, 0x18, 0xF9]);
+        let lcdc = em.get_memory(LCDC_ADDR, CPU);
+        em.write_memory(LCDC_ADDR, lcdc & 0x7F, CPU);
+        start_hdma(&mut em, 0x83);
+        em.run_headless(ADVANCES_PER_FRAME);
+        assert_eq!(em.get_memory(HDMA5_ADDR, CPU), 0x03);
+        em.write_memory(LCDC_ADDR, lcdc, CPU);
+        while em.get_memory(HDMA5_ADDR, CPU) == 0x03 {
+            em.advance();
+        }
+        assert_eq!(em.get_memory(HDMA5_ADDR, CPU), 0x02);
+        em.write_memory(HDMA5_ADDR, 0x00, CPU);
+        assert_eq!(em.get_memory(HDMA5_ADDR, CPU), 0x82);
+        em.run_headless(ADVANCES_PER_FRAME);
+        assert_eq!(em.get_memory(HDMA5_ADDR, CPU), 0x82);
+        assert_eq!(em.access_vram(VRAM_START_ADDR + 0xF_usize, 0), 0x0F ^ 0x5A);
+        assert_eq!(em.access_vram(VRAM_START_ADDR + 0x10_usize, 0), 0);
+    }
+
+    /// Advances until the PPU is scanning OAM row `row` and fills OAM with its own indices.
+    fn scanning_row(em: &mut GameBoyEmulator, row: u32) {
+        while em.mem_unit.ppu_mode != OAM_SEARCH_MODE || em.ppu.cycle_count != row * 4 {
+            em.advance();
+        }
+        for i in 0..DMA_LENGTH {
+            em.mem_unit.oam[i] = i as u8;
+        }
+    }
+
+    #[test]
+    fn oam_bug_scrambles_the_scanned_row() {
+        let mut em = halted();
+        scanning_row(&mut em, 5);
+        em.oam_bug(0xFE80_usize, OamBug::Write);
+        // ((a ^ c) & (b ^ c)) ^ c with a = 0x2928, b = 0x2120, c = 0x2524.
+        assert_eq!(em.oam_word(5, 0), 0x2120);
+        assert_eq!(em.mem_unit.oam[42..48], [34, 35, 36, 37, 38, 39]);
+
+        scanning_row(&mut em, 5);
+        em.oam_bug(0xFEFF_usize, OamBug::Read);
+        assert_eq!(em.oam_word(5, 0), 0x2120 | (0x2928 & 0x2524));
+
+        scanning_row(&mut em, 5);
+        em.oam_bug(0xFE00_usize, OamBug::ReadIncrease);
+        let (a, b, c, d) = (0x1918, 0x2120, 0x2928, 0x2524);
+        let merged = (b & (a | c | d)) | (a & c & d);
+        for row in 3..=5 {
+            assert_eq!(em.oam_word(row, 0), merged);
+            assert_eq!(em.oam_word(row, 3), 0x2726);
+        }
+
+        // Row 0, other modes and addresses outside OAM are left alone.
+        scanning_row(&mut em, 0);
+        em.oam_bug(0xFE00_usize, OamBug::Write);
+        em.oam_bug(0xFDFF_usize, OamBug::Write);
+        while em.mem_unit.ppu_mode == OAM_SEARCH_MODE {
+            em.advance();
+        }
+        em.oam_bug(0xFE00_usize, OamBug::Write);
+        for i in 0..DMA_LENGTH {
+            assert_eq!(em.mem_unit.oam[i], i as u8);
+        }
+    }
+
+    #[test]
+    fn cgb_has_no_oam_bug() {
+        let mut em = cgb(&[0xF3, 0x76, 0x18, 0xFD]);
+        scanning_row(&mut em, 5);
+        em.oam_bug(0xFE80_usize, OamBug::Write);
+        for i in 0..DMA_LENGTH {
+            assert_eq!(em.mem_unit.oam[i], i as u8);
+        }
+    }
+
+    #[test]
+    fn io_registers_read_back_after_power_up() {
+        let mut dmg = halted();
+        let mut cgb = cgb(&[0xF3, 0x76, 0x18, 0xFD]);
+        // (register, DMG read, CGB read)
+        let expected: [(usize, u8, u8); 14] = [
+            (P1_ADDR, 0xCF, 0xCF),
+            (0xFF03, 0xFF, 0xFF),
+            (TAC_ADDR, 0xF8, 0xF8),
+            (NR13_ADDR, 0xFF, 0xFF),
+            (0xFF20, 0xFF, 0xFF),
+            (0xFF27, 0xFF, 0xFF),
+            (KEY0_ADDR, 0xFF, 0xFF),
+            (KEY1_ADDR, 0xFF, 0x7E),
+            (VBK_ADDR, 0xFF, 0xFE),
+            (HDMA1_ADDR, 0xFF, 0xFF),
+            (HDMA5_ADDR, 0xFF, 0xFF),
+            (SVBK_ADDR, 0xFF, 0xF9),
+            (0xFF71, 0xFF, 0xFF),
+            (0xFF7F, 0xFF, 0xFF),
+        ];
+        for (addr, dmg_val, cgb_val) in expected.iter() {
+            assert_eq!(dmg.get_memory(*addr, CPU), *dmg_val, "DMG {:X}", addr);
+            assert_eq!(cgb.get_memory(*addr, CPU), *cgb_val, "CGB {:X}", addr);
+        }
+        for em in [&mut dmg, &mut cgb] {
+            assert_eq!(em.get_memory(INT_FLAG_ADDR, CPU) & 0xE0, 0xE0);
+            assert_eq!(em.get_memory(STAT_ADDR, CPU) & 0x80, 0x80);
+            em.write_memory(TAC_ADDR, 0x00, CPU);
+            assert_eq!(em.get_memory(TAC_ADDR, CPU), 0xF8);
+            em.write_memory(0xFF72_usize, 0xAB, CPU);
+            em.write_memory(0xFF75_usize, 0x00, CPU);
+            em.write_memory(0xFF08_usize, 0x00, CPU);
+            assert_eq!(em.get_memory(0xFF08_usize, CPU), 0xFF);
+            let sound = [
+                (0xFF10_usize, 0x80),
+                (0xFF11_usize, 0x3F),
+                (0xFF16_usize, 0x3F),
+                (0xFF19_usize, 0xBF),
+                (0xFF1E_usize, 0xBF),
+                (0xFF23_usize, 0xBF),
+            ];
+            for (addr, val) in sound.iter() {
+                em.write_memory(*addr, 0x00, CPU);
+                assert_eq!(em.get_memory(*addr, CPU), *val, "{:X}", addr);
+            }
+        }
+        assert_eq!(dmg.get_memory(SC_ADDR, CPU), 0x7E);
+        assert_eq!(cgb.get_memory(SC_ADDR, CPU) & 0x7C, 0x7C);
+        assert_eq!(dmg.get_memory(0xFF72_usize, CPU), 0xFF);
+        assert_eq!(cgb.get_memory(0xFF72_usize, CPU), 0xAB);
+        assert_eq!(cgb.get_memory(0xFF75_usize, CPU), 0x8F);
+        cgb.write_memory(BCPS_ADDR, 0x85, CPU);
+        cgb.write_memory(BCPD_ADDR, 0x12, CPU);
+        assert_eq!(cgb.get_memory(BCPS_ADDR, CPU), 0xC6);
+        cgb.write_memory(BCPS_ADDR, 0x05, CPU);
+        assert_eq!(cgb.get_memory(BCPD_ADDR, CPU), 0x12);
+        assert_eq!(dmg.get_memory(BCPS_ADDR, CPU), 0xFF);
+    }
+
+    // Stores A, B, C, D, E, H, L and F in HRAM, then halts.
+    const DUMP_REGISTERS: [u8; 25] = [
+        0xE0, 0x80, 0x78, 0xE0, 0x81, 0x79, 0xE0, 0x82, 0x7A, 0xE0, 0x83, 0x7B, 0xE0, 0x84, 0x7C,
+        0xE0, 0x85, 0x7D, 0xE0, 0x86, 0xF5, 0xC1, 0x79, 0xE0, 0x87,
+    ];
+
+    /// Runs `DUMP_REGISTERS` on `model` after running or skipping the boot ROM. `header` is
+    /// written over the cartridge header, which is a DMG one without it.
+    fn dumped(model: Model, header: &[(usize, &[u8])], skip: bool) -> GameBoyEmulator {
+        let mut program = DUMP_REGISTERS.to_vec();
+        program.extend_from_slice(&[0xF3, 0x76, 0x18, 0xFD]);
+        let mut rom = test_rom(&program);
+        for (addr, bytes) in header {
+            rom[*addr..*addr + bytes.len()].copy_from_slice(bytes);
+        }
+        set_header_checksum(&mut rom);
+        let mut em = GameBoyEmulator::new_headless();
+        em.model = model;
+        em.boot_roms.skip = skip;
+        em.load_rom_data(rom);
+        run_to_program(&mut em);
+        while !em.cpu.halting {
+            em.advance();
+        }
+        em
+    }
+
+    #[test]
+    fn skipping_the_boot_rom_matches_running_it() {
+        let (ran, skipped) = (
+            dumped(Model::Dmg, &[], false),
+            dumped(Model::Dmg, &[], true),
+        );
+        assert_eq!(
+            skipped.mem_unit.high_ram[..8],
+            [0x01, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D, 0xB0]
+        );
+        assert_eq!(ran.mem_unit.high_ram[..8], skipped.mem_unit.high_ram[..8]);
+        assert!(ran.mem_unit.vram_0 == skipped.mem_unit.vram_0);
+        for addr in [LCDC_ADDR, BGP_ADDR, 0xFF11, 0xFF12, 0xFF24, 0xFF25, 0xFF50] {
+            assert_eq!(ran.get_memory(addr, CPU), skipped.get_memory(addr, CPU));
+        }
+        // The APU is powered up either way, but only the boot chime leaves channel 1 playing.
+        assert_eq!(
+            ran.get_memory(NR52_ADDR, CPU) & 0xF0,
+            skipped.get_memory(NR52_ADDR, CPU)
+        );
+
+        let cgb_cartridge: &[(usize, &[u8])] = &[(CGB_FLAG_ADDR, &[0x80])];
+        let (ran, skipped) = (
+            dumped(Model::Cgb, cgb_cartridge, false),
+            dumped(Model::Cgb, cgb_cartridge, true),
+        );
+        assert!(ran.cgb && skipped.cgb);
+        assert_eq!(ran.mem_unit.high_ram[..8], skipped.mem_unit.high_ram[..8]);
+        // Skipping doesn't draw the CGB logo, so VRAM bank 0 is left out.
+        assert!(ran.mem_unit.vram_1 == skipped.mem_unit.vram_1);
+        assert!(ran.mem_unit.bg_color_ram == skipped.mem_unit.bg_color_ram);
+        assert!(ran.mem_unit.obj_color_ram == skipped.mem_unit.obj_color_ram);
+        for addr in [
+            LCDC_ADDR, BGP_ADDR, 0xFF11, 0xFF12, 0xFF24, 0xFF25, KEY0_ADDR, SVBK_ADDR,
+        ] {
+            assert_eq!(ran.get_memory(addr, CPU), skipped.get_memory(addr, CPU));
+        }
+
+        // The boot ROM colorizes Nintendo's DMG games by their title checksum.
+        let tetris: &[(usize, &[u8])] = &[
+            (ROM_TITLE_START_ADDR, b"TETRIS"),
+            (OLD_LICENSEE_ADDR, &[NINTENDO_LICENSEE]),
+        ];
+        let (ran, skipped) = (
+            dumped(Model::Cgb, tetris, false),
+            dumped(Model::Cgb, tetris, true),
+        );
+        assert_eq!(
+            skipped.mem_unit.bg_color_ram[..8],
+            [0xFF, 0x7F, 0xFF, 0x03, 0x1F, 0x00, 0x00, 0x00]
+        );
+        assert!(ran.mem_unit.bg_color_ram == skipped.mem_unit.bg_color_ram);
+        assert!(ran.mem_unit.obj_color_ram == skipped.mem_unit.obj_color_ram);
+        assert_eq!(
+            ran.get_memory(KEY0_ADDR, CPU),
+            skipped.get_memory(KEY0_ADDR, CPU)
+        );
+    }
+
+    #[test]
+    fn boot_rom_is_overlaid_on_reads() {
+        let mut em = GameBoyEmulator::new_headless();
+        let rom = test_rom(&[0xF3, 0x76, 0x18, 0xFD]);
+        em.load_rom_data(rom.clone());
+        assert_eq!(em.get_memory(0x0000_usize, CPU), DMG_BOOTROM[0]);
+        assert_eq!(em.get_memory(0x0101_usize, CPU), rom[0x101]);
+        assert_eq!(em.mem_unit.rom[..0x100], rom[..0x100]);
+        run_to_program(&mut em);
+        assert_eq!(em.get_memory(0x0000_usize, CPU), rom[0]);
+
+        let path = std::env::temp_dir().join(format!("gb-boot-{}.bin", std::process::id()));
+        std::fs::write(&path, [0xAB; 0x100]).unwrap();
+        assert!(em.boot_roms.load(BootRomModel::Cgb, &path).is_err());
+        em.boot_roms.load(BootRomModel::Dmg0, &path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let mut em2 = GameBoyEmulator::new_headless();
+        em2.boot_roms = em.boot_roms;
+        em2.load_rom_data(rom);
+        assert_eq!(em2.get_memory(0x00FF_usize, CPU), 0xAB);
+    }
+
+    #[test]
+    fn models_leave_their_registers() {
+        for (model, skip, a, b) in [
+            (Model::Mgb, true, 0xFF, 0x00),
+            (Model::Sgb2, true, 0xFF, 0x00),
+            (Model::Cgb, true, 0x11, 0x00),
+            (Model::Agb, false, 0x11, 0x01),
+        ] {
+            let em = dumped(model, &[], skip);
+            assert_eq!(em.mem_unit.high_ram[..2], [a, b], "{:?}", model);
+            assert_eq!(em.dmg_compat, model.is_cgb());
+        }
     }
 }
@@ -0,0 +1,90 @@
+use crate::bootroms::BootRomModel;
+use crate::constants::*;
+
+/// The console being emulated. `Auto` picks the GBC for cartridges that support it and the
+/// DMG for everything else.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Model {
+    Auto,
+    Dmg,
+    Mgb,
+    Sgb,
+    Sgb2,
+    Cgb,
+    Agb,
+}
+
+impl Model {
+    pub fn from_name(name: &str) -> Option<Model> {
+        match name {
+            "auto" => Some(Model::Auto),
+            "dmg" => Some(Model::Dmg),
+            "mgb" => Some(Model::Mgb),
+            "sgb" => Some(Model::Sgb),
+            "sgb2" => Some(Model::Sgb2),
+            "cgb" => Some(Model::Cgb),
+            "agb" => Some(Model::Agb),
+            _ => None,
+        }
+    }
+    /// The model to run a cartridge on, given the CGB flag from its header.
+    pub fn resolve(self, cgb_flag: u8) -> Model {
+        match self {
+            Model::Auto if (cgb_flag >> 7) == 1 => Model::Cgb,
+            Model::Auto => Model::Dmg,
+            model => model,
+        }
+    }
+    /// Whether the model has the GBC hardware. The GBA runs Game Boy games in CGB mode.
+    pub fn is_cgb(self) -> bool {
+        matches!(self, Model::Cgb | Model::Agb)
+    }
+    pub fn is_sgb(self) -> bool {
+        matches!(self, Model::Sgb | Model::Sgb2)
+    }
+    pub fn boot_rom(self) -> BootRomModel {
+        match self {
+            Model::Auto | Model::Dmg => BootRomModel::Dmg,
+            Model::Mgb => BootRomModel::Mgb,
+            Model::Sgb | Model::Sgb2 => BootRomModel::Sgb,
+            Model::Cgb => BootRomModel::Cgb,
+            Model::Agb => BootRomModel::Agb,
+        }
+    }
+    /// A, F, B, C, D, E, H and L as the boot ROM leaves them. `dmg_mode` is for DMG
+    /// cartridges on the GBC and GBA.
+    pub fn post_boot_registers(self, dmg_mode: bool) -> [u8; 8] {
+        match self {
+            Model::Auto | Model::Dmg => DMG_POST_BOOT_REGISTERS,
+            Model::Mgb => MGB_POST_BOOT_REGISTERS,
+            Model::Sgb => SGB_POST_BOOT_REGISTERS,
+            Model::Sgb2 => SGB2_POST_BOOT_REGISTERS,
+            Model::Cgb if dmg_mode => CGB_DMG_MODE_POST_BOOT_REGISTERS,
+            Model::Cgb => CGB_POST_BOOT_REGISTERS,
+            Model::Agb if dmg_mode => AGB_DMG_MODE_POST_BOOT_REGISTERS,
+            Model::Agb => AGB_POST_BOOT_REGISTERS,
+        }
+    }
+    /// Only the DMG and MGB boot ROMs leave the logo in VRAM.
+    pub fn draws_boot_logo(self) -> bool {
+        matches!(self, Model::Auto | Model::Dmg | Model::Mgb)
+    }
+    pub fn has_oam_bug(self) -> bool {
+        !self.is_cgb()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_follows_the_cgb_flag() {
+        assert_eq!(Model::Auto.resolve(0x80), Model::Cgb);
+        assert_eq!(Model::Auto.resolve(0xC0), Model::Cgb);
+        assert_eq!(Model::Auto.resolve(0x00), Model::Dmg);
+        assert_eq!(Model::Sgb2.resolve(0x80), Model::Sgb2);
+        assert_eq!(Model::from_name("agb"), Some(Model::Agb));
+        assert_eq!(Model::from_name("gba"), None);
+    }
+}
@@ -0,0 +1,250 @@
+use crate::constants::*;
+use crate::emulator::GameBoyEmulator;
+use crate::memory::convert_to_8_bit;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+/// The colors used for each DMG palette register, darkest last.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct DmgPalette {
+    pub bg: [[u8; 4]; 4],
+    pub obp0: [[u8; 4]; 4],
+    pub obp1: [[u8; 4]; 4],
+}
+
+impl DmgPalette {
+    pub const fn uniform(colors: [[u8; 4]; 4]) -> DmgPalette {
+        DmgPalette {
+            bg: colors,
+            obp0: colors,
+            obp1: colors,
+        }
+    }
+    /// Reads a palette file. Each line names a register (`bg`, `obp0`, `obp1` or `all`)
+    /// followed by four RRGGBB colors from lightest to darkest. Lines starting with `#`
+    /// are comments and registers that are not mentioned keep the green colors.
+    pub fn load(path: &Path) -> io::Result<DmgPalette> {
+        let text = fs::read_to_string(path)?;
+        let mut palette = DmgPalette::uniform(DMG_COLOR_MAP);
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let register = words.next().unwrap();
+            let mut colors = [[0, 0, 0, 255]; 4];
+            for color in colors.iter_mut() {
+                let word = words
+                    .next()
+                    .ok_or_else(|| invalid_palette(format!("Missing color in \"{}\"", line)))?;
+                let hex = word.trim_start_matches('#');
+                let rgb = match u32::from_str_radix(hex, 16) {
+                    Ok(rgb) if hex.len() == 6 => rgb,
+                    _ => return Err(invalid_palette(format!("Bad color \"{}\"", word))),
+                };
+                *color = [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8, 255];
+            }
+            match register {
+                "bg" => palette.bg = colors,
+                "obp0" => palette.obp0 = colors,
+                "obp1" => palette.obp1 = colors,
+                "all" => palette = DmgPalette::uniform(colors),
+                _ => {
+                    return Err(invalid_palette(format!(
+                        "Unknown palette register \"{}\"",
+                        register
+                    )))
+                }
+            }
+        }
+        Ok(palette)
+    }
+}
+
+fn invalid_palette(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+pub const PALETTE_PRESETS: [(&str, DmgPalette); 5] = [
+    ("green", DmgPalette::uniform(DMG_COLOR_MAP)),
+    ("grayscale", DmgPalette::uniform(GRAYSCALE_COLOR_MAP)),
+    ("pocket", DmgPalette::uniform(POCKET_COLOR_MAP)),
+    ("light", DmgPalette::uniform(LIGHT_COLOR_MAP)),
+    (
+        "sprite contrast",
+        DmgPalette {
+            bg: GRAYSCALE_COLOR_MAP,
+            obp0: RED_COLOR_MAP,
+            obp1: BLUE_COLOR_MAP,
+        },
+    ),
+];
+
+/// How 15 bit CGB colors are turned into the RGB shown on screen.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ColorCorrection {
+    /// Scales each channel straight to 8 bits.
+    None,
+    /// Mixes the channels and darkens them like the CGB's LCD does.
+    GbcLcd,
+    /// Models the darker, higher gamma screen of the GBA.
+    GbaLcd,
+}
+
+impl ColorCorrection {
+    pub fn from_name(name: &str) -> Option<ColorCorrection> {
+        match name {
+            "none" => Some(ColorCorrection::None),
+            "gbc" => Some(ColorCorrection::GbcLcd),
+            "gba" => Some(ColorCorrection::GbaLcd),
+            _ => None,
+        }
+    }
+    fn next(self) -> ColorCorrection {
+        match self {
+            ColorCorrection::None => ColorCorrection::GbcLcd,
+            ColorCorrection::GbcLcd => ColorCorrection::GbaLcd,
+            ColorCorrection::GbaLcd => ColorCorrection::None,
+        }
+    }
+    /// The RGBA color for every 15 bit CGB color, indexed by the color's palette RAM value.
+    pub fn color_table(self) -> Vec<[u8; 4]> {
+        let (lcd_gamma, mix, scale) = match self {
+            ColorCorrection::None => {
+                return (0..0x8000u16)
+                    .map(|color| {
+                        [
+                            convert_to_8_bit((color & 0x1F) as u8),
+                            convert_to_8_bit(((color >> 5) & 0x1F) as u8),
+                            convert_to_8_bit(((color >> 10) & 0x1F) as u8),
+                            0xFF,
+                        ]
+                    })
+                    .collect();
+            }
+            ColorCorrection::GbcLcd => (GBC_LCD_GAMMA, GBC_LCD_MIX, GBC_LCD_SCALE),
+            ColorCorrection::GbaLcd => (GBA_LCD_GAMMA, GBA_LCD_MIX, GBA_LCD_SCALE),
+        };
+        (0..0x8000u16)
+            .map(|color| {
+                let linear = [
+                    ((color & 0x1F) as f32 / 31.0).powf(lcd_gamma),
+                    (((color >> 5) & 0x1F) as f32 / 31.0).powf(lcd_gamma),
+                    (((color >> 10) & 0x1F) as f32 / 31.0).powf(lcd_gamma),
+                ];
+                let mut out = [0xFF; 4];
+                for (channel, weights) in mix.iter().enumerate() {
+                    let mixed =
+                        weights[0] * linear[0] + weights[1] * linear[1] + weights[2] * linear[2];
+                    let encoded = mixed.min(1.0).powf(1.0 / OUTPUT_GAMMA) * scale;
+                    out[channel] = (encoded * 255.0).round().min(255.0) as u8;
+                }
+                out
+            })
+            .collect()
+    }
+}
+
+impl GameBoyEmulator {
+    pub fn set_color_correction(&mut self, correction: ColorCorrection) {
+        self.color_correction = correction;
+        self.color_table = correction.color_table();
+    }
+    pub fn next_color_correction(&mut self) {
+        self.set_color_correction(self.color_correction.next());
+        eprintln!("Color correction: {:?}", self.color_correction);
+    }
+    /// Colors for the four BGP shades. A DMG cartridge colorized by the CGB boot ROM takes
+    /// them from CGB palette 0 instead of the selected DMG palette.
+    pub fn bg_shades(&self) -> [[u8; 4]; 4] {
+        if self.sgb.is_some() {
+            SGB_SHADE_KEYS
+        } else if self.dmg_compat {
+            self.get_bg_rbg(0)
+        } else {
+            self.dmg_palette.bg
+        }
+    }
+    /// Colors for the four shades of OBP0 (`obp` 0) or OBP1 (`obp` 1).
+    pub fn obj_shades(&self, obp: u8) -> [[u8; 4]; 4] {
+        if self.sgb.is_some() {
+            SGB_SHADE_KEYS
+        } else if self.dmg_compat {
+            self.get_obj_rbg(obp)
+        } else if obp == 0 {
+            self.dmg_palette.obp0
+        } else {
+            self.dmg_palette.obp1
+        }
+    }
+    /// Selects a preset by name, or loads a palette file if no preset has that name.
+    pub fn set_palette(&mut self, name: &str) -> io::Result<()> {
+        if let Some(index) = self.palettes.iter().position(|(preset, _)| preset == name) {
+            self.palette_index = index;
+        } else {
+            let palette = DmgPalette::load(Path::new(name))?;
+            self.palettes.push((name.to_string(), palette));
+            self.palette_index = self.palettes.len() - 1;
+        }
+        self.dmg_palette = self.palettes[self.palette_index].1;
+        Ok(())
+    }
+    pub fn next_palette(&mut self) {
+        self.palette_index = (self.palette_index + 1) % self.palettes.len();
+        self.dmg_palette = self.palettes[self.palette_index].1;
+        eprintln!("Palette: {}", self.palettes[self.palette_index].0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::constants::*;
+    use crate::emulator::{run_to_program, set_header_checksum, test_rom, GameBoyEmulator};
+    use crate::model::Model;
+
+    const WHITE: [u8; 4] = [255, 255, 255, 255];
+    const BLACK: [u8; 4] = [0, 0, 0, 255];
+
+    /// Boots a Nintendo licensed DMG cartridge called TETRIS on the CGB while holding
+    /// `directional_presses`. The colorization is done by the boot ROM, so it has to run.
+    fn colorize(directional_presses: u8) -> GameBoyEmulator {
+        let mut rom = test_rom(&[0x18, 0xFE]);
+        rom[ROM_TITLE_START_ADDR..ROM_TITLE_START_ADDR + 6].copy_from_slice(b"TETRIS");
+        rom[OLD_LICENSEE_ADDR] = 0x01;
+        set_header_checksum(&mut rom);
+        let mut em = GameBoyEmulator::new_headless();
+        em.model = Model::Cgb;
+        em.load_rom_data(rom);
+        em.set_joypad(directional_presses, 0xF);
+        run_to_program(&mut em);
+        assert!(em.dmg_compat && !em.cgb);
+        em
+    }
+
+    #[test]
+    fn title_checksum_palette() {
+        let em = colorize(0xF);
+        let tetris = [WHITE, [255, 255, 0, 255], [255, 0, 0, 255], BLACK];
+        assert_eq!(em.bg_shades(), tetris);
+        assert_eq!(em.obj_shades(0), tetris);
+        assert_eq!(em.obj_shades(1), tetris);
+    }
+
+    #[test]
+    fn boot_combo_overrides_title() {
+        let em = colorize(0b1101);
+        assert_eq!(
+            em.bg_shades(),
+            [WHITE, [99, 165, 255, 255], [0, 0, 255, 255], BLACK]
+        );
+        assert_eq!(
+            em.obj_shades(0),
+            [WHITE, [255, 132, 132, 255], [148, 57, 57, 255], BLACK]
+        );
+        assert_eq!(
+            em.obj_shades(1),
+            [WHITE, [123, 255, 49, 255], [0, 132, 0, 255], BLACK]
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use crate::constants::*;
 use crate::emulator::RequestSource;
+use crate::fifo::PixelFifo;
 use serde::{Deserialize, Serialize};
 use std::cmp;
 
@@ -8,18 +9,18 @@ use crate::emulator::GameBoyEmulator;
 const SOURCE: RequestSource = RequestSource::PPU;
 
 #[inline]
-fn convert_to_index(row: impl Into<usize>, column: impl Into<usize>) -> usize {
+pub fn convert_to_index(row: impl Into<usize>, column: impl Into<usize>) -> usize {
     (160 * row.into() as usize + column.into() as usize) * 4
 }
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct PictureProcessingUnit {
-    cycle_count: u32,
-    possible_sprites: Vec<[u8; 4]>,
-    starting: bool,
+    pub cycle_count: u32,
+    pub possible_sprites: Vec<[u8; 4]>,
+    pub starting: bool,
     current_sprite_search: usize,
-    sprite_num: usize,
-    window_row_activated: bool,
+    pub sprite_num: usize,
+    pub window_row_activated: bool,
     draw_window: bool,
     color_indexes: [usize; 4],
     bg_tilemap_start_addr: usize,
@@ -32,7 +33,7 @@ pub struct PictureProcessingUnit {
     win_row_within_tile: usize,
     column: usize,
     tile_num: i8,
-    current_window_row: usize,
+    pub current_window_row: usize,
     pixel_priority: Vec<u8>,
     current_sprite_drawing: usize,
     bg_win_enable: bool,
@@ -45,6 +46,11 @@ pub struct PictureProcessingUnit {
     mode_2_line: bool,
     mode_1_line: bool,
     mode_0_line: bool,
+    pub fifo_renderer: bool,
+    fifo_line: bool,
+    pub fifo: PixelFifo,
+    drawing_dots: u32,
+    pub hblank_dots: u32,
 }
 
 impl PictureProcessingUnit {
@@ -81,6 +87,11 @@ impl PictureProcessingUnit {
             mode_2_line: false,
             mode_1_line: false,
             mode_0_line: false,
+            fifo_renderer: false,
+            fifo_line: false,
+            fifo: PixelFifo::new(),
+            drawing_dots: DRAWING_DOTS,
+            hblank_dots: HBLANK_DOTS,
         }
     }
 }
@@ -110,19 +121,19 @@ impl GameBoyEmulator {
                 false
             };
     }
-    fn get_bg_tile_map_flag(&self) -> u8 {
+    pub fn get_bg_tile_map_flag(&self) -> u8 {
         (self.get_memory(LCDC_ADDR, SOURCE) >> 3) & 1
     }
-    fn get_win_tile_map_flag(&self) -> u8 {
+    pub fn get_win_tile_map_flag(&self) -> u8 {
         (self.get_memory(LCDC_ADDR, SOURCE) >> 6) & 1
     }
-    fn get_tile_data_flag(&self) -> u8 {
+    pub fn get_tile_data_flag(&self) -> u8 {
         (self.get_memory(LCDC_ADDR, SOURCE) >> 4) & 1
     }
-    fn get_win_enable_flag(&self) -> u8 {
+    pub fn get_win_enable_flag(&self) -> u8 {
         (self.get_memory(LCDC_ADDR, SOURCE) >> 5) & 1
     }
-    fn get_obj_size(&self) -> u8 {
+    pub fn get_obj_size(&self) -> u8 {
         if ((self.get_memory(LCDC_ADDR, SOURCE) >> 2) & 1) == 1 {
             16
         } else {
@@ -130,10 +141,10 @@ impl GameBoyEmulator {
         }
     }
 
-    fn get_sprite_enable_flag(&self) -> u8 {
+    pub fn get_sprite_enable_flag(&self) -> u8 {
         (self.get_memory(LCDC_ADDR, SOURCE) >> 1) & 1
     }
-    fn get_bg_window_enable(&self) -> u8 {
+    pub fn get_bg_window_enable(&self) -> u8 {
         self.get_memory(LCDC_ADDR, SOURCE) & 1
     }
     fn get_ppu_enable(&self) -> u8 {
@@ -154,7 +165,7 @@ impl GameBoyEmulator {
     pub fn get_mode(&self) -> u8 {
         self.get_memory(STAT_ADDR, SOURCE) & 0b11
     }
-    fn set_mode(&mut self, mode: u8) {
+    pub fn set_mode(&mut self, mode: u8) {
         match mode {
             0 => {
                 self.ppu.mode_0_line = true;
@@ -211,6 +222,23 @@ impl GameBoyEmulator {
             SOURCE,
         );
     }
+    /// Runs until the PPU has finished `frames` more frames. While the LCD is off it
+    /// finishes none, so every frame's worth of M-cycles with it off counts as one.
+    pub fn run_frames(&mut self, frames: u32) {
+        let target = self.ppu.frame_num + frames;
+        let mut skipped = 0;
+        let mut off_advances = 0;
+        while self.ppu.frame_num + skipped < target {
+            self.advance();
+            if self.get_ppu_enable() == 0 {
+                off_advances += 1;
+                if off_advances == ADVANCES_PER_FRAME {
+                    off_advances = 0;
+                    skipped += 1;
+                }
+            }
+        }
+    }
     pub fn ppu_advance(&mut self) {
         if self.get_ppu_enable() == 0 {
             self.set_mode(0);
@@ -276,6 +304,11 @@ impl GameBoyEmulator {
 
     fn drawing_tiles(&mut self) {
         if self.ppu.starting {
+            self.ppu.fifo_line = self.ppu.fifo_renderer;
+        }
+        if self.ppu.fifo_line {
+            self.fifo_drawing();
+        } else if self.ppu.starting {
             self.drawing_initialize();
         } else if self.ppu.column < WINDOW_WIDTH && (self.ppu.bg_win_enable || self.cgb) {
             self.bg_win_draw();
@@ -283,7 +316,8 @@ impl GameBoyEmulator {
             self.obj_draw();
         } else {
             self.ppu.cycle_count += ADVANCE_CYCLES;
-            if self.ppu.cycle_count == DRAWING_DOTS {
+            if self.ppu.cycle_count >= self.ppu.drawing_dots {
+                self.ppu.hblank_dots = DRAWING_DOTS + HBLANK_DOTS - self.ppu.drawing_dots;
                 self.ppu.cycle_count = 0;
                 self.ppu.starting = true;
                 self.set_mode(HBLANK_MODE);
@@ -335,8 +369,45 @@ impl GameBoyEmulator {
         self.ppu.obj_enable = self.get_sprite_enable_flag() == 1;
         self.ppu.current_sprite_drawing = 0;
         self.ppu.pixel_priority = vec![255u8; 160];
+        self.ppu.drawing_dots = self.mode_3_dots(scx, wx);
         self.ppu.cycle_count += ADVANCE_CYCLES;
     }
+    /// Length of mode 3 on this line, rounded up to a whole M-cycle. It grows with the
+    /// SCX fine scroll, with the fetcher restarting for the window and with every sprite
+    /// fetch, which also waits for the background fetch of its tile to finish.
+    fn mode_3_dots(&self, scx: usize, wx: usize) -> u32 {
+        let mut dots = DRAWING_DOTS + (scx % TILE_WIDTH) as u32;
+        if self.ppu.window_row_activated {
+            dots += WINDOW_FETCH_DOTS;
+        }
+        if self.ppu.obj_enable {
+            let mut tiles_considered = Vec::with_capacity(MAX_SPRITES_PER_ROW);
+            for sprite in self.ppu.possible_sprites.iter() {
+                let x = sprite[OAM_X_INDEX] as usize;
+                if x >= WINDOW_WIDTH + TILE_WIDTH {
+                    continue;
+                }
+                // A sprite hidden off the left edge always waits out a whole fetch, whatever
+                // the fine scroll, and doesn't share it with the sprites after it.
+                if x == 0 {
+                    dots += OBJ_FETCH_DOTS + OBJ_MAX_ALIGN_DOTS;
+                    continue;
+                }
+                dots += OBJ_FETCH_DOTS;
+                let in_window = self.ppu.window_row_activated && x + 7 >= wx + TILE_WIDTH;
+                let (tile, offset) = if in_window {
+                    ((true, (x + 7 - wx) / TILE_WIDTH), (x + 7 - wx) % TILE_WIDTH)
+                } else {
+                    ((false, (x + scx) / TILE_WIDTH), (x + scx) % TILE_WIDTH)
+                };
+                if !tiles_considered.contains(&tile) {
+                    tiles_considered.push(tile);
+                    dots += OBJ_MAX_ALIGN_DOTS - cmp::min(OBJ_MAX_ALIGN_DOTS, offset as u32);
+                }
+            }
+        }
+        dots.div_ceil(ADVANCE_CYCLES) * ADVANCE_CYCLES
+    }
     fn bg_win_draw(&mut self) {
         let row = self.get_memory(LY_ADDR, SOURCE) as usize;
         let mut frame_index = convert_to_index(row, self.ppu.column);
@@ -375,12 +446,13 @@ impl GameBoyEmulator {
             )
         } else {
             let color_data = self.get_memory(BGP_ADDR, SOURCE) as usize;
+            let colors = self.bg_shades();
 
             let palette: [[u8; 4]; 4] = [
-                DMG_COLOR_MAP[color_data & 0b11],
-                DMG_COLOR_MAP[(color_data >> 2) & 0b11],
-                DMG_COLOR_MAP[(color_data >> 4) & 0b11],
-                DMG_COLOR_MAP[(color_data >> 6) & 0b11],
+                colors[color_data & 0b11],
+                colors[(color_data >> 2) & 0b11],
+                colors[(color_data >> 4) & 0b11],
+                colors[(color_data >> 6) & 0b11],
             ];
             (false, false, false, 0, palette)
         };
@@ -409,7 +481,12 @@ impl GameBoyEmulator {
         };
 
         let wx = self.get_memory(WX_ADDR, SOURCE) as usize;
-        let frame = self.pixels.get_frame();
+        let layer_shown = if self.ppu.draw_window {
+            self.layers.window
+        } else {
+            self.layers.bg
+        };
+        let frame = &mut self.frame;
         'pixel_loop: for pixel in bg_range.into_iter() {
             let color_index = ((((most_sig_byte >> (TILE_WIDTH - pixel - 1)) & 1) << 1)
                 + ((least_sig_byte >> (TILE_WIDTH - pixel - 1)) & 1))
@@ -425,7 +502,8 @@ impl GameBoyEmulator {
             };
             self.ppu.pixel_priority[self.ppu.column] = priority_level;
             self.ppu.row_colors[self.ppu.column] = color_index;
-            frame[frame_index..(frame_index + PIXEL_LENGTH)].copy_from_slice(&palette[color_index]);
+            let shown_index = if layer_shown { color_index } else { 0 };
+            frame[frame_index..(frame_index + PIXEL_LENGTH)].copy_from_slice(&palette[shown_index]);
             frame_index += PIXEL_LENGTH;
             self.ppu.column += 1;
             if self.ppu.column == WINDOW_WIDTH {
@@ -472,16 +550,18 @@ impl GameBoyEmulator {
                 (sprite[OAM_ATTRIBUTE_INDEX] >> 3) & 1,
             )
         } else {
-            let color_data = if (sprite[OAM_ATTRIBUTE_INDEX] >> 4) & 1 == 0 {
+            let obp = (sprite[OAM_ATTRIBUTE_INDEX] >> 4) & 1;
+            let color_data = if obp == 0 {
                 self.get_memory(OBP0_ADDR, SOURCE) as usize
             } else {
                 self.get_memory(OBP1_ADDR, SOURCE) as usize
             };
+            let colors = self.obj_shades(obp);
             let palette: [[u8; 4]; 4] = [
-                DMG_COLOR_MAP[color_data & 0b11],
-                DMG_COLOR_MAP[(color_data >> 2) & 0b11],
-                DMG_COLOR_MAP[(color_data >> 4) & 0b11],
-                DMG_COLOR_MAP[(color_data >> 6) & 0b11],
+                colors[color_data & 0b11],
+                colors[(color_data >> 2) & 0b11],
+                colors[(color_data >> 4) & 0b11],
+                colors[(color_data >> 6) & 0b11],
             ];
             (palette, 0)
         };
@@ -513,7 +593,7 @@ impl GameBoyEmulator {
                 (starting_pixel..=ending_pixel).collect::<Vec<usize>>()
             };
             let mut frame_index = convert_to_index(row, x_start);
-            let frame = self.pixels.get_frame();
+            let frame = &mut self.frame;
             for pixel in obj_range.into_iter() {
                 let color_index = ((((most_sig_byte >> (TILE_WIDTH - pixel - 1)) & 1) << 1)
                     + ((least_sig_byte >> (TILE_WIDTH - pixel - 1)) & 1))
@@ -521,7 +601,9 @@ impl GameBoyEmulator {
                 let color_pixels = palette[color_index];
                 if (priority < self.ppu.pixel_priority[x]) && (color_index != 0) {
                     self.ppu.pixel_priority[x] = priority;
-                    frame[frame_index..(frame_index + 4)].copy_from_slice(&color_pixels);
+                    if self.layers.objs {
+                        frame[frame_index..(frame_index + 4)].copy_from_slice(&color_pixels);
+                    }
                 }
                 frame_index += 4;
                 x += 1;
@@ -541,7 +623,7 @@ impl GameBoyEmulator {
             self.ppu.starting = false;
         } else {
             self.ppu.cycle_count += ADVANCE_CYCLES;
-            if self.ppu.cycle_count == HBLANK_DOTS {
+            if self.ppu.cycle_count == self.ppu.hblank_dots {
                 let ly = self.get_memory(LY_ADDR, SOURCE) + 1;
                 self.update_ly(ly);
                 self.ppu.cycle_count = 0;
@@ -560,7 +642,9 @@ impl GameBoyEmulator {
         if self.ppu.starting {
             self.ppu.frame_num += 1;
             self.ppu.starting = false;
-            self.pixels.render().unwrap();
+            self.sgb_finish_frame();
+            self.present_frame();
+            self.apply_cheats();
             self.ppu.current_window_row = 0;
             self.ppu.frame_index = 0;
         }
@@ -576,3 +660,45 @@ impl GameBoyEmulator {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Mode 3 length for a line with sprites at the given OAM X positions.
+    fn dots(scx: usize, window: bool, xs: &[u8]) -> u32 {
+        let mut em = GameBoyEmulator::new_headless();
+        em.ppu.obj_enable = true;
+        em.ppu.window_row_activated = window;
+        em.ppu.possible_sprites = xs.iter().map(|&x| [16, x, 0, 0]).collect();
+        em.mode_3_dots(scx, 7)
+    }
+
+    #[test]
+    fn fine_scroll_and_window_lengthen_mode_3() {
+        assert_eq!(dots(0, false, &[]), 172);
+        assert_eq!(dots(3, false, &[]), 176);
+        assert_eq!(dots(8, false, &[]), 172);
+        assert_eq!(dots(0, true, &[]), 180);
+    }
+
+    #[test]
+    fn sprites_lengthen_mode_3() {
+        // 6 dots per fetch, plus up to 5 waiting for the background fetch of its tile.
+        assert_eq!(dots(0, false, &[8]), 184);
+        assert_eq!(dots(0, false, &[13]), 180);
+        // A second sprite on the same tile doesn't wait again.
+        assert_eq!(dots(0, false, &[8, 9]), 192);
+        // Sprites off the right edge cost nothing.
+        assert_eq!(dots(0, false, &[168]), 172);
+        // With OAM X at 0 the sprite waits 11 dots regardless of SCX.
+        assert_eq!(dots(0, false, &[0]), 184);
+        assert_eq!(dots(3, false, &[0]), 188);
+        assert_eq!(dots(3, false, &[0, 8]), 196);
+
+        let mut em = GameBoyEmulator::new_headless();
+        em.ppu.possible_sprites = vec![[16, 8, 0, 0]];
+        em.ppu.obj_enable = false;
+        assert_eq!(em.mode_3_dots(0, 7), 172);
+    }
+}
@@ -0,0 +1,337 @@
+use crate::constants::*;
+use crate::serial::SerialDevice;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::PathBuf;
+
+#[derive(Clone, Copy)]
+enum PacketState {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+/// A Game Boy Printer plugged into the link port.
+///
+/// Each packet is `0x88 0x33`, a command, a compression flag, a 16 bit little endian data
+/// length, the data, a 16 bit checksum of everything after the magic bytes, and two bytes
+/// during which the printer answers with 0x81 and its status. Every print command writes
+/// the received image, with its margins and print palette applied, to a new PNG file in
+/// `output_dir`.
+pub struct GameBoyPrinter {
+    output_dir: PathBuf,
+    print_count: u32,
+    state: PacketState,
+    command: u8,
+    compressed: bool,
+    length: u16,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    status: u8,
+    busy_cycles: u32,
+    image: Vec<u8>,
+}
+
+impl GameBoyPrinter {
+    pub fn new(output_dir: PathBuf) -> GameBoyPrinter {
+        GameBoyPrinter {
+            output_dir,
+            print_count: 0,
+            state: PacketState::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            busy_cycles: 0,
+            image: Vec::new(),
+        }
+    }
+    fn receive(&mut self, byte: u8) -> u8 {
+        let mut response = 0;
+        self.state = match self.state {
+            PacketState::Magic1 if byte == PRINTER_MAGIC[0] => PacketState::Magic2,
+            PacketState::Magic1 => PacketState::Magic1,
+            PacketState::Magic2 if byte == PRINTER_MAGIC[1] => PacketState::Command,
+            PacketState::Magic2 => PacketState::Magic1,
+            PacketState::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                PacketState::Compression
+            }
+            PacketState::Compression => {
+                self.compressed = byte & 1 == 1;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                PacketState::LengthLow
+            }
+            PacketState::LengthLow => {
+                self.length = byte as u16;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                PacketState::LengthHigh
+            }
+            PacketState::LengthHigh => {
+                self.length |= (byte as u16) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                self.data.clear();
+                if self.length == 0 {
+                    PacketState::ChecksumLow
+                } else {
+                    PacketState::Data
+                }
+            }
+            PacketState::Data => {
+                self.data.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.data.len() == self.length as usize {
+                    PacketState::ChecksumLow
+                } else {
+                    PacketState::Data
+                }
+            }
+            PacketState::ChecksumLow => {
+                self.received_checksum = byte as u16;
+                PacketState::ChecksumHigh
+            }
+            PacketState::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+                PacketState::Alive
+            }
+            PacketState::Alive => {
+                response = PRINTER_ALIVE;
+                if self.received_checksum == self.checksum {
+                    self.status &= !PRINTER_STATUS_CHECKSUM_ERROR;
+                    self.run_command();
+                } else {
+                    self.status |= PRINTER_STATUS_CHECKSUM_ERROR;
+                }
+                PacketState::Status
+            }
+            PacketState::Status => {
+                response = self.status;
+                if self.busy_cycles == 0 && self.status & PRINTER_STATUS_FULL != 0 {
+                    self.status &= !PRINTER_STATUS_FULL;
+                }
+                PacketState::Magic1
+            }
+        };
+        response
+    }
+    fn run_command(&mut self) {
+        match self.command {
+            PRINTER_INIT_COMMAND => {
+                self.image.clear();
+                self.status = 0;
+            }
+            PRINTER_PRINT_COMMAND if self.data.len() == 4 => {
+                let margins = self.data[1];
+                let palette = self.data[2];
+                let rows = self.print_rows(margins >> 4, margins & 0xF, palette);
+                if let Err(e) = self.write_png(&rows) {
+                    eprintln!("Could not save printout: {}", e);
+                }
+                self.image.clear();
+                self.status = (self.status & !PRINTER_STATUS_UNPROCESSED)
+                    | PRINTER_STATUS_PRINTING
+                    | PRINTER_STATUS_FULL;
+                self.busy_cycles = PRINTER_BUSY_CYCLES;
+            }
+            PRINTER_DATA_COMMAND => {
+                let data = if self.compressed {
+                    decompress(&self.data)
+                } else {
+                    self.data.clone()
+                };
+                for band in data.chunks_exact(PRINTER_BAND_BYTES) {
+                    self.add_band(band);
+                }
+                if !data.is_empty() {
+                    self.status |= PRINTER_STATUS_UNPROCESSED;
+                }
+            }
+            PRINTER_BREAK_COMMAND => {
+                self.busy_cycles = 0;
+                self.status &= !PRINTER_STATUS_PRINTING;
+            }
+            // Every packet ends with the status, so there is nothing more to do here.
+            PRINTER_STATUS_COMMAND => {}
+            _ => {}
+        }
+    }
+    /// Decodes a band of 2 rows of 20 tiles into one color index per pixel.
+    fn add_band(&mut self, band: &[u8]) {
+        if self.image.len() >= PRINTER_MAX_IMAGE_ROWS * WINDOW_WIDTH {
+            return;
+        }
+        for tile_row in band.chunks_exact(PRINTER_TILES_PER_ROW * 16) {
+            for y in 0..8 {
+                for tile in tile_row.chunks_exact(16) {
+                    let low = tile[y * 2];
+                    let high = tile[y * 2 + 1];
+                    for bit in (0..8).rev() {
+                        self.image
+                            .push(((low >> bit) & 1) | (((high >> bit) & 1) << 1));
+                    }
+                }
+            }
+        }
+    }
+    fn print_rows(&self, margin_before: u8, margin_after: u8, palette: u8) -> Vec<u8> {
+        let mut rows =
+            vec![PRINTER_SHADES[0]; margin_before as usize * PRINTER_FEED_ROWS * WINDOW_WIDTH];
+        for color in self.image.iter() {
+            rows.push(PRINTER_SHADES[((palette >> (color * 2)) & 0b11) as usize]);
+        }
+        rows.resize(
+            rows.len() + margin_after as usize * PRINTER_FEED_ROWS * WINDOW_WIDTH,
+            PRINTER_SHADES[0],
+        );
+        rows
+    }
+    fn write_png(&mut self, rows: &[u8]) -> io::Result<()> {
+        let height = rows.len() / WINDOW_WIDTH;
+        if height == 0 {
+            return Ok(());
+        }
+        std::fs::create_dir_all(&self.output_dir)?;
+        let mut path;
+        loop {
+            self.print_count += 1;
+            path = self
+                .output_dir
+                .join(format!("print_{:04}.png", self.print_count));
+            if !path.exists() {
+                break;
+            }
+        }
+        let file = File::create(&path)?;
+        let mut encoder =
+            png::Encoder::new(BufWriter::new(file), WINDOW_WIDTH as u32, height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(rows)?;
+        Ok(())
+    }
+}
+
+/// Expands the printer's run length encoding. A control byte with bit 7 set repeats the
+/// next byte (control & 0x7F) + 2 times, otherwise the next control + 1 bytes are copied.
+fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut i = 0;
+    while i < data.len() {
+        let control = data[i];
+        i += 1;
+        if control & 0x80 != 0 {
+            if let Some(&byte) = data.get(i) {
+                out.resize(out.len() + (control & 0x7F) as usize + 2, byte);
+            }
+            i += 1;
+        } else {
+            let end = (i + control as usize + 1).min(data.len());
+            out.extend_from_slice(&data[i..end]);
+            i = end;
+        }
+    }
+    out
+}
+
+impl SerialDevice for GameBoyPrinter {
+    fn exchange(&mut self, out: u8) -> u8 {
+        self.receive(out)
+    }
+    fn advance(&mut self) {
+        if self.busy_cycles > 0 {
+            self.busy_cycles -= ADVANCE_CYCLES;
+            if self.busy_cycles == 0 {
+                self.status &= !PRINTER_STATUS_PRINTING;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn send_packet(printer: &mut GameBoyPrinter, command: u8, compressed: bool, data: &[u8]) -> u8 {
+        let mut packet = vec![
+            PRINTER_MAGIC[0],
+            PRINTER_MAGIC[1],
+            command,
+            compressed as u8,
+            data.len() as u8,
+            (data.len() >> 8) as u8,
+        ];
+        packet.extend_from_slice(data);
+        let checksum = packet[2..]
+            .iter()
+            .fold(0u16, |sum, &byte| sum.wrapping_add(byte as u16));
+        packet.extend_from_slice(&[checksum as u8, (checksum >> 8) as u8]);
+        for byte in packet {
+            assert_eq!(printer.exchange(byte), 0);
+        }
+        assert_eq!(printer.exchange(0), PRINTER_ALIVE);
+        printer.exchange(0)
+    }
+
+    #[test]
+    fn prints_compressed_band() {
+        let dir = std::env::temp_dir().join(format!("gb-printer-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut printer = GameBoyPrinter::new(dir.clone());
+        assert_eq!(
+            send_packet(&mut printer, PRINTER_INIT_COMMAND, false, &[]),
+            0
+        );
+
+        // Every pixel of the band is color 3, repeated in runs of 128 bytes.
+        let data = [0xFE, 0xFF, 0xFE, 0xFF, 0xFE, 0xFF, 0xFE, 0xFF, 0xFE, 0xFF];
+        let status = send_packet(&mut printer, PRINTER_DATA_COMMAND, true, &data);
+        assert_eq!(status, PRINTER_STATUS_UNPROCESSED);
+
+        let status = send_packet(
+            &mut printer,
+            PRINTER_PRINT_COMMAND,
+            false,
+            &[1, 0x10, 0xE4, 0x40],
+        );
+        assert_ne!(status & PRINTER_STATUS_PRINTING, 0);
+        for _ in 0..PRINTER_BUSY_CYCLES / ADVANCE_CYCLES {
+            printer.advance();
+        }
+        assert_eq!(
+            send_packet(&mut printer, PRINTER_STATUS_COMMAND, false, &[]),
+            PRINTER_STATUS_FULL
+        );
+        assert_eq!(
+            send_packet(&mut printer, PRINTER_STATUS_COMMAND, false, &[]),
+            0
+        );
+
+        let decoder = png::Decoder::new(File::open(dir.join("print_0001.png")).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        let info = reader.info();
+        assert_eq!(
+            (info.width, info.height),
+            (160, 16 + PRINTER_FEED_ROWS as u32)
+        );
+        assert_eq!(pixels[0], PRINTER_SHADES[0]);
+        assert_eq!(pixels[pixels.len() - 1], PRINTER_SHADES[3]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,446 @@
+use crate::constants::*;
+use crate::emulator::GameBoyEmulator;
+use crate::screenshot::timestamp;
+use std::fs::File;
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufWriter, SeekFrom};
+use std::path::{Path, PathBuf};
+
+fn le(values: &[u32]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn fourcc(id: &[u8; 4]) -> u32 {
+    u32::from_le_bytes(*id)
+}
+
+fn chunk(id: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    let mut out = id.to_vec();
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    if data.len() % 2 == 1 {
+        out.push(0);
+    }
+    out
+}
+
+fn list(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+    chunk(b"LIST", &[&kind[..], data].concat())
+}
+
+/// An AVI with uncompressed 24 bit frames and 16 bit stereo PCM, interleaved one video
+/// chunk and the audio produced during that frame at a time.
+struct AviWriter {
+    file: BufWriter<File>,
+    size: u64,
+    movi_offset: u64,
+    frames_offset: u64,
+    video_length_offset: u64,
+    audio_length_offset: u64,
+    index: Vec<u8>,
+    frame_count: u32,
+    sample_count: u32,
+}
+
+impl AviWriter {
+    fn create(path: &Path) -> io::Result<AviWriter> {
+        let (width, height) = (WINDOW_WIDTH as u32, WINDOW_HEIGHT as u32);
+        let frame_bytes = AVI_FRAME_BYTES as u32;
+        let audio_bytes_per_second = SAMPLES_PER_SECOND * 4;
+        let micro_seconds_per_frame =
+            (FRAME_CYCLES as u64 * 1_000_000 / CYCLES_PER_SECOND as u64) as u32;
+        let bytes_per_second = (frame_bytes as u64 * CYCLES_PER_SECOND as u64 / FRAME_CYCLES as u64)
+            as u32
+            + audio_bytes_per_second;
+        let avih = chunk(
+            b"avih",
+            &le(&[
+                micro_seconds_per_frame,
+                bytes_per_second,
+                0,
+                AVIF_HASINDEX,
+                0,
+                0,
+                2,
+                frame_bytes,
+                width,
+                height,
+                0,
+                0,
+                0,
+                0,
+            ]),
+        );
+        let video_header = le(&[
+            fourcc(b"vids"),
+            0,
+            0,
+            0,
+            0,
+            FRAME_CYCLES,
+            CYCLES_PER_SECOND,
+            0,
+            0,
+            frame_bytes,
+            u32::MAX,
+            0,
+            0,
+            width | height << 16,
+        ]);
+        let bitmap_info = le(&[40, width, height, 1 | 24 << 16, 0, frame_bytes, 0, 0, 0, 0]);
+        let video = list(
+            b"strl",
+            &[chunk(b"strh", &video_header), chunk(b"strf", &bitmap_info)].concat(),
+        );
+        let audio_header = le(&[
+            fourcc(b"auds"),
+            0,
+            0,
+            0,
+            0,
+            1,
+            SAMPLES_PER_SECOND,
+            0,
+            0,
+            audio_bytes_per_second,
+            u32::MAX,
+            4,
+            0,
+            0,
+        ]);
+        let mut wave_format = le(&[
+            WAVE_FORMAT_PCM | 2 << 16,
+            SAMPLES_PER_SECOND,
+            audio_bytes_per_second,
+            4 | 16 << 16,
+        ]);
+        wave_format.extend_from_slice(&[0, 0]);
+        let audio = list(
+            b"strl",
+            &[chunk(b"strh", &audio_header), chunk(b"strf", &wave_format)].concat(),
+        );
+
+        // Offsets of the RIFF and LIST headers before each stream's strh data.
+        let frames_offset = (12 + 12 + 8 + AVIH_FRAMES_OFFSET) as u64;
+        let video_length_offset = (12 + 12 + avih.len() + 12 + 8 + STRH_LENGTH_OFFSET) as u64;
+        let audio_length_offset = video_length_offset + video.len() as u64;
+
+        let mut header = b"RIFF\0\0\0\0AVI ".to_vec();
+        header.extend(list(b"hdrl", &[avih, video, audio].concat()));
+        header.extend_from_slice(b"LIST\0\0\0\0movi");
+        let mut file = BufWriter::new(File::create(path)?);
+        file.write_all(&header)?;
+        Ok(AviWriter {
+            file,
+            size: header.len() as u64,
+            movi_offset: header.len() as u64 - 4,
+            frames_offset,
+            video_length_offset,
+            audio_length_offset,
+            index: Vec::new(),
+            frame_count: 0,
+            sample_count: 0,
+        })
+    }
+    fn write_chunk(&mut self, id: &[u8; 4], data: &[u8]) -> io::Result<()> {
+        let offset = (self.size - self.movi_offset) as u32;
+        self.index
+            .extend(le(&[fourcc(id), AVIIF_KEYFRAME, offset, data.len() as u32]));
+        let chunk = chunk(id, data);
+        self.file.write_all(&chunk)?;
+        self.size += chunk.len() as u64;
+        Ok(())
+    }
+    fn add_frame(&mut self, frame: &[u8], samples: &[[i16; 2]]) -> io::Result<()> {
+        if self.size > AVI_MAX_BYTES {
+            return Err(io::Error::other("the AVI has reached its size limit"));
+        }
+        // Uncompressed AVI frames are BGR and stored bottom row first.
+        let mut bgr = Vec::with_capacity(AVI_FRAME_BYTES);
+        for row in frame.chunks_exact(WINDOW_WIDTH * PIXEL_LENGTH).rev() {
+            for pixel in row.chunks_exact(PIXEL_LENGTH) {
+                bgr.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+            }
+        }
+        self.write_chunk(b"00db", &bgr)?;
+        self.frame_count += 1;
+        if !samples.is_empty() {
+            let pcm: Vec<u8> = samples
+                .iter()
+                .flat_map(|sample| [sample[0].to_le_bytes(), sample[1].to_le_bytes()].concat())
+                .collect();
+            self.write_chunk(b"01wb", &pcm)?;
+            self.sample_count += samples.len() as u32;
+        }
+        Ok(())
+    }
+    fn finish(mut self) -> io::Result<()> {
+        let movi_end = self.size;
+        let index = chunk(b"idx1", &self.index);
+        self.file.write_all(&index)?;
+        self.size += index.len() as u64;
+        let patches = [
+            (4, (self.size - 8) as u32),
+            (self.movi_offset - 4, (movi_end - self.movi_offset) as u32),
+            (self.frames_offset, self.frame_count),
+            (self.video_length_offset, self.frame_count),
+            (self.audio_length_offset, self.sample_count),
+        ];
+        for (offset, value) in patches.iter() {
+            self.file.seek(SeekFrom::Start(*offset))?;
+            self.file.write_all(&value.to_le_bytes())?;
+        }
+        self.file.flush()
+    }
+}
+
+/// An endlessly looping GIF of every `GIF_FRAME_STEP`th frame, since most viewers will
+/// not show frames for less than 2/100 of a second.
+struct GifWriter {
+    encoder: gif::Encoder<BufWriter<File>>,
+}
+
+fn gif_error(e: gif::EncodingError) -> io::Error {
+    io::Error::other(e)
+}
+
+/// The time of `frame` in 1/100 seconds.
+fn centiseconds(frame: u64) -> u64 {
+    frame * 100 * FRAME_CYCLES as u64 / CYCLES_PER_SECOND as u64
+}
+
+impl GifWriter {
+    fn create(path: &Path) -> io::Result<GifWriter> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = gif::Encoder::new(file, WINDOW_WIDTH as u16, WINDOW_HEIGHT as u16, &[])
+            .map_err(gif_error)?;
+        encoder
+            .set_repeat(gif::Repeat::Infinite)
+            .map_err(gif_error)?;
+        Ok(GifWriter { encoder })
+    }
+    fn add_frame(&mut self, frame: &[u8], frame_num: u64) -> io::Result<()> {
+        if !frame_num.is_multiple_of(GIF_FRAME_STEP) {
+            return Ok(());
+        }
+        let mut rgba = frame.to_vec();
+        let mut gif_frame = gif::Frame::from_rgba_speed(
+            WINDOW_WIDTH as u16,
+            WINDOW_HEIGHT as u16,
+            &mut rgba,
+            GIF_QUANTIZE_SPEED,
+        );
+        gif_frame.delay =
+            (centiseconds(frame_num + GIF_FRAME_STEP) - centiseconds(frame_num)) as u16;
+        self.encoder.write_frame(&gif_frame).map_err(gif_error)
+    }
+    fn finish(self) -> io::Result<()> {
+        self.encoder.into_inner()?.flush()
+    }
+}
+
+enum RecordingOutput {
+    Avi(AviWriter),
+    Gif(GifWriter),
+}
+
+/// Records every finished frame, and for AVIs the mixed APU output, to a file.
+///
+/// While the LCD is off no frames are finished, so the last frame is repeated whenever the
+/// video falls two frames behind the audio.
+pub struct Recorder {
+    output: RecordingOutput,
+    path: PathBuf,
+    last_frame: Vec<u8>,
+    samples: Vec<[i16; 2]>,
+    frame_count: u64,
+    sample_count: u64,
+}
+
+impl Recorder {
+    /// Starts a GIF if `path` ends in `.gif`, and an AVI otherwise.
+    pub fn create(path: &Path) -> io::Result<Recorder> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        let is_gif = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("gif"));
+        let output = if is_gif {
+            RecordingOutput::Gif(GifWriter::create(path)?)
+        } else {
+            RecordingOutput::Avi(AviWriter::create(path)?)
+        };
+        Ok(Recorder {
+            output,
+            path: path.to_path_buf(),
+            last_frame: vec![0xFF; WINDOW_WIDTH * WINDOW_HEIGHT * PIXEL_LENGTH],
+            samples: Vec::new(),
+            frame_count: 0,
+            sample_count: 0,
+        })
+    }
+    pub fn add_frame(&mut self, frame: &[u8]) -> io::Result<()> {
+        self.last_frame.copy_from_slice(frame);
+        match &mut self.output {
+            RecordingOutput::Avi(avi) => avi.add_frame(frame, &self.samples)?,
+            RecordingOutput::Gif(gif) => gif.add_frame(frame, self.frame_count)?,
+        }
+        self.samples.clear();
+        self.frame_count += 1;
+        Ok(())
+    }
+    pub fn add_sample(&mut self, sample: [f32; 2]) -> io::Result<()> {
+        self.samples.push([
+            (sample[0].clamp(-1.0, 1.0) * i16::MAX as f32) as i16,
+            (sample[1].clamp(-1.0, 1.0) * i16::MAX as f32) as i16,
+        ]);
+        self.sample_count += 1;
+        let audio_frames = self.sample_count * CYCLES_PER_SECOND as u64
+            / (SAMPLES_PER_SECOND as u64 * FRAME_CYCLES as u64);
+        if audio_frames >= self.frame_count + 2 {
+            let frame = self.last_frame.clone();
+            self.add_frame(&frame)?;
+        }
+        Ok(())
+    }
+    pub fn finish(self) -> io::Result<PathBuf> {
+        match self.output {
+            RecordingOutput::Avi(avi) => avi.finish()?,
+            RecordingOutput::Gif(gif) => gif.finish()?,
+        }
+        Ok(self.path)
+    }
+}
+
+impl GameBoyEmulator {
+    pub fn start_recording(&mut self, path: &Path) {
+        self.stop_recording();
+        match Recorder::create(path) {
+            Ok(recorder) => {
+                eprintln!("Recording to {}", path.display());
+                self.recorder = Some(recorder);
+            }
+            Err(e) => eprintln!("Could not start recording: {}", e),
+        }
+    }
+    pub fn stop_recording(&mut self) {
+        if let Some(recorder) = self.recorder.take() {
+            match recorder.finish() {
+                Ok(path) => eprintln!("Saved recording to {}", path.display()),
+                Err(e) => eprintln!("Could not save recording: {}", e),
+            }
+        }
+    }
+    /// Starts a recording named after the ROM title and the time in `recording_dir`, or
+    /// stops the current one.
+    pub fn toggle_recording(&mut self, extension: &str) {
+        if self.recorder.is_some() {
+            self.stop_recording();
+        } else {
+            let name = format!("{}_{}.{}", self.rom_title(), timestamp(), extension);
+            let path = self.recording_dir.join(name);
+            self.start_recording(&path);
+        }
+    }
+    pub fn record_frame(&mut self) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(e) = recorder.add_frame(&self.frame) {
+                eprintln!("Recording stopped: {}", e);
+                self.stop_recording();
+            }
+        }
+    }
+    pub fn record_sample(&mut self, sample: [f32; 2]) {
+        if let Some(recorder) = self.recorder.as_mut() {
+            if let Err(e) = recorder.add_sample(sample) {
+                eprintln!("Recording stopped: {}", e);
+                self.stop_recording();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::test_rom;
+
+    const FRAMES: u32 = 30;
+
+    fn record(name: &str) -> (GameBoyEmulator, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("gb-recorder-test-{}", std::process::id()));
+        let path = dir.join(name);
+        let mut em = GameBoyEmulator::new_headless();
+        em.load_rom_data(test_rom(&[0x18, 0xFE]));
+        em.start_recording(&path);
+        em.run_headless(FRAMES * ADVANCES_PER_FRAME);
+        em.stop_recording();
+        (em, path)
+    }
+
+    fn read_u32(data: &[u8], offset: usize) -> u32 {
+        u32::from_le_bytes([
+            data[offset],
+            data[offset + 1],
+            data[offset + 2],
+            data[offset + 3],
+        ])
+    }
+
+    #[test]
+    fn avi_keeps_audio_in_step() {
+        let (_, path) = record("test.avi");
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(read_u32(&data, 4) as usize, data.len() - 8);
+        let frames = read_u32(&data, 12 + 12 + 8 + AVIH_FRAMES_OFFSET);
+        assert!(frames == FRAMES || frames == FRAMES - 1);
+
+        let mut offset = data.windows(4).position(|id| id == b"movi").unwrap() + 4;
+        let (mut video_chunks, mut samples) = (0, 0);
+        while &data[offset..offset + 4] != b"idx1" {
+            let size = read_u32(&data, offset + 4) as usize;
+            match &data[offset..offset + 4] {
+                b"00db" => {
+                    assert_eq!(size, AVI_FRAME_BYTES);
+                    video_chunks += 1;
+                }
+                b"01wb" => samples += size / 4,
+                id => panic!("Unexpected chunk {:?}", id),
+            }
+            offset += 8 + size;
+        }
+        assert_eq!(video_chunks, frames);
+        let audio_frames = samples as f64 * CYCLES_PER_SECOND as f64
+            / (SAMPLES_PER_SECOND as f64 * FRAME_CYCLES as f64);
+        assert!((audio_frames - frames as f64).abs() < 1.0);
+        assert_eq!(
+            read_u32(&data, offset + 4) as usize,
+            frames as usize * 2 * 16
+        );
+    }
+
+    #[test]
+    fn gif_has_every_other_frame() {
+        let (em, path) = record("test.gif");
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::RGBA);
+        let mut decoder = options.read_info(File::open(&path).unwrap()).unwrap();
+        let mut frames = 0;
+        let mut last = Vec::new();
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert!(frame.delay == 3 || frame.delay == 4);
+            last = frame.buffer.to_vec();
+            frames += 1;
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(frames, FRAMES / GIF_FRAME_STEP as u32);
+        assert_eq!(last.len(), em.frame.len());
+    }
+}
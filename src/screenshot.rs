@@ -0,0 +1,118 @@
+use crate::constants::*;
+use crate::emulator::GameBoyEmulator;
+use crate::filter::nearest;
+use std::fs::File;
+use std::io;
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The current UTC time as `YYYYMMDD-HHMMSS-mmm`.
+pub fn timestamp() -> String {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let secs = now.as_secs();
+    let (hour, minute, second) = (secs / 3600 % 24, secs / 60 % 60, secs % 60);
+
+    // Days since 1970-01-01 to a civil date, counting in 400 year eras starting in March.
+    let z = secs / 86400 + 719_468;
+    let era = z / 146_097;
+    let day_of_era = z - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}-{:03}",
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+        now.subsec_millis()
+    )
+}
+
+impl GameBoyEmulator {
+    /// The window's size in whole multiples of the Game Boy screen.
+    pub fn window_scale(&self) -> usize {
+        match &self.window {
+            Some(window) => {
+                let (width, height) = window.size();
+                let (screen_width, screen_height) = self.screen_size();
+                (width as usize / screen_width)
+                    .min(height as usize / screen_height)
+                    .max(1)
+            }
+            None => 1,
+        }
+    }
+    /// Saves the last finished frame, scaled up `scale` times, as a PNG in `dir` named
+    /// after the ROM title and the current time.
+    pub fn save_screenshot(&self, dir: &Path, scale: usize) -> io::Result<PathBuf> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}_{}.png", self.rom_title(), timestamp()));
+        let (width, height) = (WINDOW_WIDTH * scale, WINDOW_HEIGHT * scale);
+        let data = nearest(&self.finished_frame, WINDOW_WIDTH, scale);
+        let file = File::create(&path)?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), width as u32, height as u32);
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&data)?;
+        Ok(path)
+    }
+    pub fn take_screenshot(&self, scale: usize) {
+        match self.save_screenshot(&self.screenshot_dir, scale) {
+            Ok(path) => eprintln!("Saved screenshot to {}", path.display()),
+            Err(e) => eprintln!("Could not save screenshot: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{booted_rom, set_header_checksum, test_rom};
+    use std::fs::File;
+
+    #[test]
+    fn saves_scaled_frame() {
+        let dir = std::env::temp_dir().join(format!("gb-screenshot-test-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let mut rom = test_rom(&[0x18, 0xFE]);
+        rom[ROM_TITLE_START_ADDR..ROM_TITLE_START_ADDR + 5].copy_from_slice(b"SHOT!");
+        set_header_checksum(&mut rom);
+        let mut em = booted_rom(rom);
+        em.run_frames(2);
+        // Stop halfway through the next frame, with the screen half drawn.
+        em.run_headless(ADVANCES_PER_FRAME / 2);
+        em.frame.iter_mut().for_each(|byte| *byte = 0x12);
+        let path = em.save_screenshot(&dir, 2).unwrap();
+        let name = path.file_name().unwrap().to_str().unwrap();
+        assert!(name.starts_with("SHOT__") && name.ends_with(".png"));
+
+        let decoder = png::Decoder::new(File::open(&path).unwrap());
+        let mut reader = decoder.read_info().unwrap();
+        let mut pixels = vec![0; reader.output_buffer_size()];
+        reader.next_frame(&mut pixels).unwrap();
+        assert_eq!((reader.info().width, reader.info().height), (320, 288));
+        let row = 320 * PIXEL_LENGTH;
+        for (y, x) in [(0, 0), (100, 57), (143, 159)] {
+            let source = (y * WINDOW_WIDTH + x) * PIXEL_LENGTH;
+            let scaled = y * 2 * row + x * 2 * PIXEL_LENGTH;
+            let expected = &em.finished_frame[source..source + PIXEL_LENGTH];
+            assert_ne!(expected[0], 0x12);
+            assert_eq!(&pixels[scaled..scaled + PIXEL_LENGTH], expected);
+            assert_eq!(&pixels[scaled + row + 4..scaled + row + 8], expected);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,397 @@
+use crate::constants::*;
+use crate::emulator::GameBoyEmulator;
+use std::path::Path;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum RamRegion {
+    Wram,
+    Eram,
+    Hram,
+}
+
+/// A byte of searchable RAM, as the address it shows up at and the bank that has to be
+/// mapped for it to be there.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct RamLocation {
+    pub region: RamRegion,
+    pub bank: usize,
+    pub addr: usize,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Interpretation {
+    U8,
+    U16,
+    Bcd8,
+    Bcd16,
+}
+
+impl Interpretation {
+    pub fn from_name(name: &str) -> Option<Interpretation> {
+        match name {
+            "u8" => Some(Interpretation::U8),
+            "u16" => Some(Interpretation::U16),
+            "bcd8" => Some(Interpretation::Bcd8),
+            "bcd16" => Some(Interpretation::Bcd16),
+            _ => None,
+        }
+    }
+    pub fn width(self) -> usize {
+        match self {
+            Interpretation::U8 | Interpretation::Bcd8 => 1,
+            Interpretation::U16 | Interpretation::Bcd16 => 2,
+        }
+    }
+    /// Reads a little endian value, or `None` for BCD bytes with a digit above 9.
+    pub fn decode(self, bytes: &[u8]) -> Option<u32> {
+        let raw = bytes[..self.width()]
+            .iter()
+            .rev()
+            .fold(0, |acc, byte| (acc << 8) | *byte as u32);
+        match self {
+            Interpretation::U8 | Interpretation::U16 => Some(raw),
+            Interpretation::Bcd8 | Interpretation::Bcd16 => {
+                let mut val = 0;
+                for shift in (0..self.width() * 8).step_by(4).rev() {
+                    let digit = (raw >> shift) & 0xF;
+                    if digit > 9 {
+                        return None;
+                    }
+                    val = val * 10 + digit;
+                }
+                Some(val)
+            }
+        }
+    }
+    /// The little endian bytes that hold `val`, or `None` if it does not fit.
+    pub fn encode(self, val: u32) -> Option<Vec<u8>> {
+        let raw = match self {
+            Interpretation::U8 | Interpretation::U16 => val,
+            Interpretation::Bcd8 | Interpretation::Bcd16 => {
+                let mut raw = 0;
+                let mut rest = val;
+                for shift in (0..self.width() * 8).step_by(4) {
+                    raw |= (rest % 10) << shift;
+                    rest /= 10;
+                }
+                if rest != 0 {
+                    return None;
+                }
+                raw
+            }
+        };
+        if raw >> (self.width() * 8) != 0 {
+            return None;
+        }
+        Some((0..self.width()).map(|i| (raw >> (i * 8)) as u8).collect())
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum SearchFilter {
+    Equal,
+    Changed,
+    Increased,
+    Decreased,
+    Value(u32),
+}
+
+impl SearchFilter {
+    fn keeps(self, previous: u32, current: u32) -> bool {
+        match self {
+            SearchFilter::Equal => current == previous,
+            SearchFilter::Changed => current != previous,
+            SearchFilter::Increased => current > previous,
+            SearchFilter::Decreased => current < previous,
+            SearchFilter::Value(val) => current == val,
+        }
+    }
+}
+
+/// Narrows RAM down to the places that behave like a game variable. Every filter compares
+/// against the values seen by the previous step.
+pub struct RamSearch {
+    pub interpretation: Interpretation,
+    snapshot: Vec<u8>,
+    pub candidates: Vec<usize>,
+}
+
+impl GameBoyEmulator {
+    /// Takes a snapshot of WRAM, cartridge RAM and HRAM to search with.
+    pub fn start_ram_search(&mut self, interpretation: Interpretation) {
+        let snapshot = self.searchable_ram();
+        let candidates = (0..snapshot.len())
+            .filter(|offset| self.searchable_span(*offset, interpretation.width()))
+            .filter(|offset| interpretation.decode(&snapshot[*offset..]).is_some())
+            .collect();
+        self.ram_search = Some(RamSearch {
+            interpretation,
+            snapshot,
+            candidates,
+        });
+    }
+    /// Whether `width` bytes from `offset` are next to each other in the memory map.
+    fn searchable_span(&self, offset: usize, width: usize) -> bool {
+        let first = self.searchable_location(offset);
+        (1..width).all(|i| {
+            offset + i < self.searchable_ram_len() && {
+                let next = self.searchable_location(offset + i);
+                next.region == first.region && next.addr == first.addr + i
+            }
+        })
+    }
+    /// Keeps the candidates that pass `filter` and returns how many are left.
+    pub fn filter_ram_search(&mut self, filter: SearchFilter) -> usize {
+        let current = self.searchable_ram();
+        let search = match self.ram_search.as_mut() {
+            Some(search) => search,
+            None => return 0,
+        };
+        let interpretation = search.interpretation;
+        let snapshot = &search.snapshot;
+        search.candidates.retain(|offset| {
+            match (
+                interpretation.decode(&snapshot[*offset..]),
+                interpretation.decode(&current[*offset..]),
+            ) {
+                (Some(previous), Some(current)) => filter.keeps(previous, current),
+                _ => false,
+            }
+        });
+        search.snapshot = current;
+        search.candidates.len()
+    }
+    /// The location and current value of each remaining candidate.
+    pub fn ram_search_results(&self) -> Vec<(RamLocation, u32)> {
+        let search = match self.ram_search.as_ref() {
+            Some(search) => search,
+            None => return Vec::new(),
+        };
+        search
+            .candidates
+            .iter()
+            .filter_map(|offset| {
+                let val = search.interpretation.decode(&search.snapshot[*offset..])?;
+                Some((self.searchable_location(*offset), val))
+            })
+            .collect()
+    }
+    /// GameShark codes that hold the `result`th candidate at `val`. The codes can only reach
+    /// the cartridge RAM bank that is mapped, so candidates in other banks are refused.
+    pub fn ram_search_codes(&self, result: usize, val: u32) -> Result<Vec<String>, String> {
+        let search = self.ram_search.as_ref().ok_or("No RAM search running")?;
+        let offset = *search.candidates.get(result).ok_or("No such result")?;
+        let bytes = search
+            .interpretation
+            .encode(val)
+            .ok_or_else(|| format!("{} does not fit", val))?;
+        bytes
+            .iter()
+            .enumerate()
+            .map(|(i, byte)| {
+                let location = self.searchable_location(offset + i);
+                let code_type = match location.region {
+                    RamRegion::Wram
+                        if self.cgb && location.addr >= WRAM_START_ADDR + WRAM_BANK_SIZE =>
+                    {
+                        (GAMESHARK_WRAM_BANK_TYPE << 4) | location.bank
+                    }
+                    RamRegion::Eram if location.bank != self.eram_bank() => {
+                        return Err(format!(
+                            "{:04X} is in cartridge RAM bank {}, which is not mapped",
+                            location.addr, location.bank
+                        ));
+                    }
+                    _ => 1,
+                };
+                Ok(format!(
+                    "{:02X}{:02X}{:02X}{:02X}",
+                    code_type,
+                    byte,
+                    location.addr & 0xFF,
+                    location.addr >> 8
+                ))
+            })
+            .collect()
+    }
+    /// Turns the `result`th candidate into cheats that hold it at `val`.
+    pub fn ram_search_to_cheat(&mut self, result: usize, val: u32) -> Result<Vec<usize>, String> {
+        let codes = self.ram_search_codes(result, val)?;
+        let name = format!("RAM search {}", val);
+        codes
+            .iter()
+            .map(|code| self.add_cheat(code, &name))
+            .collect()
+    }
+    /// Adds the `result`th candidate to the memory watch, starting one at `path` if none is
+    /// running.
+    pub fn ram_search_to_watch(&mut self, result: usize, path: &Path) -> Result<(), String> {
+        let search = self.ram_search.as_ref().ok_or("No RAM search running")?;
+        let offset = *search.candidates.get(result).ok_or("No such result")?;
+        let location = self.searchable_location(offset);
+        let range = (
+            location.addr,
+            location.addr + search.interpretation.width() - 1,
+        );
+        match &self.watch {
+            Some(watch) => watch.borrow_mut().add_range(range),
+            None => self.start_watch(path, vec![range]),
+        }
+        Ok(())
+    }
+    /// Runs a RAM search command typed into the console:
+    /// `start [u8|u16|bcd8|bcd16]`, `equal`, `changed`, `increased`, `decreased`,
+    /// `value <n>`, `list`, `cheat <result> <n>` or `watch <result>`.
+    pub fn ram_search_command(&mut self, line: &str) -> Result<(), String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let number = |i: usize| -> Result<u32, String> {
+            let word = words.get(i).ok_or("Missing a number")?;
+            word.parse::<u32>()
+                .map_err(|_| format!("\"{}\" is not a number", word))
+        };
+        let filter = match words.first() {
+            None => return Ok(()),
+            Some(&"start") => {
+                let name = words.get(1).unwrap_or(&"u8");
+                let interpretation = Interpretation::from_name(name)
+                    .ok_or("Values must be u8, u16, bcd8 or bcd16")?;
+                self.start_ram_search(interpretation);
+                None
+            }
+            Some(&"equal") => Some(SearchFilter::Equal),
+            Some(&"changed") => Some(SearchFilter::Changed),
+            Some(&"increased") => Some(SearchFilter::Increased),
+            Some(&"decreased") => Some(SearchFilter::Decreased),
+            Some(&"value") => Some(SearchFilter::Value(number(1)?)),
+            Some(&"list") => None,
+            Some(&"cheat") => {
+                let val = number(2)?;
+                for index in self.ram_search_to_cheat(number(1)? as usize, val)? {
+                    eprintln!("Added cheat {}", self.cheats.list[index].code);
+                }
+                return Ok(());
+            }
+            Some(&"watch") => {
+                return self
+                    .ram_search_to_watch(number(1)? as usize, Path::new(RAM_SEARCH_WATCH_LOG))
+            }
+            Some(word) => return Err(format!("Unknown RAM search command \"{}\"", word)),
+        };
+        if let Some(filter) = filter {
+            self.filter_ram_search(filter);
+        }
+        self.print_ram_search();
+        Ok(())
+    }
+    /// Prints the first results, for the hotkeys.
+    pub fn print_ram_search(&self) {
+        let results = self.ram_search_results();
+        eprintln!("RAM search: {} results", results.len());
+        for (location, val) in results.iter().take(RAM_SEARCH_PRINTED_RESULTS) {
+            eprintln!(
+                "  {:?} bank {} {:04X}: {}",
+                location.region, location.bank, location.addr, val
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{
+        booted, booted_cgb, booted_rom, set_header_checksum, test_rom, RequestSource,
+    };
+
+    #[test]
+    fn bcd_round_trips() {
+        assert_eq!(Interpretation::Bcd8.decode(&[0x42]), Some(42));
+        assert_eq!(Interpretation::Bcd8.decode(&[0x4A]), None);
+        assert_eq!(Interpretation::Bcd16.decode(&[0x34, 0x12]), Some(1234));
+        assert_eq!(Interpretation::U16.decode(&[0x34, 0x12]), Some(0x1234));
+        assert_eq!(Interpretation::Bcd16.encode(1234), Some(vec![0x34, 0x12]));
+        assert_eq!(Interpretation::Bcd8.encode(100), None);
+        assert_eq!(Interpretation::U8.encode(0x100), None);
+    }
+
+    #[test]
+    fn finds_a_changing_counter() {
+        let mut em = booted_cgb(&[0xF3, 0x76, 0x18, 0xFD]);
+        let set = |em: &mut GameBoyEmulator, val: u8| {
+            em.write_wram_bank(3, 0xD123, val);
+            em.write_memory(0xD124_usize, val, RequestSource::SPEC);
+        };
+        set(&mut em, 5);
+        em.start_ram_search(Interpretation::U8);
+        set(&mut em, 6);
+        em.filter_ram_search(SearchFilter::Increased);
+        set(&mut em, 3);
+        em.filter_ram_search(SearchFilter::Decreased);
+        em.filter_ram_search(SearchFilter::Equal);
+        assert_eq!(em.filter_ram_search(SearchFilter::Value(3)), 2);
+        let results = em.ram_search_results();
+        let wram = |bank, addr| RamLocation {
+            region: RamRegion::Wram,
+            bank,
+            addr,
+        };
+        assert_eq!(results, vec![(wram(1, 0xD124), 3), (wram(3, 0xD123), 3)]);
+
+        assert_eq!(em.ram_search_codes(1, 99), Ok(vec!["936323D1".to_string()]));
+        em.ram_search_to_cheat(1, 99).unwrap();
+        em.apply_cheats();
+        em.filter_ram_search(SearchFilter::Changed);
+        assert_eq!(em.ram_search_results(), vec![(wram(3, 0xD123), 99)]);
+    }
+
+    #[test]
+    fn cheats_only_reach_the_mapped_cartridge_ram_bank() {
+        let mut rom = test_rom(&[0xF3, 0x76, 0x18, 0xFD]);
+        rom[CART_TYPE_ADDR] = 0x1A;
+        rom[RAM_BANK_ADDR] = 3;
+        set_header_checksum(&mut rom);
+        let mut em = booted_rom(rom);
+        em.write_memory(0x0000_usize, 0x0A, RequestSource::SPEC);
+        em.write_memory(0x4000_usize, 2, RequestSource::SPEC);
+        em.start_ram_search(Interpretation::U8);
+        let result = em
+            .ram_search_results()
+            .iter()
+            .position(|(location, _)| {
+                *location
+                    == RamLocation {
+                        region: RamRegion::Eram,
+                        bank: 2,
+                        addr: 0xA010,
+                    }
+            })
+            .unwrap();
+        assert_eq!(
+            em.ram_search_codes(result, 7),
+            Ok(vec!["010710A0".to_string()])
+        );
+        em.ram_search_to_cheat(result, 7).unwrap();
+        em.apply_cheats();
+        assert_eq!(em.get_memory(0xA010_usize, RequestSource::SPEC), 7);
+
+        em.write_memory(0x4000_usize, 0, RequestSource::SPEC);
+        assert!(em.ram_search_codes(result, 7).is_err());
+    }
+
+    #[test]
+    fn runs_console_commands() {
+        let mut em = booted(&[0xF3, 0x76, 0x18, 0xFD]);
+        em.write_memory(HRAM_START_ADDR, 0x12, RequestSource::SPEC);
+        assert!(em.ram_search_command("start bcd16").is_ok());
+        em.write_memory(HRAM_START_ADDR, 0x13, RequestSource::SPEC);
+        assert!(em.ram_search_command("increased").is_ok());
+        assert!(em.ram_search_command("value 13").is_ok());
+        let results = em.ram_search_results();
+        assert!(results
+            .iter()
+            .any(|(location, _)| location.addr == HRAM_START_ADDR));
+        assert!(em.ram_search_command("start u32").is_err());
+        assert!(em.ram_search_command("value x").is_err());
+        assert!(em.ram_search_command("jump").is_err());
+    }
+}
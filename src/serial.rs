@@ -0,0 +1,108 @@
+use crate::constants::*;
+use crate::emulator::{GameBoyEmulator, RequestSource};
+use serde::{Deserialize, Serialize};
+
+const SOURCE: RequestSource = RequestSource::Serial;
+
+/// Whatever is plugged into the link port.
+pub trait SerialDevice {
+    /// Called when the Game Boy starts a transfer on its internal clock. Receives the byte
+    /// being shifted out and returns the byte that will be shifted in over the next 8 bits.
+    fn exchange(&mut self, out: u8) -> u8;
+    /// Polled every M-cycle while the Game Boy waits on an external clock. Returns the
+    /// received byte once the device has clocked a full transfer, taking `out` in return.
+    fn external_exchange(&mut self, _out: u8) -> Option<u8> {
+        None
+    }
+    /// Called once per normal speed M-cycle, before the port advances, for devices that need
+    /// to follow emulated time. Double speed doesn't change how often it is called, so the
+    /// device sees the same time on either speed.
+    fn advance(&mut self) {}
+}
+
+/// An empty link port. With nothing driving the data line every bit reads back as 1, and
+/// nothing ever supplies an external clock.
+pub struct Disconnected;
+
+impl SerialDevice for Disconnected {
+    fn exchange(&mut self, _out: u8) -> u8 {
+        0xFF
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+pub struct SerialPort {
+    transferring: bool,
+    bits_remaining: u8,
+    bit_cycles: u32,
+    cycle_count: u32,
+    incoming: u8,
+}
+
+impl SerialPort {
+    pub fn new() -> SerialPort {
+        SerialPort {
+            transferring: false,
+            bits_remaining: 0,
+            bit_cycles: SERIAL_DMG_BIT_CYCLES,
+            cycle_count: 0,
+            incoming: 0,
+        }
+    }
+}
+
+impl GameBoyEmulator {
+    pub fn connect_serial_device(&mut self, device: Box<dyn SerialDevice>) {
+        self.serial_device = device;
+    }
+    fn set_serial_interrupt(&mut self) {
+        self.write_memory(
+            INT_FLAG_ADDR,
+            self.get_memory(INT_FLAG_ADDR, SOURCE) | 0b1000,
+            SOURCE,
+        );
+    }
+    fn finish_transfer(&mut self) {
+        self.serial.transferring = false;
+        self.write_memory(SC_ADDR, self.get_memory(SC_ADDR, SOURCE) & 0x7F, SOURCE);
+        self.set_serial_interrupt();
+    }
+    pub fn sc_write(&mut self, val: u8) {
+        self.write_memory(SC_ADDR, val, SOURCE);
+        self.serial.transferring = false;
+        if (val >> 7) == 1 && (val & 1) == 1 {
+            let out = self.get_memory(SB_ADDR, SOURCE);
+            self.serial.incoming = self.serial_device.exchange(out);
+            self.serial.transferring = true;
+            self.serial.bits_remaining = 8;
+            self.serial.cycle_count = 0;
+            self.serial.bit_cycles = if self.cgb && ((val >> 1) & 1) == 1 {
+                SERIAL_FAST_BIT_CYCLES
+            } else {
+                SERIAL_DMG_BIT_CYCLES
+            };
+        }
+    }
+    pub fn serial_advance(&mut self) {
+        let sc = self.get_memory(SC_ADDR, SOURCE);
+        if self.serial.transferring {
+            self.serial.cycle_count += ADVANCE_CYCLES;
+            if self.serial.cycle_count == self.serial.bit_cycles {
+                self.serial.cycle_count = 0;
+                let sb = (self.get_memory(SB_ADDR, SOURCE) << 1) | (self.serial.incoming >> 7);
+                self.serial.incoming <<= 1;
+                self.write_memory(SB_ADDR, sb, SOURCE);
+                self.serial.bits_remaining -= 1;
+                if self.serial.bits_remaining == 0 {
+                    self.finish_transfer();
+                }
+            }
+        } else if (sc >> 7) == 1 && (sc & 1) == 0 {
+            let out = self.get_memory(SB_ADDR, SOURCE);
+            if let Some(incoming) = self.serial_device.external_exchange(out) {
+                self.write_memory(SB_ADDR, incoming, SOURCE);
+                self.finish_transfer();
+            }
+        }
+    }
+}
@@ -0,0 +1,635 @@
+use crate::constants::*;
+use crate::emulator::{GameBoyEmulator, RequestSource};
+use crate::memory::convert_to_8_bit;
+use serde::{Deserialize, Serialize};
+use std::mem;
+
+// The SGB sees VRAM transfers through what the PPU puts on the screen.
+const SOURCE: RequestSource = RequestSource::PPU;
+
+const PAL01: u8 = 0x00;
+const PAL23: u8 = 0x01;
+const PAL03: u8 = 0x02;
+const PAL12: u8 = 0x03;
+const ATTR_BLK: u8 = 0x04;
+const ATTR_LIN: u8 = 0x05;
+const ATTR_DIV: u8 = 0x06;
+const ATTR_CHR: u8 = 0x07;
+const PAL_SET: u8 = 0x0A;
+const PAL_TRN: u8 = 0x0B;
+const MLT_REQ: u8 = 0x11;
+const CHR_TRN: u8 = 0x13;
+const PCT_TRN: u8 = 0x14;
+const MASK_EN: u8 = 0x17;
+
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum ScreenMask {
+    Off,
+    /// Keeps showing the last frame.
+    Freeze,
+    Black,
+    /// Fills the screen with color 0.
+    Color0,
+}
+
+/// Super Game Boy state: the packet being received over P1, the colors and attributes the
+/// game has sent and the border around the screen.
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Sgb {
+    packet: Vec<u8>,
+    bits: usize,
+    receiving: bool,
+    command: Vec<u8>,
+    pub palettes: [[u16; 4]; 4],
+    system_palettes: Vec<u16>,
+    /// The palette of each 8x8 cell of the screen.
+    pub attributes: Vec<u8>,
+    pub mask: ScreenMask,
+    frozen: Vec<u8>,
+    border_tiles: Vec<u8>,
+    border_map: Vec<u8>,
+    border_palettes: Vec<[u16; 16]>,
+    /// The border drawn from the tiles, map and palettes above.
+    border: Vec<u8>,
+    pub border_loaded: bool,
+    pub players: usize,
+    pub player: usize,
+    joypads: [(u8, u8); 4],
+}
+
+impl Sgb {
+    pub fn new() -> Sgb {
+        Sgb {
+            packet: Vec::new(),
+            bits: 0,
+            receiving: false,
+            command: Vec::new(),
+            palettes: [SGB_DEFAULT_PALETTE; 4],
+            system_palettes: vec![0; SGB_SYSTEM_PALETTES * 4],
+            attributes: vec![0; SGB_ATTR_COLUMNS * SGB_ATTR_ROWS],
+            mask: ScreenMask::Off,
+            frozen: Vec::new(),
+            border_tiles: vec![0; SGB_BORDER_TILES * SGB_BORDER_TILE_SIZE],
+            border_map: vec![0; SGB_BORDER_MAP_SIZE],
+            border_palettes: vec![[0; 16]; SGB_BORDER_PALETTES],
+            border: Vec::new(),
+            border_loaded: false,
+            players: 1,
+            player: 0,
+            joypads: [(0xF, 0xF); 4],
+        }
+    }
+    /// Sets the palette of the cells from (`x1`, `y1`) to (`x2`, `y2`), inclusive.
+    fn fill_attributes(&mut self, x1: usize, y1: usize, x2: usize, y2: usize, palette: u8) {
+        for y in y1..=y2.min(SGB_ATTR_ROWS - 1) {
+            for x in x1..=x2.min(SGB_ATTR_COLUMNS - 1) {
+                self.attributes[y * SGB_ATTR_COLUMNS + x] = palette;
+            }
+        }
+    }
+    fn attr_blk(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for set in data[2..].chunks_exact(6).take(count) {
+            let (control, palettes) = (set[0], set[1]);
+            let (x1, y1, x2, y2) = (
+                set[2] as usize & 0x1F,
+                set[3] as usize & 0x1F,
+                set[4] as usize & 0x1F,
+                set[5] as usize & 0x1F,
+            );
+            let inside = palettes & 0b11;
+            let outside = (palettes >> 4) & 0b11;
+            // With only the inside or only the outside set, the border takes its palette.
+            let border = match control & 0b111 {
+                0b001 => Some(inside),
+                0b100 => Some(outside),
+                control if control & 0b010 != 0 => Some((palettes >> 2) & 0b11),
+                _ => None,
+            };
+            for y in 0..SGB_ATTR_ROWS {
+                for x in 0..SGB_ATTR_COLUMNS {
+                    let within = (x1..=x2).contains(&x) && (y1..=y2).contains(&y);
+                    let on_edge = within && (x == x1 || x == x2 || y == y1 || y == y2);
+                    let palette = if on_edge {
+                        border
+                    } else if within && control & 0b001 != 0 {
+                        Some(inside)
+                    } else if !within && control & 0b100 != 0 {
+                        Some(outside)
+                    } else {
+                        None
+                    };
+                    if let Some(palette) = palette {
+                        self.attributes[y * SGB_ATTR_COLUMNS + x] = palette;
+                    }
+                }
+            }
+        }
+    }
+    fn attr_lin(&mut self, data: &[u8]) {
+        let count = data[1] as usize;
+        for line in data[2..].iter().take(count) {
+            let index = (line & 0x1F) as usize;
+            let palette = (line >> 5) & 0b11;
+            if line >> 7 == 1 {
+                self.fill_attributes(0, index, SGB_ATTR_COLUMNS - 1, index, palette);
+            } else {
+                self.fill_attributes(index, 0, index, SGB_ATTR_ROWS - 1, palette);
+            }
+        }
+    }
+    fn attr_div(&mut self, data: &[u8]) {
+        let (after, before, line) = (data[1] & 0b11, (data[1] >> 2) & 0b11, (data[1] >> 4) & 0b11);
+        let at = data[2] as usize;
+        for y in 0..SGB_ATTR_ROWS {
+            for x in 0..SGB_ATTR_COLUMNS {
+                let position = if (data[1] >> 6) & 1 == 1 { y } else { x };
+                self.attributes[y * SGB_ATTR_COLUMNS + x] = match position.cmp(&at) {
+                    std::cmp::Ordering::Less => before,
+                    std::cmp::Ordering::Equal => line,
+                    std::cmp::Ordering::Greater => after,
+                };
+            }
+        }
+    }
+    fn attr_chr(&mut self, data: &[u8]) {
+        let (mut x, mut y) = (data[1] as usize, data[2] as usize);
+        let count = (data[3] as usize | (data[4] as usize) << 8).min(self.attributes.len());
+        let vertical = data[5] == 1;
+        for i in 0..count {
+            let byte = match data.get(6 + i / 4) {
+                Some(byte) => *byte,
+                None => break,
+            };
+            if x < SGB_ATTR_COLUMNS && y < SGB_ATTR_ROWS {
+                self.attributes[y * SGB_ATTR_COLUMNS + x] = (byte >> (6 - (i % 4) * 2)) & 0b11;
+            }
+            if vertical {
+                y += 1;
+                if y == SGB_ATTR_ROWS {
+                    y = 0;
+                    x += 1;
+                }
+            } else {
+                x += 1;
+                if x == SGB_ATTR_COLUMNS {
+                    x = 0;
+                    y += 1;
+                }
+            }
+        }
+    }
+    /// Returns the presses of the joypad the game is reading, and keeps the others for when
+    /// it moves on to them in multiplayer mode.
+    pub fn select_joypad(&mut self, joypads: [(u8, u8); 2]) -> (u8, u8) {
+        self.joypads[..2].copy_from_slice(&joypads);
+        self.joypads[self.player]
+    }
+    /// The RGBA color for one of the SNES's 15 bit colors.
+    fn color(color: u16) -> [u8; 4] {
+        [
+            convert_to_8_bit((color & 0x1F) as u8),
+            convert_to_8_bit(((color >> 5) & 0x1F) as u8),
+            convert_to_8_bit(((color >> 10) & 0x1F) as u8),
+            0xFF,
+        ]
+    }
+    /// The color index of pixel (`x`, `y`) of a 4 bit per pixel SNES border tile.
+    fn border_pixel(&self, tile: usize, x: usize, y: usize) -> usize {
+        let data = &self.border_tiles[tile * SGB_BORDER_TILE_SIZE..];
+        let bit = 7 - x;
+        let planes = [
+            data[y * 2],
+            data[y * 2 + 1],
+            data[16 + y * 2],
+            data[17 + y * 2],
+        ];
+        planes
+            .iter()
+            .enumerate()
+            .map(|(plane, byte)| (((byte >> bit) & 1) as usize) << plane)
+            .sum()
+    }
+    /// Draws the whole border, with color 0 showing the backdrop.
+    fn render_border(&mut self) {
+        let backdrop = Sgb::color(self.palettes[0][0]);
+        let mut border = vec![0; SGB_BORDER_WIDTH * SGB_BORDER_HEIGHT * PIXEL_LENGTH];
+        for (i, pixel) in border.chunks_exact_mut(PIXEL_LENGTH).enumerate() {
+            let (x, y) = (i % SGB_BORDER_WIDTH, i / SGB_BORDER_WIDTH);
+            let cell = (y / TILE_WIDTH) * SGB_BORDER_COLUMNS + x / TILE_WIDTH;
+            let entry =
+                u16::from_le_bytes([self.border_map[2 * cell], self.border_map[2 * cell + 1]]);
+            let (mut tile_x, mut tile_y) = (x % TILE_WIDTH, y % TILE_WIDTH);
+            if (entry >> 14) & 1 == 1 {
+                tile_x = TILE_WIDTH - 1 - tile_x;
+            }
+            if (entry >> 15) & 1 == 1 {
+                tile_y = TILE_WIDTH - 1 - tile_y;
+            }
+            let index = self.border_pixel((entry & 0xFF) as usize, tile_x, tile_y);
+            // The border uses the SNES's palettes 4 to 7.
+            let palette = (((entry >> 10) & 0b111) as usize)
+                .saturating_sub(SGB_BORDER_PALETTE_OFFSET)
+                % SGB_BORDER_PALETTES;
+            let color = if index == 0 {
+                backdrop
+            } else {
+                Sgb::color(self.border_palettes[palette][index])
+            };
+            pixel.copy_from_slice(&color);
+        }
+        self.border = border;
+    }
+}
+
+fn words(bytes: &[u8]) -> impl Iterator<Item = u16> + '_ {
+    bytes
+        .chunks_exact(2)
+        .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+}
+
+impl GameBoyEmulator {
+    /// Watches CPU writes to P1 for the pulses that carry command packets and for the
+    /// joypad changes of multiplayer mode. A low P14 sends a 0 bit, a low P15 sends a 1 and
+    /// both low starts a packet, with both lines going high again between pulses.
+    pub fn sgb_p1_write(&mut self, previous: u8, val: u8) {
+        let sgb = match self.sgb.as_mut() {
+            Some(sgb) => sgb,
+            None => return,
+        };
+        let (previous, val) = (previous & 0x30, val & 0x30);
+        // P15 going high again moves on to the next joypad.
+        if sgb.players > 1 && !sgb.receiving && previous & 0x20 == 0 && val & 0x20 != 0 {
+            sgb.player = (sgb.player + 1) % sgb.players;
+            let (directional_presses, action_presses) = sgb.joypads[sgb.player];
+            self.mem_unit.directional_presses = directional_presses;
+            self.mem_unit.action_presses = action_presses;
+        }
+        if previous != 0x30 {
+            return;
+        }
+        let mut packet = None;
+        match val {
+            0x00 => {
+                sgb.receiving = true;
+                sgb.packet.clear();
+                sgb.bits = 0;
+            }
+            0x10 | 0x20 if sgb.receiving => {
+                if sgb.bits % 8 == 0 {
+                    sgb.packet.push(0);
+                }
+                if val == 0x10 {
+                    *sgb.packet.last_mut().unwrap() |= 1 << (sgb.bits % 8);
+                }
+                sgb.bits += 1;
+                // The stop bit that follows is ignored.
+                if sgb.bits == SGB_PACKET_LENGTH * 8 {
+                    sgb.receiving = false;
+                    packet = Some(mem::take(&mut sgb.packet));
+                }
+            }
+            _ => {}
+        }
+        if let Some(packet) = packet {
+            self.sgb_packet(packet);
+        }
+    }
+    /// Collects packets until the command they belong to is complete, then runs it.
+    fn sgb_packet(&mut self, packet: Vec<u8>) {
+        let sgb = self.sgb.as_mut().unwrap();
+        sgb.command.extend_from_slice(&packet);
+        let length = (sgb.command[0] & 0b111).max(1) as usize;
+        if sgb.command.len() < length * SGB_PACKET_LENGTH {
+            return;
+        }
+        let command = mem::take(&mut sgb.command);
+        self.sgb_command(&command);
+    }
+    fn sgb_command(&mut self, data: &[u8]) {
+        let transfer = match data[0] >> 3 {
+            PAL_TRN | CHR_TRN | PCT_TRN => self.sgb_vram_transfer(),
+            _ => Vec::new(),
+        };
+        let scale = self.window_scale();
+        let sgb = self.sgb.as_mut().unwrap();
+        let had_border = sgb.border_loaded;
+        let backdrop = sgb.palettes[0][0];
+        match data[0] >> 3 {
+            command @ (PAL01 | PAL23 | PAL03 | PAL12) => {
+                let (first, second) = match command {
+                    PAL01 => (0, 1),
+                    PAL23 => (2, 3),
+                    PAL03 => (0, 3),
+                    _ => (1, 2),
+                };
+                let colors: Vec<u16> = words(&data[1..15]).collect();
+                // Color 0 is shared by all four palettes.
+                for palette in sgb.palettes.iter_mut() {
+                    palette[0] = colors[0];
+                }
+                sgb.palettes[first][1..].copy_from_slice(&colors[1..4]);
+                sgb.palettes[second][1..].copy_from_slice(&colors[4..7]);
+            }
+            ATTR_BLK => sgb.attr_blk(data),
+            ATTR_LIN => sgb.attr_lin(data),
+            ATTR_DIV => sgb.attr_div(data),
+            ATTR_CHR => sgb.attr_chr(data),
+            PAL_SET => {
+                let numbers: Vec<usize> = words(&data[1..9])
+                    .map(|number| number as usize % SGB_SYSTEM_PALETTES)
+                    .collect();
+                // Color 0 of the first palette is shared by all four.
+                let color_0 = sgb.system_palettes[numbers[0] * 4];
+                for (palette, number) in sgb.palettes.iter_mut().zip(numbers) {
+                    palette.copy_from_slice(&sgb.system_palettes[number * 4..number * 4 + 4]);
+                    palette[0] = color_0;
+                }
+                if (data[9] >> 6) & 1 == 1 {
+                    sgb.mask = ScreenMask::Off;
+                }
+            }
+            PAL_TRN => sgb.system_palettes = words(&transfer).collect(),
+            MLT_REQ => {
+                sgb.players = match data[1] & 0b11 {
+                    1 => 2,
+                    3 => 4,
+                    _ => 1,
+                };
+                sgb.player = 0;
+            }
+            CHR_TRN => {
+                let start = (data[1] & 1) as usize * SGB_TRANSFER_SIZE;
+                sgb.border_tiles[start..start + SGB_TRANSFER_SIZE].copy_from_slice(&transfer);
+            }
+            PCT_TRN => {
+                sgb.border_map
+                    .copy_from_slice(&transfer[..SGB_BORDER_MAP_SIZE]);
+                let colors: Vec<u16> = words(&transfer[SGB_BORDER_MAP_SIZE..]).collect();
+                for (palette, colors) in sgb.border_palettes.iter_mut().zip(colors.chunks(16)) {
+                    palette.copy_from_slice(colors);
+                }
+                sgb.border_loaded = true;
+            }
+            MASK_EN => {
+                sgb.mask = match data[1] & 0b11 {
+                    1 => ScreenMask::Freeze,
+                    2 => ScreenMask::Black,
+                    3 => ScreenMask::Color0,
+                    _ => ScreenMask::Off,
+                };
+                sgb.frozen.clear();
+            }
+            _ => {}
+        }
+        let border_changed = matches!(data[0] >> 3, CHR_TRN | PCT_TRN);
+        if sgb.border_loaded && (border_changed || sgb.palettes[0][0] != backdrop) {
+            sgb.render_border();
+        }
+        if !had_border && sgb.border_loaded {
+            self.fit_window_to_screen(scale);
+        }
+    }
+    /// The 4KB the SGB reads off the screen for a transfer: the tiles of the first 256
+    /// background map entries, row by row.
+    fn sgb_vram_transfer(&self) -> Vec<u8> {
+        let lcdc = self.get_memory(LCDC_ADDR, SOURCE);
+        let map = if (lcdc >> 3) & 1 == 1 {
+            TILE_MAP_2_START_ADDR
+        } else {
+            TILE_MAP_1_START_ADDR
+        };
+        let mut data = Vec::with_capacity(SGB_TRANSFER_SIZE);
+        for i in 0..SGB_TRANSFER_SIZE / BYTES_PER_TILE {
+            let entry = map + (i / SGB_ATTR_COLUMNS) * TILES_PER_ROW + i % SGB_ATTR_COLUMNS;
+            let index = self.get_memory(entry, SOURCE) as usize;
+            let tile = if (lcdc >> 4) & 1 == 1 {
+                VRAM_START_ADDR + index * BYTES_PER_TILE
+            } else {
+                VRAM_START_ADDR + 0x1000 + (index as i8 as isize * BYTES_PER_TILE as isize) as usize
+            };
+            for byte in 0..BYTES_PER_TILE {
+                data.push(self.get_memory(tile + byte, SOURCE));
+            }
+        }
+        data
+    }
+    /// In multiplayer mode P1 reads the current joypad's number while no buttons are
+    /// selected.
+    pub fn sgb_joypad_id(&self) -> Option<u8> {
+        match &self.sgb {
+            Some(sgb) if sgb.players > 1 => Some(sgb.player as u8),
+            _ => None,
+        }
+    }
+    /// Recolors the finished frame with the palette of each attribute cell, or applies the
+    /// screen mask. A frozen screen keeps the first frame finished after the freeze.
+    pub fn sgb_finish_frame(&mut self) {
+        let sgb = match self.sgb.as_mut() {
+            Some(sgb) => sgb,
+            None => return,
+        };
+        let fill = match sgb.mask {
+            ScreenMask::Freeze if !sgb.frozen.is_empty() => {
+                self.frame.copy_from_slice(&sgb.frozen);
+                return;
+            }
+            ScreenMask::Black => Some([0, 0, 0, 0xFF]),
+            ScreenMask::Color0 => Some(Sgb::color(sgb.palettes[0][0])),
+            _ => None,
+        };
+        for (i, pixel) in self.frame.chunks_exact_mut(PIXEL_LENGTH).enumerate() {
+            if let Some(color) = fill {
+                pixel.copy_from_slice(&color);
+                continue;
+            }
+            let (x, y) = (i % WINDOW_WIDTH, i / WINDOW_WIDTH);
+            if let Some(shade) = SGB_SHADE_KEYS.iter().position(|key| key == pixel) {
+                let palette =
+                    sgb.attributes[(y / BG_TILE_HEIGHT) * SGB_ATTR_COLUMNS + x / TILE_WIDTH];
+                pixel.copy_from_slice(&Sgb::color(sgb.palettes[palette as usize][shade]));
+            }
+        }
+        if sgb.mask == ScreenMask::Freeze {
+            sgb.frozen = self.frame.clone();
+        }
+    }
+    /// The size of what the window shows: the border once a game has sent one, otherwise
+    /// just the screen.
+    pub fn screen_size(&self) -> (usize, usize) {
+        match &self.sgb {
+            Some(sgb) if sgb.border_loaded => (SGB_BORDER_WIDTH, SGB_BORDER_HEIGHT),
+            _ => (WINDOW_WIDTH, WINDOW_HEIGHT),
+        }
+    }
+    /// Places `frame` inside the SGB border, if there is one. Only the game screen is copied
+    /// each frame, on top of the border drawn when it last changed.
+    pub fn sgb_screen(&self, frame: Vec<u8>) -> (Vec<u8>, usize, usize) {
+        let sgb = match &self.sgb {
+            Some(sgb) if sgb.border_loaded => sgb,
+            _ => return (frame, WINDOW_WIDTH, WINDOW_HEIGHT),
+        };
+        let mut screen = sgb.border.clone();
+        for (row, line) in frame.chunks_exact(WINDOW_WIDTH * PIXEL_LENGTH).enumerate() {
+            let start = ((SGB_SCREEN_Y + row) * SGB_BORDER_WIDTH + SGB_SCREEN_X) * PIXEL_LENGTH;
+            screen[start..start + line.len()].copy_from_slice(line);
+        }
+        (screen, SGB_BORDER_WIDTH, SGB_BORDER_HEIGHT)
+    }
+    /// Grows the window to fit the border at the scale it had before.
+    fn fit_window_to_screen(&mut self, scale: usize) {
+        let (width, height) = self.screen_size();
+        if let Some(window) = self.window.as_mut() {
+            let resized = window
+                .set_minimum_size(width as u32, height as u32)
+                .and_then(|_| window.set_size((width * scale) as u32, (height * scale) as u32));
+            if let Err(error) = resized {
+                eprintln!("Could not fit the window to the border: {}", error);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{run_to_program, set_header_checksum, test_rom};
+    use crate::model::Model;
+
+    fn sgb_game() -> GameBoyEmulator {
+        let mut rom = test_rom(&[0xF3, 0x76, 0x18, 0xFD]);
+        rom[SGB_FLAG_ADDR] = SGB_SUPPORT_FLAG;
+        rom[OLD_LICENSEE_ADDR] = SGB_LICENSEE;
+        set_header_checksum(&mut rom);
+        let mut em = GameBoyEmulator::new_headless();
+        em.model = Model::Sgb;
+        em.boot_roms.skip = true;
+        em.load_rom_data(rom);
+        run_to_program(&mut em);
+        em
+    }
+
+    fn send(em: &mut GameBoyEmulator, command: u8, data: &[u8]) {
+        let mut bytes = vec![(command << 3) | 1];
+        bytes.extend_from_slice(data);
+        bytes.resize(SGB_PACKET_LENGTH, 0);
+        em.write_memory(P1_ADDR, 0x30, RequestSource::CPU);
+        em.write_memory(P1_ADDR, 0x00, RequestSource::CPU);
+        em.write_memory(P1_ADDR, 0x30, RequestSource::CPU);
+        for byte in bytes {
+            for bit in 0..8 {
+                let pulse = if (byte >> bit) & 1 == 1 { 0x10 } else { 0x20 };
+                em.write_memory(P1_ADDR, pulse, RequestSource::CPU);
+                em.write_memory(P1_ADDR, 0x30, RequestSource::CPU);
+            }
+        }
+        em.write_memory(P1_ADDR, 0x20, RequestSource::CPU);
+        em.write_memory(P1_ADDR, 0x30, RequestSource::CPU);
+    }
+
+    #[test]
+    fn colors_cells_with_their_palettes() {
+        let mut em = sgb_game();
+        assert!(em.sgb.is_some());
+        // Palette 0 gets red for shade 3 and palette 1 gets blue.
+        let mut colors = vec![0xFF, 0x7F, 0, 0, 0, 0, 0x1F, 0x00];
+        colors.extend_from_slice(&[0, 0, 0, 0, 0x00, 0x7C]);
+        send(&mut em, PAL01, &colors);
+        // The right half of the screen uses palette 1.
+        send(&mut em, ATTR_DIV, &[0b01_00_01, 10]);
+        assert_eq!(em.sgb.as_ref().unwrap().attributes[9], 0);
+        assert_eq!(em.sgb.as_ref().unwrap().attributes[10], 1);
+
+        for pixel in em.frame.chunks_exact_mut(PIXEL_LENGTH) {
+            pixel.copy_from_slice(&SGB_SHADE_KEYS[3]);
+        }
+        em.sgb_finish_frame();
+        assert_eq!(em.frame[..PIXEL_LENGTH], [0xFF, 0, 0, 0xFF]);
+        let right = (WINDOW_WIDTH - 1) * PIXEL_LENGTH;
+        assert_eq!(em.frame[right..right + PIXEL_LENGTH], [0, 0, 0xFF, 0xFF]);
+
+        send(&mut em, ATTR_BLK, &[1, 0b011, 0b10_11, 2, 2, 4, 4]);
+        let attributes = &em.sgb.as_ref().unwrap().attributes;
+        assert_eq!(attributes[3 * SGB_ATTR_COLUMNS + 3], 3);
+        assert_eq!(attributes[2 * SGB_ATTR_COLUMNS + 2], 2);
+        assert_eq!(attributes[SGB_ATTR_COLUMNS + 1], 0);
+
+        send(&mut em, MASK_EN, &[3]);
+        em.sgb_finish_frame();
+        assert_eq!(em.frame[..PIXEL_LENGTH], [0xFF, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn multiplayer_switches_joypads() {
+        let mut em = sgb_game();
+        assert_eq!(em.get_memory(P1_ADDR, RequestSource::CPU) & 0xF, 0xF);
+        send(&mut em, MLT_REQ, &[1]);
+        em.sgb
+            .as_mut()
+            .unwrap()
+            .select_joypad([(0xF, 0xF), (0xE, 0xF)]);
+        em.write_memory(P1_ADDR, 0x30, RequestSource::CPU);
+        assert_eq!(em.get_memory(P1_ADDR, RequestSource::CPU) & 0xF, 0xF);
+        em.write_memory(P1_ADDR, 0x10, RequestSource::CPU);
+        em.write_memory(P1_ADDR, 0x30, RequestSource::CPU);
+        assert_eq!(em.get_memory(P1_ADDR, RequestSource::CPU) & 0xF, 0xE);
+        // Player 2 is holding right.
+        em.write_memory(P1_ADDR, 0x20, RequestSource::CPU);
+        assert_eq!(em.get_memory(P1_ADDR, RequestSource::CPU) & 0xF, 0xE);
+    }
+
+    #[test]
+    fn transfers_a_border() {
+        let mut em = sgb_game();
+        assert_eq!(em.screen_size(), (WINDOW_WIDTH, WINDOW_HEIGHT));
+        // Show tiles 0-255 in order, with tile data at 0x8000.
+        em.write_memory(LCDC_ADDR, 0x91, RequestSource::SPEC);
+        for i in 0..SGB_TRANSFER_SIZE / BYTES_PER_TILE {
+            let entry = TILE_MAP_1_START_ADDR + (i / 20) * TILES_PER_ROW + i % 20;
+            em.write_memory(entry, i as u8, RequestSource::PPU);
+        }
+        // Tile 1 has color 1 in its top left pixel.
+        em.write_memory(
+            VRAM_START_ADDR + SGB_BORDER_TILE_SIZE,
+            0x80,
+            RequestSource::PPU,
+        );
+        send(&mut em, CHR_TRN, &[0]);
+        for i in 0..SGB_TRANSFER_SIZE {
+            em.write_memory(VRAM_START_ADDR + i, 0, RequestSource::PPU);
+        }
+        // The top left map entry uses tile 1 with palette 4, whose color 1 is green.
+        em.write_memory(VRAM_START_ADDR, 0x01, RequestSource::PPU);
+        em.write_memory(VRAM_START_ADDR + 1, 0x10, RequestSource::PPU);
+        em.write_memory(
+            VRAM_START_ADDR + SGB_BORDER_MAP_SIZE + 2,
+            0xE0,
+            RequestSource::PPU,
+        );
+        em.write_memory(
+            VRAM_START_ADDR + SGB_BORDER_MAP_SIZE + 3,
+            0x03,
+            RequestSource::PPU,
+        );
+        send(&mut em, PCT_TRN, &[]);
+
+        assert_eq!(em.screen_size(), (SGB_BORDER_WIDTH, SGB_BORDER_HEIGHT));
+        let mut frame = vec![0; WINDOW_WIDTH * WINDOW_HEIGHT * PIXEL_LENGTH];
+        frame[..PIXEL_LENGTH].copy_from_slice(&[1, 2, 3, 4]);
+        let (screen, width, height) = em.sgb_screen(frame);
+        assert_eq!((width, height), (SGB_BORDER_WIDTH, SGB_BORDER_HEIGHT));
+        assert_eq!(screen[..PIXEL_LENGTH], [0, 0xFF, 0, 0xFF]);
+        assert_eq!(
+            screen[PIXEL_LENGTH..PIXEL_LENGTH * 2],
+            Sgb::color(SGB_DEFAULT_PALETTE[0])
+        );
+        let game = (SGB_SCREEN_Y * SGB_BORDER_WIDTH + SGB_SCREEN_X) * PIXEL_LENGTH;
+        assert_eq!(screen[game..game + PIXEL_LENGTH], [1, 2, 3, 4]);
+
+        // A new backdrop color shows up in the border without another transfer.
+        send(&mut em, PAL01, &[0x1F, 0x00]);
+        let (screen, _, _) = em.sgb_screen(vec![0; WINDOW_WIDTH * WINDOW_HEIGHT * PIXEL_LENGTH]);
+        assert_eq!(screen[PIXEL_LENGTH..PIXEL_LENGTH * 2], [0xFF, 0, 0, 0xFF]);
+        assert_eq!(screen[..PIXEL_LENGTH], [0, 0xFF, 0, 0xFF]);
+    }
+}
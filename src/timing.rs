@@ -1,26 +1,32 @@
 use crate::constants::*;
 use crate::emulator::{GameBoyEmulator, RequestSource};
 use serde::{Deserialize, Serialize};
-const TAC_MAPPING: [u32; 4] = [1024, 16, 64, 256];
+
+// Bit of the internal divider whose falling edge clocks TIMA for each TAC clock select.
+const TAC_BIT_MAPPING: [u16; 4] = [9, 3, 5, 7];
 
 const SOURCE: RequestSource = RequestSource::Timer;
 
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+enum TimaState {
+    Counting,
+    Overflowed,
+    Reloading,
+}
+
 #[derive(Serialize, Deserialize, Clone, Copy)]
 pub struct Timer {
-    prev_clock: u32,
-    tima_counter: u32,
-    div_counter: u32,
+    divider: u16,
+    tima_state: TimaState,
 }
 
 impl Timer {
     pub fn new() -> Timer {
-        let prev_clock = 64;
-        let tima_counter = 0;
-        let div_counter = 0;
+        let divider = 0;
+        let tima_state = TimaState::Counting;
         Timer {
-            prev_clock,
-            tima_counter,
-            div_counter,
+            divider,
+            tima_state,
         }
     }
 }
@@ -32,36 +38,161 @@ impl GameBoyEmulator {
             SOURCE,
         );
     }
+    fn timer_signal(&self, divider: u16, tac: u8) -> bool {
+        let enabled = (tac >> 2) & 1 == 1;
+        let bit = TAC_BIT_MAPPING[tac as usize & 0b11];
+        enabled && (divider >> bit) & 1 == 1
+    }
+    fn increment_tima(&mut self) {
+        let tima = self.get_memory(TIMA_ADDR, SOURCE).wrapping_add(1);
+        if tima == 0 {
+            self.timer.tima_state = TimaState::Overflowed;
+        }
+        self.write_memory(TIMA_ADDR, tima, SOURCE);
+    }
+    pub fn set_divider(&mut self, divider: u16) {
+        let tac = self.get_memory(TAC_ADDR, SOURCE);
+        let old_signal = self.timer_signal(self.timer.divider, tac);
+        self.timer.divider = divider;
+        self.write_memory(DIV_ADDR, (divider >> 8) as u8, SOURCE);
+        if old_signal && !self.timer_signal(divider, tac) {
+            self.increment_tima();
+        }
+    }
+    pub fn div_write(&mut self) {
+        self.set_divider(0);
+    }
+    pub fn tac_write(&mut self, val: u8) {
+        let old_tac = self.get_memory(TAC_ADDR, SOURCE);
+        let old_signal = self.timer_signal(self.timer.divider, old_tac);
+        self.write_memory(TAC_ADDR, val, SOURCE);
+        if old_signal && !self.timer_signal(self.timer.divider, val) {
+            self.increment_tima();
+        }
+    }
+    pub fn tima_write(&mut self, val: u8) {
+        match self.timer.tima_state {
+            TimaState::Counting => self.write_memory(TIMA_ADDR, val, SOURCE),
+            TimaState::Overflowed => {
+                // A write in the cycle between the overflow and the reload cancels the reload.
+                self.timer.tima_state = TimaState::Counting;
+                self.write_memory(TIMA_ADDR, val, SOURCE);
+            }
+            TimaState::Reloading => {}
+        }
+    }
+    pub fn tma_write(&mut self, val: u8) {
+        self.write_memory(TMA_ADDR, val, SOURCE);
+        if self.timer.tima_state == TimaState::Reloading {
+            self.write_memory(TIMA_ADDR, val, SOURCE);
+        }
+    }
     pub fn timer_advance(&mut self) {
-        let tac = self.get_memory(TAC_ADDR, SOURCE) as usize & 0x7;
-        if (tac >> 2) == 1 {
-            let clock = TAC_MAPPING[tac & 0b11];
-            self.timer.tima_counter = if clock == self.timer.prev_clock {
-                self.timer.tima_counter
-            } else {
-                self.timer.prev_clock = clock;
-                0
-            };
-            self.timer.tima_counter += ADVANCE_CYCLES;
-            if self.timer.tima_counter == clock {
-                self.timer.tima_counter = 0;
-                let mut tima = self.get_memory(TIMA_ADDR, SOURCE);
-                tima = (tima).wrapping_add(1);
-                if tima == 0 {
-                    tima = self.get_memory(TMA_ADDR, SOURCE);
-                    self.set_timer_interrupt();
-                }
-                self.write_memory(TIMA_ADDR, tima, SOURCE);
+        match self.timer.tima_state {
+            TimaState::Overflowed => {
+                self.write_memory(TIMA_ADDR, self.get_memory(TMA_ADDR, SOURCE), SOURCE);
+                self.set_timer_interrupt();
+                self.timer.tima_state = TimaState::Reloading;
+            }
+            TimaState::Reloading => self.timer.tima_state = TimaState::Counting,
+            TimaState::Counting => {}
+        }
+        self.set_divider(self.timer.divider.wrapping_add(ADVANCE_CYCLES as u16));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TIMER_INT: u8 = 0b100;
+
+    /// A halted cartridge with the timer counting on divider bit 3 from a cleared state.
+    fn timer() -> GameBoyEmulator {
+        let mut em = GameBoyEmulator::new_headless();
+        em.tac_write(0b101);
+        em.set_divider(0);
+        em.tima_write(0);
+        em.write_memory(INT_FLAG_ADDR, 0, SOURCE);
+        em
+    }
+
+    fn tima(em: &GameBoyEmulator) -> u8 {
+        em.get_memory(TIMA_ADDR, SOURCE)
+    }
+
+    #[test]
+    fn div_write_on_a_falling_edge_increments_tima() {
+        let mut em = timer();
+        em.set_divider(1 << 2);
+        em.div_write();
+        assert_eq!(tima(&em), 0);
+        em.set_divider(1 << 3);
+        em.div_write();
+        assert_eq!(tima(&em), 1);
+        assert_eq!(em.get_memory(DIV_ADDR, SOURCE), 0);
+    }
+
+    #[test]
+    fn tac_selects_the_divider_bit() {
+        for (select, &bit) in TAC_BIT_MAPPING.iter().enumerate() {
+            let mut em = timer();
+            em.tac_write(0b100 | select as u8);
+            let mut advances = 0;
+            while tima(&em) == 0 {
+                em.timer_advance();
+                advances += 1;
             }
+            assert_eq!(advances, (2 << bit) / ADVANCE_CYCLES, "TAC {}", select);
         }
-        self.timer.div_counter += ADVANCE_CYCLES;
-        if self.timer.div_counter == CYCLE_COUNT_16384HZ {
-            self.write_memory(
-                DIV_ADDR,
-                self.get_memory(DIV_ADDR, SOURCE).wrapping_add(1),
-                SOURCE,
-            );
-            self.timer.div_counter = 0;
+        // With the timer disabled TIMA stays put.
+        let mut em = timer();
+        em.tac_write(0b001);
+        for _ in 0..0x100 {
+            em.timer_advance();
         }
+        assert_eq!(tima(&em), 0);
+    }
+
+    /// Counts TIMA up to 0xFF with TMA at 0x42, then advances to the overflow.
+    fn overflowed() -> GameBoyEmulator {
+        let mut em = timer();
+        em.tma_write(0x42);
+        em.tima_write(0xFF);
+        em.set_divider((1 << 4) - ADVANCE_CYCLES as u16);
+        em.timer_advance();
+        assert!(em.timer.tima_state == TimaState::Overflowed);
+        em
+    }
+
+    #[test]
+    fn overflow_reloads_a_cycle_later() {
+        let mut em = overflowed();
+        assert_eq!(tima(&em), 0);
+        assert_eq!(em.get_memory(INT_FLAG_ADDR, SOURCE) & TIMER_INT, 0);
+        em.timer_advance();
+        assert_eq!(tima(&em), 0x42);
+        assert_eq!(em.get_memory(INT_FLAG_ADDR, SOURCE) & TIMER_INT, TIMER_INT);
+        em.timer_advance();
+        assert!(em.timer.tima_state == TimaState::Counting);
+    }
+
+    #[test]
+    fn writes_around_the_reload() {
+        // Writing TIMA before the reload cancels it.
+        let mut em = overflowed();
+        em.tima_write(0x10);
+        em.timer_advance();
+        assert_eq!(tima(&em), 0x10);
+        assert_eq!(em.get_memory(INT_FLAG_ADDR, SOURCE) & TIMER_INT, 0);
+
+        // During the reload TIMA writes are ignored and TMA writes go through to TIMA.
+        let mut em = overflowed();
+        em.timer_advance();
+        em.tima_write(0x10);
+        assert_eq!(tima(&em), 0x42);
+        em.tma_write(0x24);
+        assert_eq!(tima(&em), 0x24);
+        assert_eq!(em.get_memory(TMA_ADDR, SOURCE), 0x24);
     }
 }
@@ -0,0 +1,383 @@
+use pixels::Pixels;
+use sdl2::video::Window;
+
+use crate::constants::*;
+use crate::emulator::{GameBoyEmulator, RequestSource};
+
+const SOURCE: RequestSource = RequestSource::SPEC;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ViewerKind {
+    Tiles,
+    TileMaps,
+    Oam,
+    Palettes,
+}
+
+impl ViewerKind {
+    fn title(self) -> &'static str {
+        match self {
+            ViewerKind::Tiles => "VRAM Tiles",
+            ViewerKind::TileMaps => "Tile Maps",
+            ViewerKind::Oam => "OAM",
+            ViewerKind::Palettes => "Palettes",
+        }
+    }
+    fn size(self) -> (usize, usize) {
+        match self {
+            ViewerKind::Tiles => (TILE_VIEW_WIDTH, TILE_VIEW_HEIGHT),
+            ViewerKind::TileMaps => (2 * BG_MAP_SIZE_PX, BG_MAP_SIZE_PX),
+            ViewerKind::Oam => (OAM_VIEW_WIDTH, OAM_VIEW_HEIGHT),
+            ViewerKind::Palettes => (PALETTE_VIEW_WIDTH, PALETTE_VIEW_HEIGHT),
+        }
+    }
+}
+
+pub struct DebugViewer {
+    pub kind: ViewerKind,
+    pub window: Window,
+    pub pixels: Pixels,
+}
+
+/// An RGBA image that the views are drawn into.
+struct Canvas {
+    width: usize,
+    data: Vec<u8>,
+}
+
+impl Canvas {
+    fn new(width: usize, height: usize) -> Canvas {
+        Canvas {
+            width,
+            data: VIEWER_BACKGROUND
+                .iter()
+                .copied()
+                .cycle()
+                .take(width * height * PIXEL_LENGTH)
+                .collect(),
+        }
+    }
+    fn set(&mut self, x: usize, y: usize, color: [u8; 4]) {
+        let index = (y * self.width + x) * PIXEL_LENGTH;
+        self.data[index..index + PIXEL_LENGTH].copy_from_slice(&color);
+    }
+    fn fill(&mut self, x: usize, y: usize, width: usize, height: usize, color: [u8; 4]) {
+        for row in y..y + height {
+            for column in x..x + width {
+                self.set(column, row, color);
+            }
+        }
+    }
+    /// Writes `value` as two hex digits with the 3x5 viewer font.
+    fn hex_byte(&mut self, x: usize, y: usize, value: u8) {
+        for (digit_index, digit) in [value >> 4, value & 0xF].iter().enumerate() {
+            let glyph = HEX_FONT[*digit as usize];
+            for (row, bits) in glyph.iter().enumerate() {
+                for column in 0..3 {
+                    if (bits >> (2 - column)) & 1 == 1 {
+                        self.set(x + digit_index * 4 + column, y + row, VIEWER_TEXT);
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn tile_row_colors(low: u8, high: u8) -> [usize; 8] {
+    let mut colors = [0; 8];
+    for (i, color) in colors.iter_mut().enumerate() {
+        *color = ((((high >> (7 - i)) & 1) << 1) | ((low >> (7 - i)) & 1)) as usize;
+    }
+    colors
+}
+
+impl GameBoyEmulator {
+    pub fn toggle_viewers(&mut self) {
+        if !self.viewers.is_empty() {
+            self.viewers.clear();
+            return;
+        }
+        let video_subsystem = match &self.sdl_context {
+            Some(sdl_context) => sdl_context.video().unwrap(),
+            None => return,
+        };
+        for kind in [
+            ViewerKind::Tiles,
+            ViewerKind::TileMaps,
+            ViewerKind::Oam,
+            ViewerKind::Palettes,
+        ]
+        .iter()
+        {
+            let (width, height) = kind.size();
+            let window = video_subsystem
+                .window(
+                    kind.title(),
+                    (VIEWER_SCALE * width) as u32,
+                    (VIEWER_SCALE * height) as u32,
+                )
+                .resizable()
+                .allow_highdpi()
+                .build()
+                .unwrap();
+            let surface_texture = pixels::SurfaceTexture::new(
+                (VIEWER_SCALE * width) as u32,
+                (VIEWER_SCALE * height) as u32,
+                &window,
+            );
+            let pixels = Pixels::new(width as u32, height as u32, surface_texture).unwrap();
+            self.viewers.push(DebugViewer {
+                kind: *kind,
+                window,
+                pixels,
+            });
+        }
+        self.update_viewers();
+    }
+    pub fn update_viewers(&mut self) {
+        if self.viewers.is_empty() {
+            return;
+        }
+        let images: Vec<Vec<u8>> = self
+            .viewers
+            .iter()
+            .map(|viewer| self.render_view(viewer.kind))
+            .collect();
+        for (viewer, image) in self.viewers.iter_mut().zip(images.iter()) {
+            viewer.pixels.get_frame().copy_from_slice(image);
+            viewer.pixels.render().unwrap();
+        }
+    }
+    pub fn render_view(&self, kind: ViewerKind) -> Vec<u8> {
+        match kind {
+            ViewerKind::Tiles => self.render_tiles(),
+            ViewerKind::TileMaps => self.render_tile_maps(),
+            ViewerKind::Oam => self.render_oam(),
+            ViewerKind::Palettes => self.render_palettes(),
+        }
+    }
+    /// Draws the tile at `tile_addr` in VRAM bank `bank` with its top left corner at
+    /// `position`. Color 0 is skipped for sprites.
+    fn draw_tile(
+        &self,
+        canvas: &mut Canvas,
+        position: (usize, usize),
+        (tile_addr, bank): (usize, u8),
+        (x_flip, y_flip): (bool, bool),
+        colors: [[u8; 4]; 4],
+        transparent: bool,
+    ) {
+        let (x, y) = position;
+        for row in 0..TILE_WIDTH {
+            let source_row = if y_flip { 7 - row } else { row };
+            let low = self.access_vram(tile_addr + source_row * BYTES_PER_TILE_ROW, bank);
+            let high = self.access_vram(tile_addr + source_row * BYTES_PER_TILE_ROW + 1, bank);
+            for (column, color) in tile_row_colors(low, high).iter().enumerate() {
+                if transparent && *color == 0 {
+                    continue;
+                }
+                let column = if x_flip { 7 - column } else { column };
+                canvas.set(x + column, y + row, colors[*color]);
+            }
+        }
+    }
+    /// Both VRAM banks as 16 by 24 tile sheets, bank 0 on the left.
+    fn render_tiles(&self) -> Vec<u8> {
+        let mut canvas = Canvas::new(TILE_VIEW_WIDTH, TILE_VIEW_HEIGHT);
+        let colors = self.bg_shades();
+        for bank in 0..2 {
+            for tile in 0..VRAM_TILES {
+                let x = bank * TILE_VIEW_WIDTH / 2 + (tile % TILE_VIEW_COLUMNS) * TILE_WIDTH;
+                let y = (tile / TILE_VIEW_COLUMNS) * TILE_WIDTH;
+                let tile_addr = VRAM_START_ADDR + tile * BYTES_PER_TILE;
+                self.draw_tile(
+                    &mut canvas,
+                    (x, y),
+                    (tile_addr, bank as u8),
+                    (false, false),
+                    colors,
+                    false,
+                );
+            }
+        }
+        canvas.data
+    }
+    /// The 0x9800 and 0x9C00 tile maps side by side, with the area SCX/SCY shows outlined.
+    fn render_tile_maps(&self) -> Vec<u8> {
+        let mut canvas = Canvas::new(2 * BG_MAP_SIZE_PX, BG_MAP_SIZE_PX);
+        let lcdc = self.get_memory(LCDC_ADDR, SOURCE);
+        let bgp = self.get_memory(BGP_ADDR, SOURCE) as usize;
+        let shades = self.bg_shades();
+        let dmg_colors = [
+            shades[bgp & 0b11],
+            shades[(bgp >> 2) & 0b11],
+            shades[(bgp >> 4) & 0b11],
+            shades[(bgp >> 6) & 0b11],
+        ];
+        for (map, &map_addr) in [TILE_MAP_1_START_ADDR, TILE_MAP_2_START_ADDR]
+            .iter()
+            .enumerate()
+        {
+            for tile in 0..TILES_PER_ROW * TILES_PER_ROW {
+                let tile_map_addr = map_addr + tile;
+                let tile_index = self.access_vram(tile_map_addr, 0) as usize;
+                let absolute_tile_index = if (lcdc >> 4) & 1 == 0 && tile_index < VRAM_BLOCK_SIZE {
+                    tile_index + 2 * VRAM_BLOCK_SIZE
+                } else {
+                    tile_index
+                };
+                let (bank, flips, colors) = if self.cgb {
+                    let attributes = self.access_vram(tile_map_addr, 1);
+                    (
+                        (attributes >> 3) & 1,
+                        ((attributes >> 5) & 1 == 1, (attributes >> 6) & 1 == 1),
+                        self.get_bg_rbg(attributes & 0b111),
+                    )
+                } else {
+                    (0, (false, false), dmg_colors)
+                };
+                let position = (
+                    map * BG_MAP_SIZE_PX + (tile % TILES_PER_ROW) * TILE_WIDTH,
+                    (tile / TILES_PER_ROW) * TILE_WIDTH,
+                );
+                let tile_addr = VRAM_START_ADDR + absolute_tile_index * BYTES_PER_TILE;
+                self.draw_tile(
+                    &mut canvas,
+                    position,
+                    (tile_addr, bank),
+                    flips,
+                    colors,
+                    false,
+                );
+            }
+        }
+        let scx = self.get_memory(SCX_ADDR, SOURCE) as usize;
+        let scy = self.get_memory(SCY_ADDR, SOURCE) as usize;
+        let map = ((lcdc >> 3) & 1) as usize;
+        for i in 0..WINDOW_WIDTH {
+            let x = map * BG_MAP_SIZE_PX + (scx + i) % BG_MAP_SIZE_PX;
+            canvas.set(x, scy, VIEWER_OUTLINE);
+            canvas.set(
+                x,
+                (scy + WINDOW_HEIGHT - 1) % BG_MAP_SIZE_PX,
+                VIEWER_OUTLINE,
+            );
+        }
+        for i in 0..WINDOW_HEIGHT {
+            let y = (scy + i) % BG_MAP_SIZE_PX;
+            canvas.set(map * BG_MAP_SIZE_PX + scx, y, VIEWER_OUTLINE);
+            let right = map * BG_MAP_SIZE_PX + (scx + WINDOW_WIDTH - 1) % BG_MAP_SIZE_PX;
+            canvas.set(right, y, VIEWER_OUTLINE);
+        }
+        canvas.data
+    }
+    /// Every OAM entry drawn in 8x16 mode, next to its Y, X, tile and attribute bytes.
+    fn render_oam(&self) -> Vec<u8> {
+        let mut canvas = Canvas::new(OAM_VIEW_WIDTH, OAM_VIEW_HEIGHT);
+        for sprite in 0..OAM_SPRITE_NUM {
+            let x = (sprite % OAM_VIEW_COLUMNS) * OAM_VIEW_CELL_WIDTH;
+            let y = (sprite / OAM_VIEW_COLUMNS) * OAM_VIEW_CELL_HEIGHT;
+            let mut entry = [0u8; BYTES_PER_OAM_ENTRY];
+            for (i, byte) in entry.iter_mut().enumerate() {
+                *byte = self.get_memory(OAM_START_ADDR + sprite * BYTES_PER_OAM_ENTRY + i, SOURCE);
+            }
+            let attributes = entry[OAM_ATTRIBUTE_INDEX];
+            let (bank, colors) = if self.cgb {
+                ((attributes >> 3) & 1, self.get_obj_rbg(attributes & 0b111))
+            } else {
+                let obp = (attributes >> 4) & 1;
+                let color_data = if obp == 0 {
+                    self.get_memory(OBP0_ADDR, SOURCE)
+                } else {
+                    self.get_memory(OBP1_ADDR, SOURCE)
+                } as usize;
+                let shades = self.obj_shades(obp);
+                (
+                    0,
+                    [
+                        shades[color_data & 0b11],
+                        shades[(color_data >> 2) & 0b11],
+                        shades[(color_data >> 4) & 0b11],
+                        shades[(color_data >> 6) & 0b11],
+                    ],
+                )
+            };
+            canvas.fill(
+                x + 1,
+                y + 1,
+                TILE_WIDTH,
+                2 * TILE_WIDTH,
+                VIEWER_SPRITE_BACKGROUND,
+            );
+            let flips = ((attributes >> 5) & 1 == 1, (attributes >> 6) & 1 == 1);
+            let tile = entry[OAM_TILE_INDEX] as usize;
+            let (top, bottom) = if flips.1 {
+                (tile | 1, tile & 0xFE)
+            } else {
+                (tile & 0xFE, tile | 1)
+            };
+            for (half, &tile) in [top, bottom].iter().enumerate() {
+                let tile_addr = VRAM_START_ADDR + tile * BYTES_PER_TILE;
+                let position = (x + 1, y + 1 + half * TILE_WIDTH);
+                self.draw_tile(
+                    &mut canvas,
+                    position,
+                    (tile_addr, bank),
+                    flips,
+                    colors,
+                    true,
+                );
+            }
+            for (line, byte) in entry.iter().enumerate() {
+                canvas.hex_byte(x + TILE_WIDTH + 3, y + 1 + line * 6, *byte);
+            }
+        }
+        canvas.data
+    }
+    /// The 8 BG palettes on the left and the 8 OBJ palettes on the right.
+    fn render_palettes(&self) -> Vec<u8> {
+        let mut canvas = Canvas::new(PALETTE_VIEW_WIDTH, PALETTE_VIEW_HEIGHT);
+        let swatch = PALETTE_VIEW_SWATCH;
+        for palette in 0..8 {
+            let bg = self.get_bg_rbg(palette as u8);
+            let obj = self.get_obj_rbg(palette as u8);
+            for color in 0..4 {
+                let y = palette * swatch;
+                canvas.fill(color * swatch, y, swatch, swatch, bg[color]);
+                let x = PALETTE_VIEW_WIDTH - (4 - color) * swatch;
+                canvas.fill(x, y, swatch, swatch, obj[color]);
+            }
+        }
+        canvas.data
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::booted;
+
+    #[test]
+    fn views_show_boot_logo() {
+        let em = booted(&[0x18, 0xFE]);
+        for &kind in [
+            ViewerKind::Tiles,
+            ViewerKind::TileMaps,
+            ViewerKind::Oam,
+            ViewerKind::Palettes,
+        ]
+        .iter()
+        {
+            let (width, height) = kind.size();
+            assert_eq!(em.render_view(kind).len(), width * height * PIXEL_LENGTH);
+        }
+        // The logo tiles start at tile 1, and the viewport outline starts at SCX/SCY = 0/0.
+        let tiles = em.render_view(ViewerKind::Tiles);
+        let logo_row = &tiles[TILE_WIDTH * PIXEL_LENGTH..2 * TILE_WIDTH * PIXEL_LENGTH];
+        assert!(logo_row
+            .chunks_exact(PIXEL_LENGTH)
+            .any(|pixel| pixel != em.bg_shades()[0]));
+        let maps = em.render_view(ViewerKind::TileMaps);
+        assert_eq!(maps[..PIXEL_LENGTH], VIEWER_OUTLINE);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::constants::*;
+use crate::emulator::{GameBoyEmulator, RequestSource};
+use std::cell::RefCell;
+use std::fs::{self, File};
+use std::io;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum LogFormat {
+    Csv,
+    Jsonl,
+}
+
+impl LogFormat {
+    /// JSONL for `.jsonl` and `.json` files, CSV for anything else.
+    pub fn from_path(path: &Path) -> LogFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("jsonl") || ext.eq_ignore_ascii_case("json") => {
+                LogFormat::Jsonl
+            }
+            _ => LogFormat::Csv,
+        }
+    }
+}
+
+pub struct WatchEntry {
+    pub cycle: usize,
+    pub ly: u8,
+    pub pc: u16,
+    pub rom_bank: usize,
+    pub source: RequestSource,
+    pub access: Access,
+    pub addr: usize,
+    pub val: u8,
+}
+
+/// Logs every access to a set of address ranges, along with who made it and when.
+pub struct MemoryWatch {
+    ranges: Vec<(usize, usize)>,
+    format: LogFormat,
+    out: BufWriter<File>,
+    failed: bool,
+}
+
+impl MemoryWatch {
+    pub fn create(path: &Path, ranges: Vec<(usize, usize)>) -> io::Result<MemoryWatch> {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let format = LogFormat::from_path(path);
+        let mut out = BufWriter::new(File::create(path)?);
+        if format == LogFormat::Csv {
+            writeln!(out, "cycle,ly,pc,rom_bank,source,access,address,value")?;
+        }
+        Ok(MemoryWatch {
+            ranges,
+            format,
+            out,
+            failed: false,
+        })
+    }
+    pub fn add_range(&mut self, range: (usize, usize)) {
+        self.ranges.push(range);
+    }
+    pub fn watches(&self, addr: usize) -> bool {
+        !self.failed
+            && self
+                .ranges
+                .iter()
+                .any(|(start, end)| (*start..=*end).contains(&addr))
+    }
+    fn write_entry(&mut self, entry: &WatchEntry) -> io::Result<()> {
+        let access = match entry.access {
+            Access::Read => "read",
+            Access::Write => "write",
+        };
+        match self.format {
+            LogFormat::Csv => writeln!(
+                self.out,
+                "{},{},{:04X},{},{:?},{},{:04X},{:02X}",
+                entry.cycle,
+                entry.ly,
+                entry.pc,
+                entry.rom_bank,
+                entry.source,
+                access,
+                entry.addr,
+                entry.val
+            ),
+            LogFormat::Jsonl => writeln!(
+                self.out,
+                "{{\"cycle\":{},\"ly\":{},\"pc\":\"{:04X}\",\"rom_bank\":{},\"source\":\"{:?}\",\"access\":\"{}\",\"address\":\"{:04X}\",\"value\":\"{:02X}\"}}",
+                entry.cycle,
+                entry.ly,
+                entry.pc,
+                entry.rom_bank,
+                entry.source,
+                access,
+                entry.addr,
+                entry.val
+            ),
+        }
+    }
+    pub fn record(&mut self, entry: &WatchEntry) {
+        if let Err(e) = self.write_entry(entry) {
+            eprintln!("Memory watch stopped: {}", e);
+            self.failed = true;
+        }
+    }
+    pub fn finish(mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+/// Parses comma separated hex addresses or ranges, such as `C000-C0FF,FF40`.
+pub fn parse_ranges(spec: &str) -> Option<Vec<(usize, usize)>> {
+    let parse = |s: &str| usize::from_str_radix(s.trim().trim_start_matches("0x"), 16).ok();
+    spec.split(',')
+        .map(|part| match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (parse(start)?, parse(end)?);
+                if start <= end && end <= 0xFFFF {
+                    Some((start, end))
+                } else {
+                    None
+                }
+            }
+            None => parse(part)
+                .filter(|addr| *addr <= 0xFFFF)
+                .map(|addr| (addr, addr)),
+        })
+        .collect()
+}
+
+impl GameBoyEmulator {
+    pub fn start_watch(&mut self, path: &Path, ranges: Vec<(usize, usize)>) {
+        self.stop_watch();
+        match MemoryWatch::create(path, ranges) {
+            Ok(watch) => {
+                eprintln!("Logging memory accesses to {}", path.display());
+                self.watch = Some(RefCell::new(watch));
+            }
+            Err(e) => eprintln!("Could not start memory watch: {}", e),
+        }
+    }
+    pub fn stop_watch(&mut self) {
+        if let Some(watch) = self.watch.take() {
+            if let Err(e) = watch.into_inner().finish() {
+                eprintln!("Could not save memory watch: {}", e);
+            }
+        }
+    }
+    pub fn watch_access(&self, access: Access, addr: usize, val: u8, source: RequestSource) {
+        if let Some(watch) = &self.watch {
+            let mut watch = watch.borrow_mut();
+            if watch.watches(addr) {
+                watch.record(&WatchEntry {
+                    cycle: self.iteration_count * ADVANCE_CYCLES as usize,
+                    ly: self.current_ly(),
+                    pc: self.cpu.get_pc(),
+                    rom_bank: self.rom_bank(),
+                    source,
+                    access,
+                    addr,
+                    val,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::emulator::{booted, test_rom};
+
+    #[test]
+    fn parses_ranges() {
+        assert_eq!(
+            parse_ranges("C000-C0FF,ff40, 0xFF47"),
+            Some(vec![(0xC000, 0xC0FF), (0xFF40, 0xFF40), (0xFF47, 0xFF47)])
+        );
+        assert_eq!(parse_ranges("C0FF-C000"), None);
+        assert_eq!(parse_ranges("10000"), None);
+        assert_eq!(parse_ranges("FF4X"), None);
+    }
+
+    #[test]
+    fn logs_matching_accesses() {
+        let dir = std::env::temp_dir().join(format!("gb-watch-{}", std::process::id()));
+        // LD A,0x12; LDH (0x80),A; halt.
+        let program = [0x3E, 0x12, 0xE0, 0x80, 0xF3, 0x76];
+        let mut em = GameBoyEmulator::new_headless();
+        em.load_rom_data(test_rom(&program));
+        for name in ["watch.csv", "watch.jsonl"].iter() {
+            let path = dir.join(name);
+            em.start_watch(&path, vec![(HRAM_START_ADDR, HRAM_START_ADDR)]);
+            em.write_memory(HRAM_START_ADDR, 0x34, RequestSource::SPEC);
+            em.get_memory(HRAM_START_ADDR + 1, RequestSource::SPEC);
+            em.stop_watch();
+            let log = fs::read_to_string(&path).unwrap();
+            let lines: Vec<&str> = log.lines().collect();
+            if *name == "watch.csv" {
+                assert_eq!(lines[0], "cycle,ly,pc,rom_bank,source,access,address,value");
+                assert_eq!(lines[1], "0,0,0000,1,SPEC,write,FF80,34");
+                assert_eq!(lines.len(), 2);
+            } else {
+                assert_eq!(
+                    lines[0],
+                    "{\"cycle\":0,\"ly\":0,\"pc\":\"0000\",\"rom_bank\":1,\"source\":\"SPEC\",\"access\":\"write\",\"address\":\"FF80\",\"value\":\"34\"}"
+                );
+                assert_eq!(lines.len(), 1);
+            }
+        }
+
+        let path = dir.join("run.csv");
+        let mut em = booted(&program);
+        em.start_watch(&path, vec![(HRAM_START_ADDR, HRAM_START_ADDR)]);
+        while !em.cpu.halting {
+            em.advance();
+        }
+        em.stop_watch();
+        let log = fs::read_to_string(&path).unwrap();
+        assert!(log
+            .lines()
+            .any(|line| line.ends_with(",0152,1,CPU,write,FF80,12")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}